@@ -71,6 +71,25 @@ impl AABB {
         true
     }
 
+    // the range of parameters, within (tmin, tmax), over which r is
+    // inside the box
+    pub fn ray_interval(&self, r: &Ray, tmin: f64, tmax: f64) -> Option<(f64, f64)> {
+        let mut tmin = tmin;
+        let mut tmax = tmax;
+        for a in 0..3 {
+            let inv_d = 1.0 / r.direction()[a];
+            let t0 = (self._min[a] - r.origin()[a]) * inv_d;
+            let t1 = (self._max[a] - r.origin()[a]) * inv_d;
+            // NaNs from rays parallel to a face lose both comparisons
+            tmin = ffmax(ffmin(t0, t1), tmin);
+            tmax = ffmin(ffmax(t0, t1), tmax);
+            if tmax < tmin {
+                return None;
+            }
+        }
+        Some((tmin, tmax))
+    }
+
     // far from best possible disc, but eh
     pub fn project_to_disc_on_sphere(&self, center: &Vec3) -> Disc {
         let mut pts = vec![
@@ -0,0 +1,40 @@
+use integrator::*;
+use ray::Ray;
+use sampling;
+use vector::Vec3;
+
+//////////////////////////////////////////////////////////////////////////////
+// ambient occlusion: the fraction of cosine-weighted directions at the
+// first hit that escape within `distance`. Ignores materials and
+// lights entirely.
+
+pub struct AmbientOcclusion {
+    pub distance: f64
+}
+
+impl Integrator for AmbientOcclusion {
+    fn color(&self, ray: &Ray, context: &RenderContext) -> Vec3 {
+        match context.world.hit(ray, 0.00001, 1e20) {
+            None => Vec3::new(0.0, 0.0, 0.0),
+            Some(hr) => {
+                let normal = facing_normal(ray, &hr.normal);
+                // cosine-distributed, same trick as Lambertian::scatter
+                let direction = sampling::random_3d_direction() + normal;
+                let occlusion_ray = Ray::with_time(hr.p, direction, ray.time());
+                let max_t = self.distance / direction.length();
+                match context.world.hit(&occlusion_ray, 0.00001, max_t) {
+                    None => Vec3::new(1.0, 1.0, 1.0),
+                    Some(_) => Vec3::new(0.0, 0.0, 0.0)
+                }
+            }
+        }
+    }
+}
+
+impl AmbientOcclusion {
+    pub fn new(distance: f64) -> Box<Integrator> {
+        Box::new(AmbientOcclusion {
+            distance: distance
+        })
+    }
+}
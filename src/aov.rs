@@ -0,0 +1,124 @@
+use aabb::AABB;
+use area_light::AreaLight;
+use hitable::*;
+use integrator::*;
+use ray::Ray;
+use vector::Vec3;
+
+// testing imports
+#[allow(unused_imports)]
+use background;
+#[allow(unused_imports)]
+use lambertian::Lambertian;
+#[allow(unused_imports)]
+use light::LightList;
+#[allow(unused_imports)]
+use render_settings::RenderSettings;
+#[allow(unused_imports)]
+use sphere::Sphere;
+
+//////////////////////////////////////////////////////////////////////////////
+// arbitrary output variables: what the camera ray sees first, for
+// compositing and denoising. Each is a color per pixel, averaged over
+// the pixel's samples like the image itself, except for the object id:
+//
+// - depth: distance to the first hit, in all three channels
+// - normal: the surface normal, facing the camera
+// - albedo: the material's reflectance (white for emitters and media)
+// - position: the first hit point in world space
+// - object_id: the index of the scene object hit, counting from 1, in
+//   all three channels. Averaging two ids on an object's edge would
+//   make a third, naming some other object, so each pixel keeps the id
+//   its first sample saw instead.
+//
+// Rays that hit nothing give zero everywhere. Scattering events in
+// media have zero normals, and in fog, object id zero.
+
+// the averaged ones
+pub const AOV_NAMES: [&str; 4] = ["depth", "normal", "albedo", "position"];
+
+// the averaged variables, and the object id
+pub fn first_hit_aovs(ray: &Ray, context: &RenderContext) -> ([Vec3; 4], usize) {
+    match context.world.hit(ray, 0.00001, 1e20) {
+        None => ([Vec3::zero(); 4], 0),
+        Some(hr) => {
+            let depth = hr.t * ray.direction().length();
+            let normal = facing_normal(ray, &hr.normal);
+            let albedo = if hr.material.is_emitter() || normal == Vec3::zero() {
+                Vec3::new(1.0, 1.0, 1.0)
+            } else {
+                hr.material.albedo(ray, &Ray::new(hr.p, normal), &normal)
+            };
+            ([Vec3::new(depth, depth, depth),
+              normal,
+              albedo,
+              hr.p],
+             hr.object_id)
+        }
+    }
+}
+
+// numbers a scene object, so that its hits can be told apart
+pub struct Identified {
+    pub hitable: Box<Hitable + Send + Sync>,
+    pub id: usize
+}
+
+impl Identified {
+    pub fn new(hitable: Box<Hitable + Send + Sync>, id: usize) -> Identified {
+        Identified {
+            hitable: hitable,
+            id: id
+        }
+    }
+}
+
+impl Hitable for Identified {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'a>> {
+        self.hitable.hit(r, t_min, t_max).map(|mut hr| {
+            hr.object_id = self.id;
+            hr
+        })
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        self.hitable.bounding_box()
+    }
+
+    fn area_lights(&self) -> Vec<AreaLight> {
+        self.hitable.area_lights()
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn it_works() {
+    use tests::*;
+
+    let world = Identified::new(Box::new(Sphere::new(
+        Vec3::new(0.0, 0.0, -3.0), 1.0, Lambertian::new(&Vec3::new(0.5, 0.25, 0.125)))), 7);
+    let background = background::constant(Vec3::zero());
+    let lights = LightList::new(Vec::new());
+    let settings = RenderSettings::default();
+    let context = RenderContext {
+        world: &world,
+        background: &background,
+        lights: &lights,
+        settings: &settings
+    };
+
+    // non-unit direction, so depth has to be a distance and not a t
+    let (aovs, id) = first_hit_aovs(&Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -2.0)), &context);
+    assert!(within_eps(&aovs[0], &Vec3::new(2.0, 2.0, 2.0)));
+    assert!(within_eps(&aovs[1], &Vec3::new(0.0, 0.0, 1.0)));
+    assert!(within_eps(&aovs[2], &Vec3::new(0.5, 0.25, 0.125)));
+    assert!(within_eps(&aovs[3], &Vec3::new(0.0, 0.0, -2.0)));
+    assert!(id == 7);
+
+    let (miss, id) = first_hit_aovs(&Ray::new(Vec3::zero(), Vec3::new(0.0, 1.0, 0.0)), &context);
+    assert!(id == 0);
+    for aov in miss.iter() {
+        assert!(within_eps(aov, &Vec3::zero()));
+    }
+}
@@ -0,0 +1,646 @@
+use aabb::AABB;
+use aabb;
+use disc::Disc;
+use light::*;
+use random::*;
+use sampling;
+use sampling::SphericalRectangle;
+use polynomial::solve_quadratic;
+use spectrum::Spectrum;
+use torus::unit_torus_crossings;
+use vector::Vec3;
+use vector;
+
+use std::f64::consts::PI;
+
+//////////////////////////////////////////////////////////////////////////////
+// the emitting surfaces of a scene, collected from the hitables before
+// they disappear into the BVH. Each light keeps its own copy of the
+// geometry, so it can be sampled without walking the scene.
+
+#[derive(Debug, Clone)]
+pub enum LightShape {
+    Sphere { center: Vec3, radius: f64 },
+    Rectangle { bottom_left: Vec3, right: Vec3, up: Vec3 },
+    Triangle { vertices: [Vec3; 3] },
+    Disc { center: Vec3, normal: Vec3, radius: f64 },
+    // the sides of cylinders and cones, whose caps are discs
+    Cylinder { base: Vec3, top: Vec3, radius: f64 },
+    Cone { base: Vec3, apex: Vec3, radius: f64 },
+    // around the unit vector axis
+    Torus { center: Vec3, axis: Vec3, major_radius: f64, minor_radius: f64 }
+}
+
+#[derive(Debug, Clone)]
+pub struct AreaLight {
+    pub shape: LightShape,
+    // in RGB, for choosing between lights
+    pub emission: Vec3,
+    pub spectrum: Spectrum
+}
+
+impl AreaLight {
+    pub fn new(shape: LightShape, emission: Vec3) -> AreaLight {
+        AreaLight::with_spectrum(shape, Spectrum::from_rgb(&emission))
+    }
+
+    pub fn with_spectrum(shape: LightShape, spectrum: Spectrum) -> AreaLight {
+        AreaLight {
+            shape: shape,
+            emission: spectrum.rgb,
+            spectrum: spectrum
+        }
+    }
+
+    pub fn area(&self) -> f64 {
+        match self.shape {
+            LightShape::Sphere { radius, .. } => 4.0 * PI * radius * radius,
+            LightShape::Rectangle { right, up, .. } => vector::cross(&right, &up).length(),
+            LightShape::Triangle { vertices } => 0.5 * vector::cross(
+                &(vertices[1] - vertices[0]),
+                &(vertices[2] - vertices[0])).length(),
+            LightShape::Disc { radius, .. } => PI * radius * radius,
+            LightShape::Cylinder { base, top, radius } => 2.0 * PI * radius * (top - base).length(),
+            LightShape::Cone { base, apex, radius } => {
+                PI * radius * (radius * radius + (apex - base).length_squared()).sqrt()
+            },
+            LightShape::Torus { major_radius, minor_radius, .. } => {
+                4.0 * PI * PI * major_radius * minor_radius
+            }
+        }
+    }
+
+    pub fn bounding_box(&self) -> AABB {
+        match self.shape {
+            LightShape::Sphere { center, radius } => {
+                let r = radius.abs();
+                AABB::new(center - Vec3::new(r, r, r),
+                          center + Vec3::new(r, r, r))
+            },
+            LightShape::Rectangle { bottom_left, right, up } => {
+                AABB::from_points(&[bottom_left,
+                                    bottom_left + right,
+                                    bottom_left + up,
+                                    bottom_left + right + up])
+            },
+            LightShape::Triangle { vertices } => AABB::from_points(&vertices),
+            LightShape::Disc { center, normal, radius } => Disc::new(center, normal, radius).bounding_box(),
+            LightShape::Cylinder { base, top, radius } => {
+                let axis = vector::unit_vector(&(top - base));
+                aabb::surrounding_box(&Disc::new(base, axis, radius).bounding_box(),
+                                      &Disc::new(top, axis, radius).bounding_box())
+            },
+            LightShape::Cone { base, apex, radius } => {
+                let axis = vector::unit_vector(&(apex - base));
+                aabb::surrounding_box(&Disc::new(base, axis, radius).bounding_box(),
+                                      &AABB::new(apex, apex))
+            },
+            LightShape::Torus { center, axis, major_radius, minor_radius } => {
+                // the ring's extent along each axis, fattened by the tube
+                let e = |a: f64| major_radius * (1.0 - a * a).max(0.0).sqrt() + minor_radius;
+                let extent = Vec3::new(e(axis.x()), e(axis.y()), e(axis.z()));
+                AABB::new(center - extent, center + extent)
+            }
+        }
+    }
+
+    // a point distributed uniformly over the surface, and the normal
+    // there. The density with respect to area is 1 / area().
+    pub fn sample_point(&self) -> (Vec3, Vec3) {
+        match self.shape {
+            LightShape::Sphere { center, radius } => {
+                let n = sampling::random_3d_direction();
+                (center + n * radius.abs(), n)
+            },
+            LightShape::Rectangle { bottom_left, right, up } => {
+                let p = bottom_left + right * rand_double() + up * rand_double();
+                (p, vector::unit_vector(&vector::cross(&right, &up)))
+            },
+            LightShape::Triangle { vertices } => {
+                let su = rand_double().sqrt();
+                let v = rand_double();
+                let p = vertices[0] * (1.0 - su) +
+                    vertices[1] * (su * (1.0 - v)) +
+                    vertices[2] * (su * v);
+                let n = vector::unit_vector(&vector::cross(
+                    &(vertices[1] - vertices[0]),
+                    &(vertices[2] - vertices[0])));
+                (p, n)
+            },
+            LightShape::Disc { center, normal, radius } => {
+                let (u, v) = vector::tangent_space(&normal);
+                let d = sampling::random_in_unit_disk() * radius;
+                (center + u * d.x() + v * d.y(), normal)
+            },
+            LightShape::Cylinder { base, top, radius } => {
+                let axis = top - base;
+                let w = random_around(&vector::unit_vector(&axis));
+                (base + axis * rand_double() + w * radius, w)
+            },
+            LightShape::Cone { base, apex, radius } => {
+                // rings grow in proportion to their distance from the apex
+                let axis = base - apex;
+                let height = axis.length();
+                let w = random_around(&(axis / height));
+                let p = apex + (axis + w * radius) * rand_double().sqrt();
+                (p, vector::unit_vector(&(w - axis * (radius / (height * height)))))
+            },
+            LightShape::Torus { center, axis, major_radius, minor_radius } => {
+                // the outside of the tube has more area than the inside,
+                // in proportion to the distance from the axis
+                let w = random_around(&axis);
+                let phi = loop {
+                    let phi = 2.0 * PI * rand_double();
+                    if rand_double() * (major_radius + minor_radius) <= major_radius + minor_radius * phi.cos() {
+                        break phi;
+                    }
+                };
+                let n = w * phi.cos() + axis * phi.sin();
+                (center + w * major_radius + n * minor_radius, n)
+            }
+        }
+    }
+
+    // a direction from p towards the light, sampled exactly over the
+    // part of it p can see where that's practical: by solid angle for
+    // spheres and rectangles, and by area otherwise. Its density is
+    // direction_pdf.
+    pub fn sample_direction(&self, p: &Vec3) -> Option<Vec3> {
+        let target = match self.shape {
+            LightShape::Sphere { center, radius } => {
+                match sphere_cone(p, &center, radius) {
+                    Some((axis, one_minus_cos_max)) => {
+                        return Some(sampling::random_in_cone(&axis, one_minus_cos_max));
+                    },
+                    None => self.sample_point().0
+                }
+            },
+            LightShape::Rectangle { bottom_left, right, up } if is_rectangle(&right, &up) => {
+                let rect = SphericalRectangle::new(p, &bottom_left, &right, &up);
+                if rect.solid_angle <= 0.0 {
+                    return None;
+                }
+                rect.sample()
+            },
+            _ => self.sample_point().0
+        };
+        let d = target - *p;
+        if d.length_squared() > 0.0 {
+            Some(vector::unit_vector(&d))
+        } else {
+            None
+        }
+    }
+
+    // solid-angle density with which sample_direction generates the
+    // unit vector direction from p.
+    pub fn direction_pdf(&self, p: &Vec3, direction: &Vec3) -> f64 {
+        match self.shape {
+            LightShape::Sphere { center, radius } => {
+                match sphere_cone(p, &center, radius) {
+                    Some((axis, one_minus_cos_max)) => {
+                        if 1.0 - direction.dot(&axis) <= one_minus_cos_max {
+                            1.0 / (2.0 * PI * one_minus_cos_max)
+                        } else {
+                            0.0
+                        }
+                    },
+                    None => {
+                        // from inside, every direction hits the sphere
+                        // exactly once, on the way out
+                        let oc = *p - center;
+                        let b = oc.dot(direction);
+                        let c = oc.length_squared() - radius * radius;
+                        let t = -b + (b * b - c).max(0.0).sqrt();
+                        let normal = (*p + *direction * t - center) / radius.abs();
+                        area_to_solid_angle(t, normal.dot(direction), self.area())
+                    }
+                }
+            },
+            LightShape::Rectangle { bottom_left, right, up } => {
+                match parallelogram_hit(p, direction, &bottom_left, &right, &up) {
+                    None => 0.0,
+                    Some((t, cos)) => {
+                        if is_rectangle(&right, &up) {
+                            let rect = SphericalRectangle::new(p, &bottom_left, &right, &up);
+                            if rect.solid_angle > 0.0 { 1.0 / rect.solid_angle } else { 0.0 }
+                        } else {
+                            area_to_solid_angle(t, cos, self.area())
+                        }
+                    }
+                }
+            },
+            LightShape::Triangle { vertices } => {
+                match triangle_hit(p, direction, &vertices) {
+                    Some((t, cos)) => area_to_solid_angle(t, cos, self.area()),
+                    None => 0.0
+                }
+            },
+            LightShape::Disc { center, normal, radius } => {
+                match disc_hit(p, direction, &center, &normal, radius) {
+                    Some((t, cos)) => area_to_solid_angle(t, cos, self.area()),
+                    None => 0.0
+                }
+            },
+            // every point along the ray could have been sampled
+            _ => {
+                let area = self.area();
+                self.crossings(p, direction).iter().map(|&(t, cos)| area_to_solid_angle(t, cos, area)).sum()
+            }
+        }
+    }
+
+    // how far the light is from p along the unit vector direction
+    pub fn distance(&self, p: &Vec3, direction: &Vec3) -> Option<f64> {
+        match self.shape {
+            LightShape::Sphere { center, radius } => {
+                let oc = *p - center;
+                let b = oc.dot(direction);
+                let c = oc.length_squared() - radius * radius;
+                let discriminant = b * b - c;
+                if discriminant < 0.0 {
+                    return None;
+                }
+                let (t0, t1) = (-b - discriminant.sqrt(), -b + discriminant.sqrt());
+                if t0 > 0.0 {
+                    Some(t0)
+                } else if t1 > 0.0 {
+                    Some(t1)
+                } else {
+                    None
+                }
+            },
+            LightShape::Rectangle { bottom_left, right, up } => {
+                parallelogram_hit(p, direction, &bottom_left, &right, &up).map(|(t, _)| t)
+            },
+            LightShape::Triangle { vertices } => {
+                triangle_hit(p, direction, &vertices).map(|(t, _)| t)
+            },
+            LightShape::Disc { center, normal, radius } => {
+                disc_hit(p, direction, &center, &normal, radius).map(|(t, _)| t)
+            },
+            _ => self.crossings(p, direction).first().map(|&(t, _)| t)
+        }
+    }
+
+    // where the ray from p along the unit vector direction crosses a
+    // curved light, nearest first: each distance, and the cosine
+    // between direction and the normal there
+    fn crossings(&self, p: &Vec3, direction: &Vec3) -> Vec<(f64, f64)> {
+        match self.shape {
+            LightShape::Cylinder { base, top, radius } => side_crossings(p, direction, &base, &top, radius, 0.0),
+            LightShape::Cone { base, apex, radius } => side_crossings(p, direction, &base, &apex, radius, 1.0),
+            LightShape::Torus { center, axis, major_radius, minor_radius } => {
+                let (u, v) = vector::tangent_space(&axis);
+                let q = (*p - center) / major_radius;
+                let local = |x: &Vec3| Vec3::new(x.dot(&u), x.dot(&v), x.dot(&axis));
+                unit_torus_crossings(&local(&q), &local(direction), minor_radius / major_radius)
+                    .into_iter()
+                    .filter(|&s| s > 0.0)
+                    .map(|s| {
+                        let t = s * major_radius;
+                        let x = *p + *direction * t - center;
+                        let ring = vector::unit_vector(&(x - axis * x.dot(&axis)));
+                        (t, vector::unit_vector(&(x - ring * major_radius)).dot(direction))
+                    })
+                    .collect()
+            },
+            _ => Vec::new()
+        }
+    }
+}
+
+impl Light for AreaLight {
+    fn sample_li(&self, p: &Vec3) -> Option<LightSample> {
+        let direction = self.sample_direction(p)?;
+        let pdf = self.direction_pdf(p, &direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+        // directions sampled by solid angle can graze the silhouette
+        // and just miss, numerically
+        let distance = self.distance(p, &direction)?;
+        Some(LightSample {
+            direction: direction,
+            distance: distance,
+            radiance: self.spectrum.values(),
+            pdf: pdf,
+            delta: false
+        })
+    }
+
+    fn pdf_li(&self, p: &Vec3, direction: &Vec3) -> f64 {
+        self.direction_pdf(p, direction)
+    }
+
+    fn power(&self) -> f64 {
+        self.area() * vector::luminance(&self.emission)
+    }
+
+    fn sample_le(&self) -> Option<EmissionSample> {
+        let (p, normal) = self.sample_point();
+        let (direction, pdf_dir) = sample_emission_direction(&normal);
+        Some(EmissionSample {
+            p: p,
+            normal: normal,
+            direction: direction,
+            radiance: self.spectrum.values(),
+            pdf_pos: 1.0 / self.area(),
+            pdf_dir: pdf_dir
+        })
+    }
+
+    fn emitted(&self, _direction: &Vec3) -> Vec3 {
+        self.spectrum.values()
+    }
+
+    fn pdf_le_direction(&self, normal: &Vec3, direction: &Vec3) -> f64 {
+        emission_direction_pdf(normal, direction)
+    }
+
+    fn distance(&self, p: &Vec3, direction: &Vec3) -> Option<f64> {
+        AreaLight::distance(self, p, direction)
+    }
+}
+
+// the axis towards the sphere and 1 - the cosine of the angle it
+// subtends, as seen from p. None when p is inside.
+fn sphere_cone(p: &Vec3, center: &Vec3, radius: f64) -> Option<(Vec3, f64)> {
+    let d = *center - *p;
+    let dist2 = d.length_squared();
+    let r2 = radius * radius;
+    if dist2 <= r2 {
+        return None;
+    }
+    let sin2_max = r2 / dist2;
+    let cos_max = (1.0 - sin2_max).sqrt();
+    Some((d / dist2.sqrt(), sin2_max / (1.0 + cos_max)))
+}
+
+// a random unit vector perpendicular to the unit vector axis
+fn random_around(axis: &Vec3) -> Vec3 {
+    let (u, v) = vector::tangent_space(axis);
+    let theta = 2.0 * PI * rand_double();
+    u * theta.cos() + v * theta.sin()
+}
+
+// where the ray crosses the side of the cylinder from base to end, or
+// with taper 1 of the cone narrowing from base to a point at end: its
+// radius at height z is radius (1 - taper z / h).
+fn side_crossings(p: &Vec3, direction: &Vec3, base: &Vec3, end: &Vec3, radius: f64, taper: f64) -> Vec<(f64, f64)> {
+    let height = (*end - *base).length();
+    let axis = (*end - *base) / height;
+    let slope = -taper * radius / height;
+    let q = *p - *base;
+    let (qz, dz) = (q.dot(&axis), direction.dot(&axis));
+    let (q_perp, d_perp) = (q - axis * qz, *direction - axis * dz);
+    // |q_perp + d_perp t| = a + b t
+    let (a, b) = (radius + slope * qz, slope * dz);
+    solve_quadratic(d_perp.length_squared() - b * b,
+                    2.0 * (q_perp.dot(&d_perp) - a * b),
+                    q_perp.length_squared() - a * a)
+        .into_iter()
+        .filter_map(|t| {
+            let z = qz + dz * t;
+            let radial = q_perp + d_perp * t;
+            if t <= 0.0 || z < 0.0 || z > height || radial.length_squared() == 0.0 {
+                return None;
+            }
+            let normal = vector::unit_vector(&(vector::unit_vector(&radial) - axis * slope));
+            Some((t, normal.dot(direction)))
+        })
+        .collect()
+}
+
+// whether the parallelogram spanned by right and up has right angles,
+// which solid-angle sampling needs
+fn is_rectangle(right: &Vec3, up: &Vec3) -> bool {
+    right.dot(up).abs() <= 1e-8 * right.length() * up.length()
+}
+
+// where the ray from p along the unit vector direction meets the
+// parallelogram corner + [0, 1] e1 + [0, 1] e2: the distance, and the
+// cosine between direction and the parallelogram's normal.
+fn parallelogram_hit(p: &Vec3, direction: &Vec3, corner: &Vec3, e1: &Vec3, e2: &Vec3) -> Option<(f64, f64)> {
+    let n = vector::cross(e1, e2);
+    let denom = direction.dot(&n);
+    if denom == 0.0 {
+        return None;
+    }
+    let t = (*corner - *p).dot(&n) / denom;
+    if t <= 0.0 {
+        return None;
+    }
+    let q = *p + *direction * t - *corner;
+    let n2 = n.length_squared();
+    let alpha = vector::cross(&q, e2).dot(&n) / n2;
+    let beta = vector::cross(e1, &q).dot(&n) / n2;
+    if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+        None
+    } else {
+        Some((t, denom / n2.sqrt()))
+    }
+}
+
+fn triangle_hit(p: &Vec3, direction: &Vec3, vertices: &[Vec3; 3]) -> Option<(f64, f64)> {
+    let e1 = vertices[1] - vertices[0];
+    let e2 = vertices[2] - vertices[0];
+    parallelogram_hit(p, direction, &vertices[0], &e1, &e2).and_then(|(t, cos)| {
+        let q = *p + *direction * t - vertices[0];
+        let n = vector::cross(&e1, &e2);
+        let alpha = vector::cross(&q, &e2).dot(&n) / n.length_squared();
+        let beta = vector::cross(&e1, &q).dot(&n) / n.length_squared();
+        if alpha + beta <= 1.0 { Some((t, cos)) } else { None }
+    })
+}
+
+fn disc_hit(p: &Vec3, direction: &Vec3, center: &Vec3, normal: &Vec3, radius: f64) -> Option<(f64, f64)> {
+    let denom = direction.dot(normal);
+    if denom == 0.0 {
+        return None;
+    }
+    let t = (*center - *p).dot(normal) / denom;
+    if t <= 0.0 || (*p + *direction * t - *center).length() > radius {
+        None
+    } else {
+        Some((t, denom))
+    }
+}
+
+// converts the density 1 / area of a point at distance dist, seen at
+// an angle with cosine cos, to solid angle
+fn area_to_solid_angle(dist: f64, cos: f64, area: f64) -> f64 {
+    let cos = cos.abs();
+    if cos < 1e-8 || area < 1e-16 {
+        0.0
+    } else {
+        dist * dist / (cos * area)
+    }
+}
+
+// emitters are two-sided: picks a side, then a cosine-weighted
+// direction on it. Returns the direction and its solid-angle density.
+pub fn sample_emission_direction(normal: &Vec3) -> (Vec3, f64) {
+    let side = if rand_double() < 0.5 { *normal } else { -*normal };
+    let direction = vector::unit_vector(&(sampling::random_3d_direction() + side));
+    (direction, emission_direction_pdf(normal, &direction))
+}
+
+pub fn emission_direction_pdf(normal: &Vec3, direction: &Vec3) -> f64 {
+    direction.dot(normal).abs() / (2.0 * PI)
+}
+
+// area density of choosing an area light by power and then a point on
+// it with sample_point.
+pub fn light_origin_pdf(emission: &Vec3, total_power: f64) -> f64 {
+    if total_power > 0.0 {
+        vector::luminance(emission) / total_power
+    } else {
+        0.0
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn samples_lie_on_the_surface() {
+    use tests::*;
+
+    let sphere = AreaLight::new(LightShape::Sphere {
+        center: Vec3::new(1.0, 2.0, 3.0), radius: 0.5 }, Vec3::new(1.0, 1.0, 1.0));
+    let triangle = AreaLight::new(LightShape::Triangle { vertices: [
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(2.0, 0.0, 0.0),
+        Vec3::new(0.0, 2.0, 0.0)] }, Vec3::new(1.0, 1.0, 1.0));
+    assert!(within_eps_f(triangle.area(), 2.0));
+    let disc = AreaLight::new(LightShape::Disc {
+        center: Vec3::new(0.0, 1.0, 0.0), normal: Vec3::new(0.0, 1.0, 0.0), radius: 2.0 },
+                              Vec3::new(1.0, 1.0, 1.0));
+
+    for _ in 0..1000 {
+        let (p, n) = sphere.sample_point();
+        assert!(within_eps_f((p - Vec3::new(1.0, 2.0, 3.0)).length(), 0.5));
+        assert!(within_eps_f(n.length(), 1.0));
+
+        let (p, n) = triangle.sample_point();
+        assert!(within_eps_f(p.z(), 0.0));
+        assert!(p.x() >= 0.0 && p.y() >= 0.0 && p.x() + p.y() <= 2.0 + 1e-8);
+        assert!(within_eps(&n, &Vec3::new(0.0, 0.0, 1.0)));
+
+        let (p, n) = disc.sample_point();
+        assert!(within_eps_f(p.y(), 1.0) && (p - Vec3::new(0.0, 1.0, 0.0)).length() <= 2.0);
+        assert!(within_eps(&n, &Vec3::new(0.0, 1.0, 0.0)));
+    }
+    let b = disc.bounding_box();
+    assert!(within_eps(&b.min(), &Vec3::new(-2.0, 1.0, -2.0)) && within_eps(&b.max(), &Vec3::new(2.0, 1.0, 2.0)));
+
+    // curved ones, along y
+    let white = Vec3::new(1.0, 1.0, 1.0);
+    let cylinder = AreaLight::new(LightShape::Cylinder {
+        base: Vec3::zero(), top: Vec3::new(0.0, 2.0, 0.0), radius: 0.5 }, white);
+    let cone = AreaLight::new(LightShape::Cone {
+        base: Vec3::zero(), apex: Vec3::new(0.0, 1.0, 0.0), radius: 1.0 }, white);
+    let torus = AreaLight::new(LightShape::Torus {
+        center: Vec3::zero(), axis: Vec3::new(0.0, 1.0, 0.0), major_radius: 2.0, minor_radius: 0.5 }, white);
+    let across = |p: &Vec3| (p.x() * p.x() + p.z() * p.z()).sqrt();
+    let (mut upper, mut outer) = (0, 0);
+    for _ in 0..10000 {
+        let (p, n) = cylinder.sample_point();
+        assert!(within_eps_f(across(&p), 0.5) && p.y() >= 0.0 && p.y() <= 2.0);
+        assert!(within_eps(&n, &Vec3::new(p.x() * 2.0, 0.0, p.z() * 2.0)));
+
+        let (p, n) = cone.sample_point();
+        assert!(within_eps_f(across(&p), 1.0 - p.y()) && p.y() >= 0.0);
+        assert!(within_eps(&n, &vector::unit_vector(&Vec3::new(p.x(), across(&p), p.z()))));
+        upper += (p.y() > 0.5) as usize;
+
+        let (p, n) = torus.sample_point();
+        let ring = vector::unit_vector(&Vec3::new(p.x(), 0.0, p.z())) * 2.0;
+        assert!(within_eps_f((p - ring).length(), 0.5) && within_eps(&n, &((p - ring) * 2.0)));
+        outer += (across(&p) > 2.0) as usize;
+    }
+    // a quarter of the cone's side is above half its height, and the
+    // outside of the torus is 2 + 1 / pi of 4 of its area
+    assert!((upper as f64 / 10000.0 - 0.25).abs() < 0.02);
+    assert!((outer as f64 / 10000.0 - (2.0 + 1.0 / PI) / 4.0).abs() < 0.02);
+    let b = torus.bounding_box();
+    assert!(within_eps(&b.min(), &Vec3::new(-2.5, -0.5, -2.5)) && within_eps(&b.max(), &Vec3::new(2.5, 0.5, 2.5)));
+}
+
+#[test]
+fn direction_samples_match_their_density() {
+    let white = Vec3::new(1.0, 1.0, 1.0);
+    let sphere = AreaLight::new(LightShape::Sphere {
+        center: Vec3::new(0.0, 1.0, 0.0), radius: 0.5 }, white);
+    let rectangle = AreaLight::new(LightShape::Rectangle {
+        bottom_left: Vec3::new(-0.5, 1.0, -0.5),
+        right: Vec3::new(1.0, 0.0, 0.0),
+        up: Vec3::new(0.0, 0.0, 2.0) }, white);
+    let parallelogram = AreaLight::new(LightShape::Rectangle {
+        bottom_left: Vec3::new(-0.5, 1.0, -0.5),
+        right: Vec3::new(1.0, 0.0, 0.0),
+        up: Vec3::new(0.5, 0.5, 1.0) }, white);
+    let triangle = AreaLight::new(LightShape::Triangle { vertices: [
+        Vec3::new(-1.0, 1.0, 0.0),
+        Vec3::new(1.0, 1.0, 0.0),
+        Vec3::new(0.0, 1.0, 1.0)] }, white);
+    let disc = AreaLight::new(LightShape::Disc {
+        center: Vec3::new(0.0, 1.0, 0.5), normal: vector::unit_vector(&Vec3::new(0.0, -1.0, 1.0)),
+        radius: 0.5 }, white);
+    let cylinder = AreaLight::new(LightShape::Cylinder {
+        base: Vec3::new(-0.5, 1.0, 0.0), top: Vec3::new(0.5, 1.0, 0.5), radius: 0.3 }, white);
+    let cone = AreaLight::new(LightShape::Cone {
+        base: Vec3::new(0.0, 1.0, 0.0), apex: Vec3::new(0.0, 2.0, 0.0), radius: 0.5 }, white);
+    let torus = AreaLight::new(LightShape::Torus {
+        center: Vec3::new(0.0, 1.0, 0.0), axis: vector::unit_vector(&Vec3::new(1.0, 1.0, 0.0)),
+        major_radius: 0.6, minor_radius: 0.2 }, white);
+    let cases = [(&sphere, Vec3::zero()),
+                 // from inside the sphere
+                 (&sphere, Vec3::new(0.1, 1.2, 0.0)),
+                 (&rectangle, Vec3::zero()),
+                 (&rectangle, Vec3::new(1.0, 0.5, 1.0)),
+                 (&parallelogram, Vec3::zero()),
+                 (&triangle, Vec3::zero()),
+                 (&disc, Vec3::zero()),
+                 (&cylinder, Vec3::zero()),
+                 // from inside, seeing the sides all round
+                 (&cylinder, Vec3::new(0.0, 1.0, 0.25)),
+                 (&cone, Vec3::zero()),
+                 (&cone, Vec3::new(0.0, 3.0, 0.0)),
+                 (&torus, Vec3::zero()),
+                 // from the hole
+                 (&torus, Vec3::new(0.0, 1.0, 0.0))];
+
+    let n = 400000;
+    // whether the mean of n samples is within a few standard errors of
+    // what it estimates, given their standard deviation
+    let close = |mean: f64, expected: f64, stdev: f64| {
+        (mean - expected).abs() <= 5.0 * stdev / (n as f64).sqrt() + 1e-9
+    };
+    for &(light, p) in cases.iter() {
+        // the density integrates to one over the sphere of directions
+        let mut itor = (0..n).map(|_| {
+            light.direction_pdf(&p, &sampling::random_3d_direction()) * 4.0 * PI
+        });
+        let (integral, stdev) = sampling::avstdev(&mut itor);
+        assert!(close(integral, 1.0, stdev));
+
+        // and the samples come with it: 1 / pdf averages to the solid
+        // angle the light subtends, which is 1 / pdf for the exact ones.
+        // That's estimated too, by the fraction of directions that see
+        // the light.
+        let seen = (0..n).filter(|_| {
+            light.direction_pdf(&p, &sampling::random_3d_direction()) > 0.0
+        }).count() as f64 / n as f64;
+        let solid_angle = 4.0 * PI * seen;
+        let mut itor = (0..n).map(|_| {
+            let direction = light.sample_direction(&p).unwrap();
+            let pdf = light.direction_pdf(&p, &direction);
+            assert!(pdf > 0.0);
+            1.0 / pdf
+        });
+        let (average, stdev) = sampling::avstdev(&mut itor);
+        let solid_angle_stdev = 4.0 * PI * (seen * (1.0 - seen)).sqrt();
+        assert!(close(average, solid_angle, (stdev * stdev + solid_angle_stdev * solid_angle_stdev).sqrt()));
+    }
+}
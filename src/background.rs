@@ -1,7 +1,36 @@
+use sampling;
 use vector::*;
 
+use std::f64::consts::PI;
+
 pub trait Background : Send + Sync {
     fn get_background(&self, unit_direction: &Vec3) -> Vec3;
+
+    // a unit vector towards the background, and its solid-angle
+    // density. Backgrounds that know where their light comes from
+    // sample it there; the rest sample uniformly.
+    fn sample_direction(&self) -> (Vec3, f64) {
+        (sampling::random_3d_direction(), 1.0 / (4.0 * PI))
+    }
+
+    // solid-angle density with which sample_direction generates
+    // unit_direction
+    fn direction_pdf(&self, _unit_direction: &Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    // over all directions, for choosing between the background and the
+    // other lights. Estimated over a Fibonacci spiral of directions.
+    fn average_luminance(&self) -> f64 {
+        let n = 256;
+        let golden_angle = PI * (3.0 - 5.0f64.sqrt());
+        (0..n).map(|i| {
+            let z = 1.0 - (2 * i + 1) as f64 / n as f64;
+            let r = (1.0 - z * z).sqrt();
+            let phi = golden_angle * i as f64;
+            luminance(&self.get_background(&Vec3::new(r * phi.cos(), r * phi.sin(), z)))
+        }).sum::<f64>() / n as f64
+    }
 }
 
 pub struct Sky {}
@@ -0,0 +1,523 @@
+use area_light::*;
+use hitable::*;
+use integrator::*;
+use light::*;
+use material::*;
+use ray::Ray;
+use vector::Vec3;
+use vector;
+
+//////////////////////////////////////////////////////////////////////////////
+// bidirectional path tracing, after Veach's thesis and pbrt's
+// formulation of it. A camera subpath and a light subpath are traced
+// independently, and every pair of their prefixes is joined into a
+// full path. Each of those strategies is weighted with the balance
+// heuristic over all the strategies that could have produced the same
+// path.
+//
+// Strategies that put a single vertex on the camera side (light tracing
+// onto the film) aren't implemented; the MIS weights only consider the
+// ones we actually use, so the estimator is still unbiased.
+//
+// Lights are chosen in proportion to their power and sampled uniformly
+// by area, so a point x on an emitter with radiance Le is generated
+// with area density luminance(Le) / total power. Emitters are
+// two-sided, like the Emitter material.
+//
+// Point and spot lights start light subpaths like the emitters do, but
+// can't be hit by camera subpaths. Directional lights can't be joined
+// to a vertex either, since they only shine along one direction; light
+// subpaths chosen to start on one are dropped, and their light arrives
+// instead through a shadow ray from each camera vertex, the only
+// strategy that samples it. Light subpaths never start on the
+// background, so it gets shadow rays from the camera vertices too,
+// weighted with the power heuristic against camera subpaths that
+// escape to it. Emitting objects that make no area lights can't start
+// light subpaths either, so only camera subpaths that hit them count.
+
+#[derive(Clone, Copy, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface
+}
+
+#[derive(Clone, Copy)]
+struct Vertex<'a> {
+    kind: VertexKind,
+    p: Vec3,
+    normal: Vec3,
+    material: Option<&'a Material>,
+    // the light the first light vertex is on
+    light: Option<&'a Light>,
+    // the ray that arrived at this vertex, which the materials need to
+    // evaluate themselves. For light vertices it's meaningless.
+    ray_in: Ray,
+    // product of the path weights up to (not including) this vertex
+    beta: Vec3,
+    // radiance emitted at this vertex: the light's emission for the
+    // first light vertex, or what a camera subpath found when it hit an
+    // emitter.
+    emission: Option<Vec3>,
+    // whether that emitter is one of the lights, which light subpaths
+    // can start from
+    on_light: bool,
+    delta: bool,
+    // area densities of sampling this vertex from the previous one on
+    // its own subpath, and from the next one.
+    pdf_fwd: f64,
+    pdf_rev: f64
+}
+
+impl<'a> Vertex<'a> {
+    fn camera(ray: &Ray) -> Vertex<'a> {
+        Vertex {
+            kind: VertexKind::Camera,
+            p: ray.origin(),
+            normal: Vec3::zero(),
+            material: None,
+            light: None,
+            ray_in: *ray,
+            beta: Vec3::new(1.0, 1.0, 1.0),
+            emission: None,
+            on_light: false,
+            delta: false,
+            pdf_fwd: 1.0,
+            pdf_rev: 0.0
+        }
+    }
+
+    fn light(light: &'a Light, sample: &EmissionSample) -> Vertex<'a> {
+        Vertex {
+            kind: VertexKind::Light,
+            p: sample.p,
+            normal: sample.normal,
+            material: None,
+            light: Some(light),
+            ray_in: Ray::zero(),
+            beta: Vec3::new(1.0, 1.0, 1.0) / sample.pdf_pos,
+            emission: Some(sample.radiance),
+            on_light: true,
+            delta: false,
+            pdf_fwd: sample.pdf_pos,
+            pdf_rev: 0.0
+        }
+    }
+
+    fn surface(hr: &HitRecord<'a>, ray_in: &Ray, beta: Vec3) -> Vertex<'a> {
+        Vertex {
+            kind: VertexKind::Surface,
+            p: hr.p,
+            normal: hr.normal,
+            material: Some(hr.material),
+            light: None,
+            ray_in: *ray_in,
+            beta: beta,
+            emission: None,
+            on_light: false,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0
+        }
+    }
+
+    fn is_emitter(&self) -> bool {
+        self.emission.is_some()
+    }
+
+    fn is_delta_light(&self) -> bool {
+        self.light.is_some_and(|l| l.is_delta())
+    }
+
+    // can this vertex be joined to a vertex of the other subpath?
+    fn is_connectible(&self) -> bool {
+        match self.kind {
+            VertexKind::Camera => false,
+            VertexKind::Light => true,
+            VertexKind::Surface => !self.delta && !self.is_emitter()
+        }
+    }
+
+    // the cosine-weighted bsdf (or emission) at this vertex for light
+    // leaving towards `next`, given that it arrived along ray_in.
+    fn f(&self, next: &Vertex) -> Vec3 {
+        let w = next.p - self.p;
+        match self.kind {
+            VertexKind::Camera => Vec3::zero(),
+            VertexKind::Light => {
+                let w = vector::unit_vector(&w);
+                let le = self.light.unwrap().emitted(&w);
+                // point lights have no surface to be foreshortened
+                if self.normal == Vec3::zero() { le } else { le * w.dot(&self.normal).abs() }
+            },
+            VertexKind::Surface => self.f_along(&vector::unit_vector(&w))
+        }
+    }
+
+    // solid-angle density of the bsdf sampling light leaving a surface
+    // vertex along the unit vector direction
+    fn pdf_along(&self, direction: &Vec3) -> f64 {
+        let material = self.material.unwrap();
+        let ray_out = Ray::new(self.p, *direction);
+        let normal = facing_normal(&self.ray_in, &self.normal);
+        bsdf_pdf(material, &self.ray_in, &ray_out, &normal)
+    }
+
+    // the cosine-weighted bsdf at a surface vertex for light leaving
+    // along the unit vector direction
+    fn f_along(&self, direction: &Vec3) -> Vec3 {
+        let material = self.material.unwrap();
+        let ray_out = Ray::new(self.p, *direction);
+        let normal = facing_normal(&self.ray_in, &self.normal);
+        if material.bsdf(&self.ray_in, &ray_out, &normal) <= 0.0 {
+            Vec3::zero()
+        } else {
+            bsdf_value(material, &self.ray_in, &ray_out, &normal)
+        }
+    }
+
+    // area density, at next, of sampling next from this vertex when
+    // the subpath arrived here from prev.
+    fn pdf(&self, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        match self.kind {
+            VertexKind::Camera => 0.0,
+            VertexKind::Light => {
+                let w = vector::unit_vector(&(next.p - self.p));
+                convert_density(self.light.unwrap().pdf_le_direction(&self.normal, &w), self, next)
+            },
+            VertexKind::Surface => {
+                let material = self.material.unwrap();
+                let ray_in = match prev {
+                    Some(prev) => Ray::new(prev.p, self.p - prev.p),
+                    None => self.ray_in
+                };
+                let ray_out = Ray::new(self.p, next.p - self.p);
+                let normal = facing_normal(&ray_in, &self.normal);
+                convert_density(bsdf_pdf(material, &ray_in, &ray_out, &normal), self, next)
+            }
+        }
+    }
+}
+
+// converts a solid-angle density at `from` to an area density at `to`
+fn convert_density(pdf: f64, from: &Vertex, to: &Vertex) -> f64 {
+    let w = to.p - from.p;
+    let dist2 = w.length_squared();
+    if dist2 == 0.0 {
+        return 0.0;
+    }
+    let mut result = pdf / dist2;
+    // scattering events in media have no surface to be foreshortened
+    if to.kind != VertexKind::Camera && to.normal != Vec3::zero() {
+        result *= vector::unit_vector(&w).dot(&to.normal).abs();
+    }
+    result
+}
+
+// extends path by following the bsdf from ray, which was sampled with
+// solid-angle density pdf. Returns the radiance from the background
+// for camera subpaths that escape the scene.
+fn random_walk<'a>(context: &RenderContext<'a>, ray: &Ray, beta: Vec3, pdf: f64,
+                   max_vertices: usize, camera_path: bool,
+                   path: &mut Vec<Vertex<'a>>) -> Vec3 {
+    let mut current_ray = *ray;
+    let mut current_beta = beta;
+    let mut pdf_fwd = pdf;
+
+    while path.len() < max_vertices {
+        let hr = match context.world.hit(&current_ray, 0.00001, 1e20) {
+            None => {
+                if camera_path {
+                    let unit_direction = vector::unit_vector(&current_ray.direction());
+                    // camera rays and specular bounces are the only
+                    // way to reach the background along them
+                    let last = &path[path.len() - 1];
+                    let w = if last.kind == VertexKind::Camera || pdf_fwd == 0.0 {
+                        1.0
+                    } else {
+                        power_heuristic(pdf_fwd, background_pdf(context, &last.p, &unit_direction))
+                    };
+                    return context.background.get_background(&unit_direction) * current_beta * w;
+                }
+                break;
+            },
+            Some(hr) => hr
+        };
+        let prev_ix = path.len() - 1;
+        let mut vertex = Vertex::surface(&hr, &current_ray, current_beta);
+        vertex.pdf_fwd = convert_density(pdf_fwd, &path[prev_ix], &vertex);
+
+        if let Some(le) = emission(&hr) {
+            // light subpaths carry nothing useful past an emitter
+            if camera_path {
+                let length = current_ray.direction().length();
+                vertex.emission = Some(le);
+                vertex.on_light = context.lights.is_light_at(&current_ray.origin(),
+                                                             &(current_ray.direction() / length),
+                                                             hr.t * length);
+                path.push(vertex);
+            }
+            break;
+        }
+
+        let (attenuation, scattered) = match hr.material.scatter(&current_ray, &hr) {
+            Scatter::Bounce(attenuation, scattered) => (attenuation, scattered),
+            _ => {
+                path.push(vertex);
+                break;
+            }
+        };
+
+        let specular = !hr.material.wants_importance_sampling();
+        let (pdf_dir, pdf_rev_dir) = if specular {
+            (0.0, 0.0)
+        } else {
+            let normal = facing_normal(&current_ray, &hr.normal);
+            let reverse_in = Ray::new(hr.p + scattered.direction(), -scattered.direction());
+            let reverse_out = Ray::new(hr.p, -current_ray.direction());
+            let reverse_normal = facing_normal(&reverse_in, &hr.normal);
+            (bsdf_pdf(hr.material, &current_ray, &scattered, &normal),
+             bsdf_pdf(hr.material, &reverse_in, &reverse_out, &reverse_normal))
+        };
+        vertex.delta = specular;
+        path.push(vertex);
+        if !specular && pdf_dir <= 0.0 {
+            break;
+        }
+        let this_ix = path.len() - 1;
+        path[prev_ix].pdf_rev = convert_density(pdf_rev_dir, &path[this_ix], &path[prev_ix]);
+
+        current_beta = current_beta * attenuation;
+        current_ray = scattered;
+        pdf_fwd = pdf_dir;
+    }
+    Vec3::zero()
+}
+
+fn remap0(f: f64) -> f64 {
+    if f != 0.0 { f } else { 1.0 }
+}
+
+// balance heuristic weight of joining the first s light vertices to
+// the first t camera vertices.
+fn mis_weight(context: &RenderContext, light_path: &[Vertex], camera_path: &[Vertex],
+              s: usize, t: usize) -> f64 {
+    let mut light_rev: Vec<f64> = light_path[..s].iter().map(|v| v.pdf_rev).collect();
+    let mut camera_rev: Vec<f64> = camera_path[..t].iter().map(|v| v.pdf_rev).collect();
+
+    // the densities of the vertices around the connection change once
+    // the two subpaths are joined
+    let pt = &camera_path[t - 1];
+    let pt_minus = &camera_path[t - 2];
+    if s > 0 {
+        let qs = &light_path[s - 1];
+        let qs_minus = if s > 1 { Some(&light_path[s - 2]) } else { None };
+        camera_rev[t - 1] = qs.pdf(qs_minus, pt);
+        camera_rev[t - 2] = pt.pdf(Some(qs), pt_minus);
+        light_rev[s - 1] = pt.pdf(Some(pt_minus), qs);
+        if let Some(qs_minus) = qs_minus {
+            light_rev[s - 2] = qs.pdf(Some(pt), qs_minus);
+        }
+    } else if !pt.on_light {
+        // pt is on an emitter that isn't a light, so no light subpath
+        // could have started there: its reverse density is zero, and
+        // this is the only strategy that makes the path. (remap0 would
+        // treat the zero as a delta, so it's returned straight away.)
+        return 1.0;
+    } else {
+        // pt is on an emitter, and could have been the light subpath's
+        // starting point. Lights are chosen by their RGB power, also in
+        // spectral mode.
+        let emission = pt.material.unwrap().emission_spectrum().rgb;
+        camera_rev[t - 1] = context.lights.emission_pdf_pos(&emission);
+        let w = vector::unit_vector(&(pt_minus.p - pt.p));
+        camera_rev[t - 2] = convert_density(emission_direction_pdf(&pt.normal, &w), pt, pt_minus);
+    }
+
+    let mut sum_ri = 0.0;
+    let mut ri = 1.0;
+    // stop before the strategy with a single camera vertex
+    for i in (2..t).rev() {
+        ri *= remap0(camera_rev[i]) / remap0(camera_path[i].pdf_fwd);
+        if !camera_path[i].delta && !camera_path[i - 1].delta {
+            sum_ri += ri;
+        }
+    }
+    ri = 1.0;
+    for i in (0..s).rev() {
+        ri *= remap0(light_rev[i]) / remap0(light_path[i].pdf_fwd);
+        let delta_light_vertex = if i > 0 {
+            light_path[i - 1].delta
+        } else {
+            light_path[0].is_delta_light()
+        };
+        if !light_path[i].delta && !delta_light_vertex {
+            sum_ri += ri;
+        }
+    }
+    1.0 / (1.0 + sum_ri)
+}
+
+// unweighted contribution of the path made from the first s light
+// vertices and the first t camera vertices
+fn connect(context: &RenderContext, light_path: &[Vertex], camera_path: &[Vertex],
+           s: usize, t: usize) -> Vec3 {
+    let pt = &camera_path[t - 1];
+    if s == 0 {
+        return match pt.emission {
+            Some(le) => pt.beta * le,
+            None => Vec3::zero()
+        };
+    }
+    let qs = &light_path[s - 1];
+    if !pt.is_connectible() || !qs.is_connectible() {
+        return Vec3::zero();
+    }
+    let d = qs.p - pt.p;
+    let l = qs.beta * qs.f(pt) * pt.f(qs) * pt.beta / d.length_squared();
+    if l.x() == 0.0 && l.y() == 0.0 && l.z() == 0.0 {
+        return l;
+    }
+    match context.world.hit(&Ray::with_time(pt.p, d, pt.ray_in.time()), 0.00001, 1.0 - 0.00001) {
+        None => l,
+        Some(_) => Vec3::zero()
+    }
+}
+
+// solid-angle density of the shadow rays infinite_lights sends from p
+// towards the background along direction
+fn background_pdf(context: &RenderContext, p: &Vec3, direction: &Vec3) -> f64 {
+    context.lights.lights.iter()
+        .filter(|l| l.is_infinite() && !l.is_delta())
+        .map(|l| l.pdf_li(p, direction))
+        .sum()
+}
+
+// light from the directional lights and the background reflected at pt
+// towards the previous camera vertex
+fn infinite_lights(context: &RenderContext, pt: &Vertex) -> Vec3 {
+    if !pt.is_connectible() {
+        return Vec3::zero();
+    }
+    let mut result = Vec3::zero();
+    for light in context.lights.lights.iter().filter(|l| l.is_infinite()) {
+        if let Some(sample) = light.sample_li(&pt.p) {
+            let f = pt.f_along(&sample.direction);
+            if f == Vec3::zero() {
+                continue;
+            }
+            let shadow_ray = Ray::with_time(pt.p, sample.direction, pt.ray_in.time());
+            if !unoccluded(context, &shadow_ray, sample.distance) {
+                continue;
+            }
+            let w = if sample.delta {
+                1.0
+            } else {
+                power_heuristic(sample.pdf, pt.pdf_along(&sample.direction))
+            };
+            result = result + pt.beta * f * sample.radiance * (w / sample.pdf);
+        }
+    }
+    result
+}
+
+pub struct Bdpt {}
+
+impl Integrator for Bdpt {
+    fn color(&self, ray: &Ray, context: &RenderContext) -> Vec3 {
+        let max_depth = context.settings.max_depth;
+
+        let mut camera_path = vec![Vertex::camera(ray)];
+        let mut result = random_walk(context, ray, Vec3::new(1.0, 1.0, 1.0), 1.0,
+                                     max_depth + 2, true, &mut camera_path);
+
+        let mut light_path = Vec::new();
+        if let Some((light, sample)) = context.lights.sample_le() {
+            if !light.is_infinite() {
+                light_path.push(Vertex::light(light, &sample));
+                if sample.pdf_dir > 0.0 {
+                    let beta = light_path[0].beta * sample.radiance * (sample.cos() / sample.pdf_dir);
+                    // the light subpath happens when the camera's does
+                    let light_ray = Ray::with_time(sample.p, sample.direction, ray.time());
+                    random_walk(context, &light_ray, beta, sample.pdf_dir,
+                                max_depth + 1, false, &mut light_path);
+                }
+            }
+        }
+
+        for t in 2..camera_path.len() + 1 {
+            if t < max_depth + 2 {
+                result = result + infinite_lights(context, &camera_path[t - 1]);
+            }
+        }
+
+        for t in 2..camera_path.len() + 1 {
+            for s in 0..light_path.len() + 1 {
+                if s + t > max_depth + 2 {
+                    continue;
+                }
+                let l = connect(context, &light_path, &camera_path, s, t);
+                if l.x() == 0.0 && l.y() == 0.0 && l.z() == 0.0 {
+                    continue;
+                }
+                result = result + l * mis_weight(context, &light_path, &camera_path, s, t);
+            }
+        }
+        result
+    }
+}
+
+impl Bdpt {
+    pub fn new() -> Box<Integrator> {
+        Box::new(Bdpt {})
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn emitters_that_arent_lights_are_not_weighted_as_lights() {
+    use aabb::AABB;
+    use background;
+    use background::Background;
+    use emitter::Emitter;
+    use hitable_list::HitableList;
+    use lambertian::Lambertian;
+    use path_tracer::PathTracer;
+    use rectangle::Rectangle;
+    use render_settings::RenderSettings;
+    use sampling;
+    use sdf::*;
+    use sphere::Sphere;
+    use std::sync::Arc;
+
+    // a floor lit by a sphere that makes no area lights, and by a dim
+    // one off to the side that does, so that light subpaths exist
+    let objects: Vec<Box<Hitable + Send + Sync>> = vec![
+        Box::new(Rectangle::new(Vec3::new(-5.0, 0.0, 5.0), Vec3::new(10.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -10.0),
+                                Lambertian::new(&Vec3::new(0.5, 0.5, 0.5)))),
+        Box::new(SdfSurface::new(Sdf::Sphere { center: Vec3::new(0.0, 2.0, 0.0), radius: 0.5 },
+                                 AABB::new(Vec3::new(-1.0, 1.0, -1.0), Vec3::new(1.0, 3.0, 1.0)),
+                                 Emitter::new(&Vec3::new(4.0, 4.0, 4.0)), 1.0)),
+        Box::new(Sphere::new(Vec3::new(4.0, 2.0, 0.0), 0.5, Emitter::new(&Vec3::new(0.1, 0.1, 0.1))))];
+    let background: Arc<Background + Send + Sync> = Arc::new(background::constant(Vec3::zero()));
+    let lights = LightList::collect(&objects, Vec::new(), &background);
+    assert!(lights.len() == 2);
+    let world = HitableList::new(objects);
+    let settings = RenderSettings::default();
+    let context = RenderContext {
+        world: &world,
+        background: &*background,
+        lights: &lights,
+        settings: &settings
+    };
+
+    // the floor under the sphere reflects albedo * L * sin^2 of the
+    // angle it subtends, and a little from the other
+    let ray = Ray::new(Vec3::new(0.0, 1.0, 3.0), Vec3::new(0.0, -1.0, -3.0));
+    let n = 40000;
+    let (bdpt, _) = sampling::avstdev(&mut (0..n).map(|_| Bdpt::new().color(&ray, &context).x()));
+    let (path, _) = sampling::avstdev(&mut (0..n).map(|_| PathTracer::new().color(&ray, &context).x()));
+    assert!((path - 0.125).abs() < 0.01);
+    assert!((bdpt - path).abs() < 0.015);
+}
@@ -0,0 +1,51 @@
+use integrator::*;
+use material;
+use ray::Ray;
+use vector::Vec3;
+use vector;
+
+//////////////////////////////////////////////////////////////////////////////
+// the plain Shirley-style path tracer: follow whatever the bsdf samples
+// and hope to hit a light. Useful as a reference estimator.
+
+pub struct BsdfTracer {}
+
+impl Integrator for BsdfTracer {
+    fn color(&self, ray: &Ray, context: &RenderContext) -> Vec3 {
+        let mut current_ray = *ray;
+        let mut current_attenuation = Vec3::new(1.0, 1.0, 1.0);
+
+        for depth in 0..context.settings.max_depth {
+            if !russian_roulette(context, depth, &mut current_attenuation) {
+                return Vec3::new(0.0, 0.0, 0.0)
+            }
+            match context.world.hit(&current_ray, 0.00001, 1e20) {
+                None => {
+                    let unit_direction = vector::unit_vector(&current_ray.direction());
+                    return context.background.get_background(&unit_direction) * current_attenuation;
+                },
+                Some(hr) => {
+                    match hr.material.scatter(&current_ray, &hr) {
+                        material::Scatter::Bounce(next_attenuation, scattered) => {
+                            current_attenuation = current_attenuation * next_attenuation;
+                            current_ray = scattered;
+                        },
+                        material::Scatter::Emit(emission) => {
+                            return emission * current_attenuation;
+                        },
+                        material::Scatter::Absorb => {
+                            return Vec3::new(0.0, 0.0, 0.0)
+                        }
+                    }
+                }
+            }
+        }
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+}
+
+impl BsdfTracer {
+    pub fn new() -> Box<Integrator> {
+        Box::new(BsdfTracer {})
+    }
+}
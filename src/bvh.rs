@@ -1,5 +1,6 @@
 use aabb::AABB;
 use aabb;
+use area_light::AreaLight;
 use hitable::*;
 use hitable_list::*;
 use ray::Ray;
@@ -49,9 +50,10 @@ impl Hitable for BVH {
             None
         }
     }
-    // this should never be called
-    fn importance_distribution(&self) -> Option<AABB> {
-        panic!("importance_distribution called on BVH!")
+    fn area_lights(&self) -> Vec<AreaLight> {
+        let mut result = self.left.area_lights();
+        result.extend(self.right.area_lights());
+        result
     }
 }
 
@@ -59,11 +61,26 @@ impl Hitable for BVH {
 const MIN_LENGTH: usize = 32;
 
 impl BVH {
-   
-    pub fn build(mut objs: Vec<Box<Hitable + Send + Sync>>) -> Box<Hitable + Send + Sync> {
+
+    // objects without a bounding box (infinite planes, say) can't go in
+    // the tree, so they're kept on the side, in a World
+    pub fn build(objs: Vec<Box<Hitable + Send + Sync>>) -> Box<Hitable + Send + Sync> {
         if objs.len() == 0 {
             panic!("Need nonempty objs!");
-        } else if objs.len() <= MIN_LENGTH {
+        }
+        let (bounded, unbounded): (Vec<_>, Vec<_>) = objs.into_iter().partition(|h| h.bounding_box().is_some());
+        if unbounded.is_empty() {
+            BVH::build_bounded(bounded)
+        } else {
+            Box::new(World {
+                bvh: if bounded.is_empty() { None } else { Some(BVH::build_bounded(bounded)) },
+                unbounded: HitableList::new(unbounded)
+            })
+        }
+    }
+
+    fn build_bounded(mut objs: Vec<Box<Hitable + Send + Sync>>) -> Box<Hitable + Send + Sync> {
+        if objs.len() <= MIN_LENGTH {
             Box::new(HitableList::new(objs))
             // let result = objs.remove(0);
             // // eprintln!("leaf, bounding box: {:?}", result.bounding_box());
@@ -84,8 +101,8 @@ impl BVH {
             let median_ix = objs.len() / 2;
             let right_objs = objs.drain(median_ix..).collect();
             let left_objs = objs.drain(..).collect();
-            let left_hitable = BVH::build(left_objs);
-            let right_hitable = BVH::build(right_objs);
+            let left_hitable = BVH::build_bounded(left_objs);
+            let right_hitable = BVH::build_bounded(right_objs);
             let bbox = aabb::surrounding_box(
                 &left_hitable.as_ref().bounding_box().unwrap(),
                 &right_hitable.as_ref().bounding_box().unwrap());
@@ -98,3 +115,66 @@ impl BVH {
         }
     }
 }
+
+//////////////////////////////////////////////////////////////////////////////
+// a scene with unbounded objects: the tree of the bounded ones, and the
+// rest, which every ray is tested against as well. It has no bounding
+// box itself.
+
+pub struct World {
+    pub bvh: Option<Box<Hitable + Send + Sync>>,
+    pub unbounded: HitableList
+}
+
+impl Hitable for World {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'a>> {
+        let unbounded = self.unbounded.hit(r, t_min, t_max);
+        let closest = unbounded.as_ref().map_or(t_max, |hr| hr.t);
+        match self.bvh.as_ref().and_then(|bvh| bvh.hit(r, t_min, closest)) {
+            None => unbounded,
+            bounded => bounded
+        }
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        None
+    }
+
+    fn area_lights(&self) -> Vec<AreaLight> {
+        let mut result = self.unbounded.area_lights();
+        if let Some(ref bvh) = self.bvh {
+            result.extend(bvh.area_lights());
+        }
+        result
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn unbounded_objects_stay_out_of_the_tree() {
+    use lambertian::Lambertian;
+    use plane::PlaneSurface;
+    use sphere::Sphere;
+    use vector::Vec3;
+
+    let grey = || Lambertian::new(&Vec3::new(0.5, 0.5, 0.5));
+    // a ground plane under a row of spheres, enough for a tree
+    let mut objs: Vec<Box<Hitable + Send + Sync>> = (0..100).map(|i| {
+        Box::new(Sphere::new(Vec3::new(i as f64, 1.0, 0.0), 0.25, grey())) as Box<Hitable + Send + Sync>
+    }).collect();
+    objs.push(Box::new(PlaneSurface::new(&Vec3::zero(), &Vec3::new(0.0, 1.0, 0.0), grey())));
+    let world = BVH::build(objs);
+    assert!(world.bounding_box().is_none());
+
+    // the spheres hide the plane
+    let down = |x: f64| Ray::new(Vec3::new(x, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+    assert!((world.hit(&down(50.0), 0.0, 1e20).unwrap().t - 3.75).abs() < 1e-9);
+    // and between them, the plane is still there, far from the tree
+    assert!((world.hit(&down(50.5), 0.0, 1e20).unwrap().t - 5.0).abs() < 1e-9);
+    assert!((world.hit(&down(-1000.0), 0.0, 1e20).unwrap().t - 5.0).abs() < 1e-9);
+
+    // on its own, too
+    let alone = BVH::build(vec![Box::new(PlaneSurface::new(&Vec3::zero(), &Vec3::new(0.0, 1.0, 0.0), grey()))]);
+    assert!(alone.hit(&down(3.0), 0.0, 1e20).is_some());
+}
@@ -1,3 +1,4 @@
+use random::*;
 use ray::Ray;
 use sampling;
 use std::f64::consts::PI;
@@ -14,6 +15,9 @@ pub struct Camera {
     pub u: Vec3,
     pub v: Vec3,
     pub w: Vec3,
+    // rays happen at times spread evenly between these
+    pub shutter_open: f64,
+    pub shutter_close: f64,
 
     // making serialization easier
     pub params: CameraParams
@@ -41,6 +45,8 @@ impl Camera {
             u: u,
             v: v,
             w: w,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
             params: CameraParams {
                 look_from: *look_from,
                 look_at: *look_at,
@@ -52,13 +58,29 @@ impl Camera {
             }
         }
     }
+
+    pub fn with_shutter(mut self, shutter_open: f64, shutter_close: f64) -> Camera {
+        self.shutter_open = shutter_open;
+        self.shutter_close = shutter_close;
+        self
+    }
+
+    pub fn sample_time(&self) -> f64 {
+        if self.shutter_close > self.shutter_open {
+            self.shutter_open + rand_double() * (self.shutter_close - self.shutter_open)
+        } else {
+            self.shutter_open
+        }
+    }
+
     pub fn get_ray(&self, s: f64, t: f64) -> Ray {
         let rd = self.lens_radius * sampling::random_in_unit_disk();
         let offset = self.u * rd.x() + self.v * rd.y();
-        Ray::new(self.origin + offset,
-                 self.lower_left_corner +
-                 s * self.horizontal +
-                 t * self.vertical - self.origin - offset)
+        Ray::with_time(self.origin + offset,
+                       self.lower_left_corner +
+                       s * self.horizontal +
+                       t * self.vertical - self.origin - offset,
+                       self.sample_time())
     }
 }
 
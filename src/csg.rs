@@ -0,0 +1,170 @@
+use aabb::AABB;
+use aabb;
+use area_light::AreaLight;
+use hitable::*;
+use ray::Ray;
+
+//////////////////////////////////////////////////////////////////////////////
+// constructive solid geometry: the union, intersection or difference of
+// two closed objects, like a lens made from two spheres, or a block
+// with a hole drilled through it.
+//
+// Both objects' intervals along a ray are merged into the combination's,
+// which are again intervals, so combinations nest. Surfaces cut out by
+// a difference face the other way.
+//
+// Combinations don't make area lights, so only bsdf samples find
+// emitters in them.
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    Difference
+}
+
+impl CsgOperation {
+    pub fn inside(&self, in_a: bool, in_b: bool) -> bool {
+        match *self {
+            CsgOperation::Union => in_a || in_b,
+            CsgOperation::Intersection => in_a && in_b,
+            CsgOperation::Difference => in_a && !in_b
+        }
+    }
+}
+
+pub struct Csg {
+    pub operation: CsgOperation,
+    pub a: Box<Hitable + Send + Sync>,
+    pub b: Box<Hitable + Send + Sync>
+}
+
+impl Csg {
+    pub fn new(operation: CsgOperation,
+               a: Box<Hitable + Send + Sync>, b: Box<Hitable + Send + Sync>) -> Csg {
+        Csg {
+            operation: operation,
+            a: a,
+            b: b
+        }
+    }
+}
+
+impl Hitable for Csg {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'a>> {
+        self.intervals(r).into_iter()
+            .flat_map(|(enter, exit)| vec![enter, exit])
+            .find(|hr| hr.t > t_min && hr.t < t_max)
+    }
+
+    fn intervals<'a>(&'a self, r: &Ray) -> Vec<(HitRecord<'a>, HitRecord<'a>)> {
+        // every crossing of either object's surface, in order, with
+        // whether it's a's
+        let mut crossings = Vec::new();
+        for (enter, exit) in self.a.intervals(r) {
+            crossings.push((enter, true));
+            crossings.push((exit, true));
+        }
+        for (mut enter, mut exit) in self.b.intervals(r) {
+            if self.operation == CsgOperation::Difference {
+                enter.normal = -enter.normal;
+                exit.normal = -exit.normal;
+            }
+            crossings.push((enter, false));
+            crossings.push((exit, false));
+        }
+        crossings.sort_by(|x, y| x.0.t.partial_cmp(&y.0.t).unwrap());
+
+        // each crossing flips whether we're inside its object
+        let mut result = Vec::new();
+        let (mut in_a, mut in_b) = (false, false);
+        let mut entered = None;
+        for (hr, is_a) in crossings {
+            let was_inside = self.operation.inside(in_a, in_b);
+            if is_a { in_a = !in_a; } else { in_b = !in_b; }
+            let is_inside = self.operation.inside(in_a, in_b);
+            if !was_inside && is_inside {
+                entered = Some(hr);
+            } else if was_inside && !is_inside {
+                if let Some(enter) = entered.take() {
+                    result.push((enter, hr));
+                }
+            }
+        }
+        result
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        let a = self.a.bounding_box();
+        let b = self.b.bounding_box();
+        match self.operation {
+            CsgOperation::Union => match (a, b) {
+                (Some(a), Some(b)) => Some(aabb::surrounding_box(&a, &b)),
+                _ => None
+            },
+            CsgOperation::Intersection => match (a, b) {
+                (Some(a), Some(b)) => {
+                    let (lo, hi) = (a.min(), a.max());
+                    let mut result = AABB::new(lo, hi);
+                    for i in 0..3 {
+                        result._min[i] = lo[i].max(b.min()[i]);
+                        result._max[i] = hi[i].min(b.max()[i]).max(result._min[i]);
+                    }
+                    Some(result)
+                },
+                (a, None) => a,
+                (None, b) => b
+            },
+            CsgOperation::Difference => a
+        }
+    }
+
+    fn area_lights(&self) -> Vec<AreaLight> {
+        Vec::new()
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn csg_combines_solids() {
+    use lambertian::Lambertian;
+    use sphere::Sphere;
+    use vector::Vec3;
+
+    let sphere = |x: f64| -> Box<Hitable + Send + Sync> {
+        Box::new(Sphere::new(Vec3::new(x, 0.0, 0.0), 1.0, Lambertian::new(&Vec3::new(0.5, 0.5, 0.5))))
+    };
+    let along_x = Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+    let ts = |csg: &Csg| -> Vec<(f64, f64)> {
+        csg.intervals(&along_x).iter().map(|&(ref enter, ref exit)| (enter.t, exit.t)).collect()
+    };
+
+    // spheres at x = 0 and x = 1: [-1, 1] and [0, 2], and the ray
+    // starts at x = -5
+    let union = Csg::new(CsgOperation::Union, sphere(0.0), sphere(1.0));
+    assert!(ts(&union) == vec![(4.0, 7.0)]);
+    // a lens
+    let lens = Csg::new(CsgOperation::Intersection, sphere(0.0), sphere(1.0));
+    assert!(ts(&lens) == vec![(5.0, 6.0)]);
+    let bitten = Csg::new(CsgOperation::Difference, sphere(0.0), sphere(1.0));
+    assert!(ts(&bitten) == vec![(4.0, 5.0)]);
+    let apart = Csg::new(CsgOperation::Intersection, sphere(0.0), sphere(3.0));
+    assert!(ts(&apart).is_empty());
+
+    // the bite's surface is the inside of the second sphere, facing
+    // out of what's left
+    let hr = bitten.hit(&along_x, 4.5, 1e20).unwrap();
+    assert!(hr.t == 5.0 && hr.normal == Vec3::new(1.0, 0.0, 0.0));
+    // the lens from inside
+    let from_inside = Ray::new(Vec3::new(0.5, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+    assert!(lens.hit(&from_inside, 0.00001, 1e20).unwrap().t == 0.5);
+
+    // drilling through the middle, with combinations nested
+    let drilled = Csg::new(CsgOperation::Difference, Box::new(union),
+                           Box::new(Sphere::new(Vec3::new(0.5, 0.0, 0.0), 0.25,
+                                                Lambertian::new(&Vec3::zero()))));
+    assert!(ts(&drilled) == vec![(4.0, 5.25), (5.75, 7.0)]);
+    let b = lens.bounding_box().unwrap();
+    assert!(b.min() == Vec3::new(0.0, -1.0, -1.0) && b.max() == Vec3::new(1.0, 1.0, 1.0));
+}
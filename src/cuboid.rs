@@ -0,0 +1,159 @@
+use aabb::AABB;
+use area_light::*;
+use hitable::*;
+use material::*;
+use matrix::Matrix;
+use ray::Ray;
+use vector::Vec3;
+use vector;
+
+//////////////////////////////////////////////////////////////////////////////
+// a box from min to max, placed by a transform: the identity for
+// axis-aligned boxes, a rotation (and whatever else) for oriented ones.
+// Called a cuboid here because box is taken.
+//
+// Emitting boxes make an area light for each face.
+
+pub struct Cuboid {
+    pub min: Vec3,
+    pub max: Vec3,
+    pub material: Box<Material>,
+    pub to_world: Matrix,
+    pub to_local: Matrix
+}
+
+impl Cuboid {
+    pub fn new(min: Vec3, max: Vec3, material: Box<Material>) -> Cuboid {
+        Cuboid::oriented(min, max, &Matrix::identity(), material).unwrap()
+    }
+
+    // None if the transform can't be inverted
+    pub fn oriented(min: Vec3, max: Vec3, transform: &Matrix,
+                    material: Box<Material>) -> Option<Cuboid> {
+        Some(Cuboid {
+            min: min,
+            max: max,
+            material: material,
+            to_world: *transform,
+            to_local: transform.inverse()?
+        })
+    }
+
+    // where the local ray enters and leaves the box, with the normals
+    // there, by slabs
+    fn local_interval(&self, r: &Ray) -> Option<((f64, Vec3), (f64, Vec3))> {
+        let (o, d) = (r.origin(), r.direction());
+        let mut near = (-1e20, Vec3::zero());
+        let mut far = (1e20, Vec3::zero());
+        for axis in 0..3 {
+            if d[axis] == 0.0 {
+                if o[axis] < self.min[axis] || o[axis] > self.max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let mut normal = Vec3::zero();
+            normal[axis] = -d[axis].signum();
+            let t0 = (self.min[axis] - o[axis]) / d[axis];
+            let t1 = (self.max[axis] - o[axis]) / d[axis];
+            let (t_in, t_out) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+            if t_in > near.0 {
+                near = (t_in, normal);
+            }
+            if t_out < far.0 {
+                far = (t_out, -normal);
+            }
+        }
+        if near.0 > far.0 {
+            None
+        } else {
+            Some((near, far))
+        }
+    }
+
+    fn to_world_hit<'a>(&'a self, r: &Ray, (t, normal): (f64, Vec3)) -> HitRecord<'a> {
+        HitRecord::hit(t, r.point_at_parameter(t),
+                       vector::unit_vector(&Matrix::transform_normal_by_inverse(&self.to_local, &normal)),
+                       &*self.material)
+    }
+}
+
+impl Hitable for Cuboid {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'a>> {
+        let (near, far) = self.local_interval(&self.to_local.transform_ray(r))?;
+        if near.0 > t_min && near.0 < t_max {
+            Some(self.to_world_hit(r, near))
+        } else if far.0 > t_min && far.0 < t_max {
+            Some(self.to_world_hit(r, far))
+        } else {
+            None
+        }
+    }
+
+    fn intervals<'a>(&'a self, r: &Ray) -> Vec<(HitRecord<'a>, HitRecord<'a>)> {
+        match self.local_interval(&self.to_local.transform_ray(r)) {
+            None => Vec::new(),
+            Some((near, far)) => vec![(self.to_world_hit(r, near), self.to_world_hit(r, far))]
+        }
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.to_world.transform_aabb(&AABB::new(self.min, self.max)))
+    }
+
+    fn area_lights(&self) -> Vec<AreaLight> {
+        if !self.material.is_emitter() {
+            return Vec::new();
+        }
+        let size = self.max - self.min;
+        let edges = [Vec3::new(size.x(), 0.0, 0.0),
+                     Vec3::new(0.0, size.y(), 0.0),
+                     Vec3::new(0.0, 0.0, size.z())];
+        let mut result = Vec::new();
+        for axis in 0..3 {
+            let right = self.to_world.transform_vector(&edges[(axis + 1) % 3]);
+            let up = self.to_world.transform_vector(&edges[(axis + 2) % 3]);
+            for corner in [self.min, self.min + edges[axis]].iter() {
+                result.push(AreaLight::with_spectrum(LightShape::Rectangle {
+                    bottom_left: self.to_world.transform_point(corner),
+                    right: right,
+                    up: up
+                }, self.material.emission_spectrum()));
+            }
+        }
+        result
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn boxes_are_hit_on_their_faces() {
+    use emitter::Emitter;
+    use std::f64::consts::PI;
+
+    let unit = || Cuboid::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0),
+                              Emitter::new(&Vec3::new(1.0, 1.0, 1.0)));
+    let cube = unit();
+    let along_x = Ray::new(Vec3::new(-5.0, 0.5, 0.2), Vec3::new(1.0, 0.0, 0.0));
+    let hr = cube.hit(&along_x, 0.0, 1e20).unwrap();
+    assert!(hr.t == 4.0 && hr.normal == Vec3::new(-1.0, 0.0, 0.0));
+    // from inside, the far face
+    let hr = cube.hit(&along_x, 4.5, 1e20).unwrap();
+    assert!(hr.t == 6.0 && hr.normal == Vec3::new(1.0, 0.0, 0.0));
+    assert!(cube.hit(&Ray::new(Vec3::new(-5.0, 1.5, 0.0), Vec3::new(1.0, 0.0, 0.0)), 0.0, 1e20).is_none());
+
+    // six faces of area 4
+    let lights = cube.area_lights();
+    assert!(lights.len() == 6 && lights.iter().all(|l| (l.area() - 4.0).abs() < 1e-12));
+
+    // turned 45 degrees about y, it's hit on an edge's two faces
+    let turned = Cuboid::oriented(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0),
+                                  &Matrix::rotate(&Vec3::new(0.0, 1.0, 0.0), PI / 4.0),
+                                  Emitter::new(&Vec3::new(1.0, 1.0, 1.0))).unwrap();
+    let hr = turned.hit(&Ray::new(Vec3::new(-5.0, 0.0, 0.1), Vec3::new(1.0, 0.0, 0.0)), 0.0, 1e20).unwrap();
+    assert!((hr.p.x() + 2.0f64.sqrt() - 0.1).abs() < 1e-9);
+    assert!((hr.normal.y()).abs() < 1e-9 && hr.normal.x() < 0.0 && hr.normal.z() > 0.0);
+    let b = turned.bounding_box().unwrap();
+    assert!((b.max().x() - 2.0f64.sqrt()).abs() < 1e-9 && (b.max().y() - 1.0).abs() < 1e-9);
+}
@@ -0,0 +1,262 @@
+use aabb::AABB;
+use area_light::{AreaLight, LightShape};
+use hitable::*;
+use material::*;
+use matrix::Matrix;
+use polynomial::solve_quadratic;
+use ray::Ray;
+use vector::Vec3;
+use vector;
+
+//////////////////////////////////////////////////////////////////////////////
+// cylinders and cones, from the center of their base to that of their
+// top (or their apex), closed by flat caps unless they're open. Both are
+// worked out in a frame where they stand on the origin along +z.
+//
+// Emitting ones make area lights of their sides and of each cap.
+
+pub struct Cylinder {
+    pub base: Vec3,
+    pub top: Vec3,
+    pub radius: f64,
+    pub capped: bool,
+    pub material: Box<Material>,
+    pub to_world: Matrix,
+    pub to_local: Matrix
+}
+
+pub struct Cone {
+    pub base: Vec3,
+    pub apex: Vec3,
+    pub radius: f64,
+    pub capped: bool,
+    pub material: Box<Material>,
+    pub to_world: Matrix,
+    pub to_local: Matrix
+}
+
+// the closest of the local hits (t, normal) within (t_min, t_max),
+// brought back out
+fn closest_hit<'a>(hits: &[(f64, Vec3)], r: &Ray, t_min: f64, t_max: f64,
+                   to_world: &Matrix, material: &'a Material) -> Option<HitRecord<'a>> {
+    hits.iter()
+        .filter(|&&(t, _)| t > t_min && t < t_max)
+        .min_by(|x, y| x.0.partial_cmp(&y.0).unwrap())
+        .map(|&(t, normal)| {
+            HitRecord::hit(t, r.point_at_parameter(t),
+                           vector::unit_vector(&to_world.transform_vector(&normal)), material)
+        })
+}
+
+// where the local ray crosses the plane z = height within radius of the
+// axis, facing along normal_z
+fn cap_hit(r: &Ray, height: f64, radius: f64, normal_z: f64) -> Option<(f64, Vec3)> {
+    let (o, d) = (r.origin(), r.direction());
+    if d.z() == 0.0 {
+        return None;
+    }
+    let t = (height - o.z()) / d.z();
+    let p = r.point_at_parameter(t);
+    if p.x() * p.x() + p.y() * p.y() <= radius * radius {
+        Some((t, Vec3::new(0.0, 0.0, normal_z)))
+    } else {
+        None
+    }
+}
+
+fn cap_light(center: &Vec3, axis: &Vec3, radius: f64, material: &Material) -> AreaLight {
+    AreaLight::with_spectrum(LightShape::Disc {
+        center: *center,
+        normal: vector::unit_vector(axis),
+        radius: radius
+    }, material.emission_spectrum())
+}
+
+impl Cylinder {
+    pub fn new(base: Vec3, top: Vec3, radius: f64, capped: bool,
+               material: Box<Material>) -> Cylinder {
+        let to_world = Matrix::frame_along(&base, &(top - base));
+        Cylinder {
+            base: base,
+            top: top,
+            radius: radius,
+            capped: capped,
+            material: material,
+            to_world: to_world,
+            to_local: to_world.inverse().unwrap()
+        }
+    }
+
+    pub fn height(&self) -> f64 {
+        (self.top - self.base).length()
+    }
+
+    fn local_hits(&self, r: &Ray) -> Vec<(f64, Vec3)> {
+        let (o, d) = (r.origin(), r.direction());
+        let h = self.height();
+        let mut hits: Vec<(f64, Vec3)> = solve_quadratic(
+            d.x() * d.x() + d.y() * d.y(),
+            2.0 * (o.x() * d.x() + o.y() * d.y()),
+            o.x() * o.x() + o.y() * o.y() - self.radius * self.radius)
+            .into_iter()
+            .map(|t| (t, r.point_at_parameter(t)))
+            .filter(|&(_, p)| p.z() >= 0.0 && p.z() <= h)
+            .map(|(t, p)| (t, Vec3::new(p.x(), p.y(), 0.0)))
+            .collect();
+        if self.capped {
+            hits.extend(cap_hit(r, 0.0, self.radius, -1.0));
+            hits.extend(cap_hit(r, h, self.radius, 1.0));
+        }
+        hits
+    }
+}
+
+impl Hitable for Cylinder {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'a>> {
+        let local = self.to_local.transform_ray(r);
+        closest_hit(&self.local_hits(&local), r, t_min, t_max, &self.to_world, &*self.material)
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        let r = self.radius;
+        Some(self.to_world.transform_aabb(&AABB::new(Vec3::new(-r, -r, 0.0),
+                                                     Vec3::new(r, r, self.height()))))
+    }
+
+    fn area_lights(&self) -> Vec<AreaLight> {
+        if !self.material.is_emitter() {
+            return Vec::new();
+        }
+        let mut result = vec![AreaLight::with_spectrum(LightShape::Cylinder {
+            base: self.base,
+            top: self.top,
+            radius: self.radius
+        }, self.material.emission_spectrum())];
+        if self.capped {
+            result.push(cap_light(&self.base, &(self.top - self.base), self.radius, &*self.material));
+            result.push(cap_light(&self.top, &(self.top - self.base), self.radius, &*self.material));
+        }
+        result
+    }
+}
+
+impl Cone {
+    pub fn new(base: Vec3, apex: Vec3, radius: f64, capped: bool,
+               material: Box<Material>) -> Cone {
+        let to_world = Matrix::frame_along(&base, &(apex - base));
+        Cone {
+            base: base,
+            apex: apex,
+            radius: radius,
+            capped: capped,
+            material: material,
+            to_world: to_world,
+            to_local: to_world.inverse().unwrap()
+        }
+    }
+
+    pub fn height(&self) -> f64 {
+        (self.apex - self.base).length()
+    }
+
+    fn local_hits(&self, r: &Ray) -> Vec<(f64, Vec3)> {
+        // x^2 + y^2 = k^2 (h - z)^2
+        let (o, d) = (r.origin(), r.direction());
+        let h = self.height();
+        let k2 = (self.radius / h) * (self.radius / h);
+        let above = h - o.z();
+        let mut hits: Vec<(f64, Vec3)> = solve_quadratic(
+            d.x() * d.x() + d.y() * d.y() - k2 * d.z() * d.z(),
+            2.0 * (o.x() * d.x() + o.y() * d.y() + k2 * above * d.z()),
+            o.x() * o.x() + o.y() * o.y() - k2 * above * above)
+            .into_iter()
+            .map(|t| (t, r.point_at_parameter(t)))
+            .filter(|&(_, p)| p.z() >= 0.0 && p.z() <= h)
+            .map(|(t, p)| (t, Vec3::new(p.x(), p.y(), k2 * (h - p.z()))))
+            .collect();
+        if self.capped {
+            hits.extend(cap_hit(r, 0.0, self.radius, -1.0));
+        }
+        hits
+    }
+}
+
+impl Hitable for Cone {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'a>> {
+        let local = self.to_local.transform_ray(r);
+        closest_hit(&self.local_hits(&local), r, t_min, t_max, &self.to_world, &*self.material)
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        let r = self.radius;
+        Some(self.to_world.transform_aabb(&AABB::new(Vec3::new(-r, -r, 0.0),
+                                                     Vec3::new(r, r, self.height()))))
+    }
+
+    fn area_lights(&self) -> Vec<AreaLight> {
+        if !self.material.is_emitter() {
+            return Vec::new();
+        }
+        let mut result = vec![AreaLight::with_spectrum(LightShape::Cone {
+            base: self.base,
+            apex: self.apex,
+            radius: self.radius
+        }, self.material.emission_spectrum())];
+        if self.capped {
+            result.push(cap_light(&self.base, &(self.apex - self.base), self.radius, &*self.material));
+        }
+        result
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn cylinders_and_cones_have_sides_and_caps() {
+    use emitter::Emitter;
+    use lambertian::Lambertian;
+    use std::f64::consts::PI;
+
+    let grey = || Lambertian::new(&Vec3::new(0.5, 0.5, 0.5));
+    // standing on the origin, 2 high, along +y
+    let cylinder = Cylinder::new(Vec3::zero(), Vec3::new(0.0, 2.0, 0.0), 0.5, true, grey());
+    let from = |o: Vec3, d: Vec3| Ray::new(o, d);
+
+    let side = cylinder.hit(&from(Vec3::new(5.0, 1.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)), 0.0, 1e20).unwrap();
+    assert!((side.t - 4.5).abs() < 1e-9);
+    assert!((side.normal - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-9);
+    let top = cylinder.hit(&from(Vec3::new(0.2, 5.0, 0.1), Vec3::new(0.0, -1.0, 0.0)), 0.0, 1e20).unwrap();
+    assert!((top.t - 3.0).abs() < 1e-9);
+    assert!((top.normal - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-9);
+    // past the end
+    assert!(cylinder.hit(&from(Vec3::new(5.0, 2.5, 0.0), Vec3::new(-1.0, 0.0, 0.0)), 0.0, 1e20).is_none());
+
+    // open ones are seen through their ends, from inside
+    let open = Cylinder::new(Vec3::zero(), Vec3::new(0.0, 2.0, 0.0), 0.5, false, grey());
+    let inside = open.hit(&from(Vec3::new(0.0, 3.0, 0.0), Vec3::new(0.2, -1.0, 0.0)), 0.0, 1e20).unwrap();
+    assert!((inside.p - Vec3::new(0.5, 0.5, 0.0)).length() < 1e-9);
+
+    let b = cylinder.bounding_box().unwrap();
+    assert!((b.min() - Vec3::new(-0.5, 0.0, -0.5)).length() < 1e-9);
+    assert!((b.max() - Vec3::new(0.5, 2.0, 0.5)).length() < 1e-9);
+
+    // a cone 1 wide at the base and 1 high: 45 degree sides
+    let cone = Cone::new(Vec3::zero(), Vec3::new(0.0, 1.0, 0.0), 1.0, true, grey());
+    let hr = cone.hit(&from(Vec3::new(5.0, 0.5, 0.0), Vec3::new(-1.0, 0.0, 0.0)), 0.0, 1e20).unwrap();
+    assert!((hr.t - 4.5).abs() < 1e-9);
+    assert!((hr.normal - vector::unit_vector(&Vec3::new(1.0, 1.0, 0.0))).length() < 1e-9);
+    let bottom = cone.hit(&from(Vec3::new(0.5, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0)), 0.0, 1e20).unwrap();
+    assert!((bottom.t - 5.0).abs() < 1e-9);
+    assert!((bottom.normal - Vec3::new(0.0, -1.0, 0.0)).length() < 1e-9);
+    // above the apex, the other nappe isn't there
+    assert!(cone.hit(&from(Vec3::new(5.0, 1.5, 0.0), Vec3::new(-1.0, 0.0, 0.0)), 0.0, 1e20).is_none());
+
+    // glowing ones light their sides and caps
+    let glow = || Emitter::new(&Vec3::new(1.0, 1.0, 1.0));
+    let area = |lights: Vec<AreaLight>| lights.iter().map(|l| l.area()).sum::<f64>();
+    let glowing = Cylinder::new(Vec3::zero(), Vec3::new(0.0, 2.0, 0.0), 0.5, true, glow());
+    assert!(glowing.area_lights().len() == 3 && (area(glowing.area_lights()) - 2.5 * PI).abs() < 1e-9);
+    let glowing = Cone::new(Vec3::zero(), Vec3::new(0.0, 1.0, 0.0), 1.0, false, glow());
+    assert!(glowing.area_lights().len() == 1 && (area(glowing.area_lights()) - 2.0f64.sqrt() * PI).abs() < 1e-9);
+    assert!(cylinder.area_lights().is_empty());
+}
@@ -0,0 +1,296 @@
+use light::*;
+use sampling;
+use spectrum;
+use vector::Vec3;
+use vector;
+
+use std::f64::consts::PI;
+use std::f64;
+
+//////////////////////////////////////////////////////////////////////////////
+// lights without any extent: point lights, spot lights, and directional
+// lights infinitely far away. No ray can hit them, so integrators only
+// see them through shadow rays.
+
+pub struct PointLight {
+    pub position: Vec3,
+    pub intensity: Vec3
+}
+
+impl PointLight {
+    pub fn new(position: &Vec3, intensity: &Vec3) -> Box<Light> {
+        Box::new(PointLight {
+            position: *position,
+            intensity: *intensity
+        })
+    }
+}
+
+impl Light for PointLight {
+    fn sample_li(&self, p: &Vec3) -> Option<LightSample> {
+        let d = self.position - *p;
+        let dist2 = d.length_squared();
+        if dist2 == 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction: vector::unit_vector(&d),
+            distance: dist2.sqrt(),
+            radiance: spectrum::upsample(&self.intensity) / dist2,
+            pdf: 1.0,
+            delta: true
+        })
+    }
+
+    fn pdf_li(&self, _p: &Vec3, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    fn power(&self) -> f64 {
+        2.0 * vector::luminance(&self.intensity)
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn sample_le(&self) -> Option<EmissionSample> {
+        Some(EmissionSample {
+            p: self.position,
+            normal: Vec3::zero(),
+            direction: sampling::random_3d_direction(),
+            radiance: spectrum::upsample(&self.intensity),
+            pdf_pos: 1.0,
+            pdf_dir: 1.0 / (4.0 * PI)
+        })
+    }
+
+    fn emitted(&self, _direction: &Vec3) -> Vec3 {
+        spectrum::upsample(&self.intensity)
+    }
+
+    fn pdf_le_direction(&self, _normal: &Vec3, _direction: &Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// a point light shining into a cone. The intensity is full out to
+// cos_falloff_start and falls off smoothly to nothing at cos_total_width,
+// like pbrt's spot lights.
+
+pub struct SpotLight {
+    pub position: Vec3,
+    // unit vector along the axis of the cone
+    pub direction: Vec3,
+    pub intensity: Vec3,
+    pub cos_total_width: f64,
+    pub cos_falloff_start: f64
+}
+
+impl SpotLight {
+    // the angles are the cone's half-angle and the width of the falloff
+    // at its edge, in degrees
+    pub fn new(position: &Vec3, direction: &Vec3, intensity: &Vec3,
+               cone_angle: f64, cone_delta: f64) -> Box<Light> {
+        let cone_angle = cone_angle.clamp(0.0, 90.0);
+        let falloff_start = (cone_angle - cone_delta).max(0.0).min(cone_angle);
+        Box::new(SpotLight {
+            position: *position,
+            direction: vector::unit_vector(direction),
+            intensity: *intensity,
+            cos_total_width: cone_angle.to_radians().cos(),
+            cos_falloff_start: falloff_start.to_radians().cos()
+        })
+    }
+
+    fn falloff(&self, direction: &Vec3) -> f64 {
+        let cos = direction.dot(&self.direction);
+        if cos >= self.cos_falloff_start {
+            1.0
+        } else if cos <= self.cos_total_width {
+            0.0
+        } else {
+            let x = (cos - self.cos_total_width) / (self.cos_falloff_start - self.cos_total_width);
+            x * x * (3.0 - 2.0 * x)
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn sample_li(&self, p: &Vec3) -> Option<LightSample> {
+        let d = self.position - *p;
+        let dist2 = d.length_squared();
+        if dist2 == 0.0 {
+            return None;
+        }
+        let direction = vector::unit_vector(&d);
+        let falloff = self.falloff(&-direction);
+        if falloff == 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction: direction,
+            distance: dist2.sqrt(),
+            radiance: spectrum::upsample(&self.intensity) * (falloff / dist2),
+            pdf: 1.0,
+            delta: true
+        })
+    }
+
+    fn pdf_li(&self, _p: &Vec3, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    // the smoothstep averages to one half over the falloff
+    fn power(&self) -> f64 {
+        vector::luminance(&self.intensity) *
+            ((1.0 - self.cos_falloff_start) + (self.cos_falloff_start - self.cos_total_width) / 2.0)
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    // uniformly within the cone
+    fn sample_le(&self) -> Option<EmissionSample> {
+        let direction = sampling::random_in_cone(&self.direction, 1.0 - self.cos_total_width);
+        Some(EmissionSample {
+            p: self.position,
+            normal: Vec3::zero(),
+            direction: direction,
+            radiance: self.emitted(&direction),
+            pdf_pos: 1.0,
+            pdf_dir: self.pdf_le_direction(&Vec3::zero(), &direction)
+        })
+    }
+
+    fn emitted(&self, direction: &Vec3) -> Vec3 {
+        spectrum::upsample(&self.intensity) * self.falloff(direction)
+    }
+
+    fn pdf_le_direction(&self, _normal: &Vec3, direction: &Vec3) -> f64 {
+        if direction.dot(&self.direction) < self.cos_total_width {
+            0.0
+        } else {
+            1.0 / (2.0 * PI * (1.0 - self.cos_total_width))
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// parallel light arriving from infinitely far away, like the sun's.
+// Paths start on a disc just outside the scene, facing it.
+
+pub struct DirectionalLight {
+    // unit vector along which the light travels
+    pub direction: Vec3,
+    // on surfaces facing the light
+    pub irradiance: Vec3,
+    pub world_center: Vec3,
+    pub world_radius: f64
+}
+
+impl DirectionalLight {
+    pub fn new(direction: &Vec3, irradiance: &Vec3) -> Box<Light> {
+        Box::new(DirectionalLight {
+            direction: vector::unit_vector(direction),
+            irradiance: *irradiance,
+            world_center: Vec3::zero(),
+            world_radius: 1.0
+        })
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample_li(&self, _p: &Vec3) -> Option<LightSample> {
+        Some(LightSample {
+            direction: -self.direction,
+            distance: f64::INFINITY,
+            radiance: spectrum::upsample(&self.irradiance),
+            pdf: 1.0,
+            delta: true
+        })
+    }
+
+    fn pdf_li(&self, _p: &Vec3, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    // the scene catches pi R^2 E of it
+    fn power(&self) -> f64 {
+        self.world_radius * self.world_radius * vector::luminance(&self.irradiance) / 2.0
+    }
+
+    fn is_infinite(&self) -> bool {
+        true
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn preprocess(&mut self, world_center: &Vec3, world_radius: f64) {
+        self.world_center = *world_center;
+        self.world_radius = world_radius;
+    }
+
+    fn sample_le(&self) -> Option<EmissionSample> {
+        let (t1, t2) = vector::tangent_space(&self.direction);
+        let d = sampling::random_in_unit_disk() * self.world_radius;
+        let p = self.world_center - self.direction * self.world_radius + t1 * d.x() + t2 * d.y();
+        Some(EmissionSample {
+            p: p,
+            normal: self.direction,
+            direction: self.direction,
+            radiance: spectrum::upsample(&self.irradiance),
+            pdf_pos: 1.0 / (PI * self.world_radius * self.world_radius),
+            pdf_dir: 1.0
+        })
+    }
+
+    fn emitted(&self, _direction: &Vec3) -> Vec3 {
+        spectrum::upsample(&self.irradiance)
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn spot_lights_emit_their_power() {
+    // the flux is 2 pi times the power, and sample_le's directions
+    // carry it, on average
+    let light = SpotLight::new(&Vec3::zero(), &Vec3::new(0.0, -1.0, 0.0),
+                               &Vec3::new(1.0, 1.0, 1.0), 40.0, 15.0);
+    let n = 200000;
+    let mut itor = (0..n).map(|_| {
+        let sample = light.sample_le().unwrap();
+        assert!(sample.direction.y() < 0.0);
+        assert!(sample.cos() == 1.0);
+        vector::luminance(&sample.radiance) / sample.pdf_dir
+    });
+    let (flux, _) = sampling::avstdev(&mut itor);
+    assert!((flux / (2.0 * PI * light.power()) - 1.0).abs() < 0.01);
+
+    // and sample_li agrees with it
+    let below = light.sample_li(&Vec3::new(0.0, -2.0, 0.0)).unwrap();
+    assert!(below.delta);
+    assert!((below.radiance - Vec3::new(0.25, 0.25, 0.25)).length() < 1e-8);
+    assert!(light.sample_li(&Vec3::new(2.0, -1.0, 0.0)).is_none());
+}
+
+#[test]
+fn directional_lights_cover_the_scene() {
+    let mut light = DirectionalLight::new(&Vec3::new(0.0, 0.0, -2.0), &Vec3::new(1.0, 2.0, 3.0));
+    light.preprocess(&Vec3::new(1.0, 0.0, 0.0), 2.0);
+    for _ in 0..1000 {
+        let sample = light.sample_le().unwrap();
+        // on a disc behind the scene's bounding sphere, facing it
+        assert!((sample.p.z() - 2.0).abs() < 1e-8);
+        assert!((sample.p - Vec3::new(1.0, 0.0, 2.0)).length() <= 2.0 + 1e-8);
+        assert!(sample.direction == Vec3::new(0.0, 0.0, -1.0));
+    }
+    let sample = light.sample_li(&Vec3::new(5.0, 5.0, 5.0)).unwrap();
+    assert!(sample.direction == Vec3::new(0.0, 0.0, 1.0));
+    assert!(sample.distance == f64::INFINITY);
+}
@@ -0,0 +1,73 @@
+extern crate rand;
+extern crate getopts;
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate serde;
+extern crate serde_json;
+
+mod denoise;
+// shared with loom-render, which uses the rest of it
+#[allow(dead_code)]
+mod vector;
+mod tests;
+
+use denoise::*;
+use getopts::Options;
+
+use std::env;
+
+//////////////////////////////////////////////////////////////////////////////
+// denoises a render saved by loom-render --aovs: reads <input>.linear_rgb
+// and its albedo, normal and depth images, and writes the result to
+// <output>.linear_rgb.
+
+fn load(file_name: &str) -> Vec<Vec<vector::Vec3>> {
+    read_linear_rgb(file_name)
+        .unwrap_or_else(|| panic!("Could not read {}", file_name))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    let mut opts = Options::new();
+
+    opts.optopt("i", "input", "prefix of the render to denoise", "NAME");
+    opts.optopt("o", "output", "set output file name, default <input>.denoised", "NAME");
+    opts.optopt("", "iterations", "number of filtering passes, default 5", "N");
+    opts.optopt("", "sigma-color", "how different colors can be before they stop being averaged, default 0.5", "X");
+    opts.optflag("?", "help", "print this help menu");
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => { m }
+        Err(f) => { panic!("{}", f) }
+    };
+    if matches.opt_present("?") || !matches.opt_present("i") {
+        print!("{}", opts.usage("Usage: loom-denoise -i PREFIX [options]"));
+        return;
+    }
+
+    let input = matches.opt_str("i").unwrap();
+    let output = matches.opt_str("o").unwrap_or_else(|| format!("{}.denoised", input));
+    let mut settings = DenoiseSettings::default();
+    if let Some(n) = matches.opt_str("iterations").and_then(|x| x.parse::<usize>().ok()) {
+        settings.iterations = n;
+    }
+    if let Some(s) = matches.opt_str("sigma-color").and_then(|x| x.parse::<f64>().ok()) {
+        settings.sigma_color = s;
+    }
+
+    let image = load(&format!("{}.linear_rgb", input));
+    let albedo = load(&format!("{}.albedo.linear_rgb", input));
+    let normal = load(&format!("{}.normal.linear_rgb", input));
+    let depth = load(&format!("{}.depth.linear_rgb", input));
+    let features = Features {
+        albedo: &albedo,
+        normal: &normal,
+        depth: &depth
+    };
+    let result = denoise(&image, &features, &settings);
+    println!("Writing output to {}.linear_rgb", output);
+    write_linear_rgb(&result, &format!("{}.linear_rgb", output));
+}
@@ -0,0 +1,224 @@
+use vector::Vec3;
+
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+
+//////////////////////////////////////////////////////////////////////////////
+// reading and writing the .linear_rgb files loom-render writes: a
+// "width height" line, then one "r g b" line per pixel, top row first.
+// Images are indexed [row][column], with row 0 at the bottom.
+
+pub fn read_linear_rgb(file_name: &str) -> Option<Vec<Vec<Vec3>>> {
+    let f = match File::open(file_name) {
+        Ok(f) => BufReader::new(f),
+        Err(_) => return None
+    };
+    let mut numbers = Vec::new();
+    for line in f.lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(_) => return None
+        };
+        for word in line.split_whitespace() {
+            match word.parse::<f64>() {
+                Ok(x) => numbers.push(x),
+                Err(_) => return None
+            }
+        }
+    }
+    if numbers.len() < 2 {
+        return None;
+    }
+    let nx = numbers[0] as usize;
+    let ny = numbers[1] as usize;
+    if numbers.len() != 2 + nx * ny * 3 {
+        return None;
+    }
+    let mut image = vec![vec![Vec3::zero(); nx]; ny];
+    for (k, pixel) in numbers[2..].chunks(3).enumerate() {
+        let j = ny - 1 - k / nx;
+        let i = k % nx;
+        image[j][i] = Vec3::new(pixel[0], pixel[1], pixel[2]);
+    }
+    Some(image)
+}
+
+pub fn write_linear_rgb(image: &[Vec<Vec3>], file_name: &str) {
+    let mut f = BufWriter::new(File::create(file_name).unwrap());
+    f.write_fmt(format_args!("{} {}\n", image[0].len(), image.len())).unwrap();
+    for row in image.iter().rev() {
+        for p in row.iter() {
+            f.write_fmt(format_args!("{} {} {}\n", p[0], p[1], p[2])).unwrap();
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// edge-avoiding a-trous wavelet filtering, after Dammertz et al. 2010.
+//
+// The noisy image is repeatedly blurred with a 5x5 B3-spline kernel
+// whose taps spread twice as far apart every iteration. Each tap is
+// weighted down by how different the two pixels' colors, normals,
+// albedos and depths are, so the blur stops at edges the feature
+// buffers can see. Texture is kept out of the way by filtering the
+// image divided by albedo, and multiplying it back in at the end.
+
+#[derive(Clone, Copy, Debug)]
+pub struct DenoiseSettings {
+    pub iterations: usize,
+    pub sigma_color: f64,
+    pub sigma_normal: f64,
+    pub sigma_albedo: f64,
+    // relative to the depth of the center pixel
+    pub sigma_depth: f64
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> DenoiseSettings {
+        DenoiseSettings {
+            iterations: 5,
+            sigma_color: 0.5,
+            sigma_normal: 0.3,
+            sigma_albedo: 0.1,
+            sigma_depth: 0.05
+        }
+    }
+}
+
+pub struct Features<'a> {
+    pub albedo: &'a [Vec<Vec3>],
+    pub normal: &'a [Vec<Vec3>],
+    pub depth: &'a [Vec<Vec3>]
+}
+
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// albedos this dark can't be divided by
+const MIN_ALBEDO: f64 = 0.01;
+
+fn demodulate(c: f64, albedo: f64) -> f64 {
+    if albedo > MIN_ALBEDO { c / albedo } else { c }
+}
+
+fn remodulate(c: f64, albedo: f64) -> f64 {
+    if albedo > MIN_ALBEDO { c * albedo } else { c }
+}
+
+fn per_channel<F: Fn(f64, f64) -> f64>(a: &Vec3, b: &Vec3, f: F) -> Vec3 {
+    Vec3::new(f(a[0], b[0]), f(a[1], b[1]), f(a[2], b[2]))
+}
+
+pub fn denoise(image: &[Vec<Vec3>], features: &Features, settings: &DenoiseSettings) -> Vec<Vec<Vec3>> {
+    let ny = image.len();
+    let nx = image[0].len();
+    let mut current: Vec<Vec<Vec3>> = image.iter().zip(features.albedo.iter()).map(|(row, albedo_row)| {
+        row.iter().zip(albedo_row.iter()).map(|(c, a)| per_channel(c, a, demodulate)).collect()
+    }).collect();
+
+    let mut sigma_color = settings.sigma_color;
+    for iteration in 0..settings.iterations {
+        let step = 1 << iteration;
+        let mut next = vec![vec![Vec3::zero(); nx]; ny];
+        for j in 0..ny {
+            for i in 0..nx {
+                let c_p = current[j][i];
+                let n_p = features.normal[j][i];
+                let a_p = features.albedo[j][i];
+                let d_p = features.depth[j][i][0];
+                let mut sum = Vec3::zero();
+                let mut weight_sum = 0.0;
+                for (dy, ky) in KERNEL.iter().enumerate() {
+                    let y = j as isize + (dy as isize - 2) * step;
+                    if y < 0 || y >= ny as isize {
+                        continue;
+                    }
+                    let y = y as usize;
+                    for (dx, kx) in KERNEL.iter().enumerate() {
+                        let x = i as isize + (dx as isize - 2) * step;
+                        if x < 0 || x >= nx as isize {
+                            continue;
+                        }
+                        let x = x as usize;
+                        let c_q = current[y][x];
+                        // colors are compared relative to how bright they
+                        // are, so the same sigma works for dark and bright
+                        // parts of the image
+                        let color_scale = (c_p.length_squared() + c_q.length_squared()).max(1e-4);
+                        let w_color = (-(c_p - c_q).length_squared() /
+                                       (sigma_color * sigma_color * color_scale)).exp();
+                        let w_normal = (-(n_p - features.normal[y][x]).length_squared() /
+                                        (settings.sigma_normal * settings.sigma_normal)).exp();
+                        let w_albedo = (-(a_p - features.albedo[y][x]).length_squared() /
+                                        (settings.sigma_albedo * settings.sigma_albedo)).exp();
+                        let depth_scale = settings.sigma_depth * d_p.max(1e-4);
+                        let w_depth = (-(d_p - features.depth[y][x][0]).abs() / depth_scale).exp();
+                        let w = kx * ky * w_color * w_normal * w_albedo * w_depth;
+                        sum = sum + c_q * w;
+                        weight_sum += w;
+                    }
+                }
+                // the center tap always has weight, so this is never zero
+                next[j][i] = sum / weight_sum;
+            }
+        }
+        current = next;
+        // finer detail has been filtered out already, so later
+        // iterations compare colors more strictly
+        sigma_color *= 0.5;
+    }
+
+    current.iter().zip(features.albedo.iter()).map(|(row, albedo_row)| {
+        row.iter().zip(albedo_row.iter()).map(|(c, a)| per_channel(c, a, remodulate)).collect()
+    }).collect()
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn it_works() {
+    use rand;
+    use rand::Rng;
+    let mut rng = rand::thread_rng();
+
+    // two flat regions, separated by an edge in the normals: noise goes
+    // away, the edge stays.
+    let nx = 32;
+    let ny = 16;
+    let mut image = vec![vec![Vec3::zero(); nx]; ny];
+    let mut normal = vec![vec![Vec3::zero(); nx]; ny];
+    let albedo = vec![vec![Vec3::new(0.5, 0.5, 0.5); nx]; ny];
+    let depth = vec![vec![Vec3::new(1.0, 1.0, 1.0); nx]; ny];
+    for j in 0..ny {
+        for i in 0..nx {
+            let (value, n) = if i < nx / 2 {
+                (0.2, Vec3::new(0.0, 0.0, 1.0))
+            } else {
+                (0.8, Vec3::new(1.0, 0.0, 0.0))
+            };
+            let noisy = value * (0.5 + rng.gen::<f64>());
+            image[j][i] = Vec3::new(noisy, noisy, noisy);
+            normal[j][i] = n;
+        }
+    }
+    let features = Features { albedo: &albedo, normal: &normal, depth: &depth };
+    let result = denoise(&image, &features, &DenoiseSettings::default());
+
+    let error = |img: &[Vec<Vec3>]| {
+        let mut e = 0.0;
+        for row in img.iter() {
+            for (i, p) in row.iter().enumerate() {
+                let expected = if i < nx / 2 { 0.2 } else { 0.8 };
+                e += (p[0] - expected).abs();
+            }
+        }
+        e / (nx * ny) as f64
+    };
+    assert!(error(&result) < 0.5 * error(&image));
+    // nothing bled across the edge
+    let column_average = |i: usize| (0..ny).map(|j| result[j][i][0]).sum::<f64>() / ny as f64;
+    assert!(column_average(nx / 2 - 1) < 0.3);
+    assert!(column_average(nx / 2) > 0.7);
+}
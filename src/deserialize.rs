@@ -1,26 +1,56 @@
+use aabb::AABB;
+use ambient_occlusion::*;
 use background::*;
+use bdpt::*;
+use bsdf_tracer::*;
 use camera::*;
+use csg::*;
+use cuboid::*;
+use cylinder::*;
+use delta_light::*;
 use dielectric::*;
+use disc::DiscSurface;
+use direct_lighting::*;
 use emitter::*;
+use environment_map::*;
 use hitable::*;
 use hitable_list::*;
+use instance::*;
+use integrator::*;
 use lambertian::*;
+use light::*;
 use material::*;
+use matrix::*;
+use medium::*;
 use metal::*;
 use mixture::*;
+use mlt::*;
+use motion::*;
+use path_tracer::*;
+use photon_mapping::*;
+use plane::PlaneSurface;
 // use phong::*;
 use rectangle::*;
+use render_settings::*;
 use scene::*;
+use sdf::*;
 use serde_json::*;
+use sky_model::*;
+use spectrum::*;
+use torus::*;
 use sphere::*;
 use triangle_mesh::*;
 use vector::*;
+use volume::*;
 use ward::*;
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufReader;
 use std::iter::*;
 use std::option::*;
+use std::sync::Arc;
 use std::vec::*;
 
 use serde_json;
@@ -53,6 +83,45 @@ pub fn deserialize_vec3(v: &Value) -> Option<Vec3>
     }
 }
 
+// a number (the same at every wavelength), an RGB triple, a list of
+// [wavelength in nm, value] pairs, or a blackbody given as
+// {"temperature": kelvin, "scale": s}, scaled to luminance s (default 1)
+pub fn deserialize_spectrum(v: &Value) -> Option<Spectrum>
+{
+    match v {
+        &Value::Number(ref n) => n.as_f64().map(Spectrum::constant),
+        &Value::Array(ref ns) => {
+            if ns.iter().all(|x| x.is_number()) {
+                return deserialize_vec3(v).map(|rgb| Spectrum::from_rgb(&rgb));
+            }
+            let samples = Vec::from_iter(ns.iter().map(|x| match x {
+                &Value::Array(ref pair) if pair.len() == 2 => {
+                    pair[0].as_f64().and_then(|l| pair[1].as_f64().map(|v| (l, v)))
+                },
+                _ => None
+            }));
+            if samples.is_empty() || samples.iter().any(|x| x.is_none()) {
+                None
+            } else {
+                Some(Spectrum::from_samples(samples.into_iter().map(|x| x.unwrap()).collect()))
+            }
+        },
+        &Value::Object(ref m) => {
+            let temperature = m.get("temperature").and_then(|t| t.as_f64());
+            let scale = match m.get("scale") {
+                None => Some(1.0),
+                Some(s) => s.as_f64()
+            };
+            if temperature.is_none() || scale.is_none() {
+                None
+            } else {
+                Some(Spectrum::blackbody(temperature.unwrap()).scale(scale.unwrap()))
+            }
+        },
+        _ => None
+    }
+}
+
 pub fn deserialize_camera(v: &Value) -> Option<Camera>
 {
     match v {
@@ -64,29 +133,66 @@ pub fn deserialize_camera(v: &Value) -> Option<Camera>
             let aspect     = m["aspect"].as_f64();
             let aperture   = m["aperture"].as_f64();
             let focus_dist = m["focus_dist"].as_f64();
+            // no motion blur unless the shutter stays open
+            let shutter_open = match m.get("shutter_open") {
+                None => Some(0.0),
+                Some(t) => t.as_f64()
+            };
+            let shutter_close = match m.get("shutter_close") {
+                None => shutter_open,
+                Some(t) => t.as_f64()
+            };
 
             if look_from.is_none() || look_at.is_none() ||
                 vup.is_none() || vfov.is_none() ||
                 aspect.is_none() || aperture.is_none() ||
-                focus_dist.is_none() {
+                focus_dist.is_none() || shutter_open.is_none() ||
+                shutter_close.is_none() {
                 None
             } else {
                     Some(Camera::new(&look_from.unwrap(), &look_at.unwrap(), &vup.unwrap(),
                                      vfov.unwrap(), aspect.unwrap(),
-                                     aperture.unwrap(), focus_dist.unwrap()))
+                                     aperture.unwrap(), focus_dist.unwrap())
+                         .with_shutter(shutter_open.unwrap(), shutter_close.unwrap()))
             }
         },
         _ => None
     }
 }
 
+// a number, a list of [wavelength in nm, index] pairs, or coefficients
+// for Cauchy's equation, {"cauchy": {"a": a, "b": b}}, or Sellmeier's,
+// {"sellmeier": {"b": [b1, b2, b3], "c": [c1, c2, c3]}}
+pub fn deserialize_refraction_index(v: &Value) -> Option<Spectrum>
+{
+    if let Some(c) = v.get("cauchy") {
+        let a = c["a"].as_f64();
+        let b = c["b"].as_f64();
+        return if a.is_none() || b.is_none() {
+            None
+        } else {
+            Some(cauchy(a.unwrap(), b.unwrap()))
+        };
+    }
+    if let Some(s) = v.get("sellmeier") {
+        let b = deserialize_vec3(&s["b"]);
+        let c = deserialize_vec3(&s["c"]);
+        return if b.is_none() || c.is_none() {
+            None
+        } else {
+            let (b, c) = (b.unwrap(), c.unwrap());
+            Some(sellmeier(&[b.x(), b.y(), b.z()], &[c.x(), c.y(), c.z()]))
+        };
+    }
+    deserialize_spectrum(v).filter(|n| n.samples.is_some() || n.is_constant())
+}
+
 pub fn deserialize_dielectric(v: &Value) -> Option<Box<Material>>
 {
     match v {
         &Value::Object(ref m) => {
-            m["refraction_index"]
-                .as_f64()
-                .map(|ri| Dielectric::new(ri))
+            deserialize_refraction_index(&m["refraction_index"])
+                .map(Dielectric::with_spectrum)
         },
         _ => None
     }
@@ -96,8 +202,8 @@ pub fn deserialize_emitter(v: &Value) -> Option<Box<Material>>
 {
     match v {
         &Value::Object(ref m) => {
-            deserialize_vec3(&m["emission"])
-                .map(|a| Emitter::new(&a))
+            deserialize_spectrum(&m["emission"])
+                .map(Emitter::with_spectrum)
         },
         _ => None
     }
@@ -175,13 +281,24 @@ pub fn deserialize_sphere(v: &Value) -> Option<Box<Hitable + Send + Sync>>
             let center = deserialize_vec3(&m["center"]);
             let radius = m["radius"].as_f64();
             let material = deserialize_material(&m["material"]);
+            // where the center is at time 1, for spheres that move
+            let center1 = match m.get("center1") {
+                None => center,
+                Some(c) => deserialize_vec3(c)
+            };
             if center.is_none() || radius.is_none() ||
-                material.is_none() {
+                material.is_none() || center1.is_none() {
                     None
                 } else {
-                    Some(Box::new(Sphere::new(center.unwrap(),
-                                              radius.unwrap(),
-                                              material.unwrap())))
+                    let center = center.unwrap();
+                    let sphere = Box::new(Sphere::new(center,
+                                                      radius.unwrap(),
+                                                      material.unwrap()));
+                    if center1.unwrap() == center {
+                        Some(sphere)
+                    } else {
+                        Some(Box::new(sliding(sphere, &(center1.unwrap() - center))))
+                    }
                 }
         },
         _ => None
@@ -212,6 +329,265 @@ pub fn deserialize_rectangle(v: &Value) -> Option<Box<Hitable + Send + Sync>>
 }
 
 
+// an infinite plane through "point", facing "normal"
+pub fn deserialize_plane(v: &Value) -> Option<Box<Hitable + Send + Sync>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let point    = deserialize_vec3(&m["point"]);
+            let normal   = deserialize_vec3(&m["normal"]);
+            let material = deserialize_material(&m["material"]);
+            if point.is_none() || normal.is_none() || material.is_none() {
+                None
+            } else {
+                Some(Box::new(PlaneSurface::new(&point.unwrap(), &normal.unwrap(), material.unwrap())))
+            }
+        },
+        _ => None
+    }
+}
+
+pub fn deserialize_disc(v: &Value) -> Option<Box<Hitable + Send + Sync>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let center   = deserialize_vec3(&m["center"]);
+            let normal   = deserialize_vec3(&m["normal"]);
+            let radius   = m["radius"].as_f64();
+            let material = deserialize_material(&m["material"]);
+            if center.is_none() || normal.is_none() ||
+                radius.is_none() || material.is_none() {
+                    None
+                } else {
+                    Some(Box::new(DiscSurface::new(
+                        center.unwrap(), normal.unwrap(), radius.unwrap(), material.unwrap())))
+                }
+        },
+        _ => None
+    }
+}
+
+// cylinders from "base" to "top", and cones from "base" to "apex"; both
+// capped unless "capped" is false
+pub fn deserialize_cylinder(v: &Value, cone: bool) -> Option<Box<Hitable + Send + Sync>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let base     = deserialize_vec3(&m["base"]);
+            let top      = deserialize_vec3(&m[if cone { "apex" } else { "top" }]);
+            let radius   = m["radius"].as_f64();
+            let material = deserialize_material(&m["material"]);
+            let capped   = match m.get("capped") {
+                None => Some(true),
+                Some(c) => c.as_bool()
+            };
+            if base.is_none() || top.is_none() || radius.is_none() ||
+                material.is_none() || capped.is_none() {
+                    None
+                } else if cone {
+                    Some(Box::new(Cone::new(base.unwrap(), top.unwrap(), radius.unwrap(),
+                                            capped.unwrap(), material.unwrap())))
+                } else {
+                    Some(Box::new(Cylinder::new(base.unwrap(), top.unwrap(), radius.unwrap(),
+                                                capped.unwrap(), material.unwrap())))
+                }
+        },
+        _ => None
+    }
+}
+
+// from "min" to "max", turned by an optional "transform"
+pub fn deserialize_cuboid(v: &Value) -> Option<Box<Hitable + Send + Sync>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let min       = deserialize_vec3(&m["min"]);
+            let max       = deserialize_vec3(&m["max"]);
+            let material  = deserialize_material(&m["material"]);
+            let transform = match m.get("transform") {
+                None => Some(Matrix::identity()),
+                Some(t) => deserialize_matrix(t)
+            };
+            if min.is_none() || max.is_none() || material.is_none() || transform.is_none() {
+                None
+            } else {
+                Cuboid::oriented(min.unwrap(), max.unwrap(), &transform.unwrap(), material.unwrap())
+                    .map(|c| Box::new(c) as Box<Hitable + Send + Sync>)
+            }
+        },
+        _ => None
+    }
+}
+
+pub fn deserialize_torus(v: &Value) -> Option<Box<Hitable + Send + Sync>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let center       = deserialize_vec3(&m["center"]);
+            let axis         = match m.get("axis") {
+                None => Some(Vec3::new(0.0, 1.0, 0.0)),
+                Some(a) => deserialize_vec3(a)
+            };
+            let major_radius = m["major_radius"].as_f64();
+            let minor_radius = m["minor_radius"].as_f64();
+            let material     = deserialize_material(&m["material"]);
+            if center.is_none() || axis.is_none() || major_radius.is_none() ||
+                minor_radius.is_none() || material.is_none() {
+                    None
+                } else {
+                    Some(Box::new(Torus::new(center.unwrap(), axis.unwrap(), major_radius.unwrap(),
+                                             minor_radius.unwrap(), material.unwrap())))
+                }
+        },
+        _ => None
+    }
+}
+
+// the distance functions of "sdf" objects: each is an object with one
+// key naming the shape or operation
+pub fn deserialize_sdf(v: &Value) -> Option<Sdf>
+{
+    let list = |v: &Value| match v {
+        &Value::Array(ref a) => {
+            let sdfs: Vec<Option<Sdf>> = a.iter().map(deserialize_sdf).collect();
+            if sdfs.is_empty() || sdfs.iter().any(|s| s.is_none()) {
+                None
+            } else {
+                Some(sdfs.into_iter().map(|s| s.unwrap()).collect::<Vec<Sdf>>())
+            }
+        },
+        _ => None
+    };
+    let child = |m: &serde_json::Map<String, Value>| deserialize_sdf(&m["sdf"]).map(Box::new);
+    match v {
+        &Value::Object(ref m) => {
+            if let Some(&Value::Object(ref s)) = m.get("sphere") {
+                let center = deserialize_vec3(&s["center"]);
+                let radius = s["radius"].as_f64();
+                if center.is_none() || radius.is_none() {
+                    None
+                } else {
+                    Some(Sdf::Sphere { center: center.unwrap(), radius: radius.unwrap() })
+                }
+            } else if let Some(&Value::Object(ref b)) = m.get("box") {
+                let center   = deserialize_vec3(&b["center"]);
+                let size     = deserialize_vec3(&b["size"]);
+                let rounding = match b.get("rounding") {
+                    None => Some(0.0),
+                    Some(r) => r.as_f64()
+                };
+                if center.is_none() || size.is_none() || rounding.is_none() {
+                    None
+                } else {
+                    Some(Sdf::Box { center: center.unwrap(), half_size: size.unwrap() / 2.0,
+                                    rounding: rounding.unwrap() })
+                }
+            } else if let Some(&Value::Object(ref t)) = m.get("torus") {
+                let center       = deserialize_vec3(&t["center"]);
+                let major_radius = t["major_radius"].as_f64();
+                let minor_radius = t["minor_radius"].as_f64();
+                if center.is_none() || major_radius.is_none() || minor_radius.is_none() {
+                    None
+                } else {
+                    Some(Sdf::Torus { center: center.unwrap(), major_radius: major_radius.unwrap(),
+                                      minor_radius: minor_radius.unwrap() })
+                }
+            } else if let Some(&Value::Object(ref c)) = m.get("cylinder") {
+                let center = deserialize_vec3(&c["center"]);
+                let radius = c["radius"].as_f64();
+                let height = c["height"].as_f64();
+                if center.is_none() || radius.is_none() || height.is_none() {
+                    None
+                } else {
+                    Some(Sdf::Cylinder { center: center.unwrap(), radius: radius.unwrap(),
+                                         height: height.unwrap() })
+                }
+            } else if let Some(&Value::Object(ref p)) = m.get("plane") {
+                let point  = deserialize_vec3(&p["point"]);
+                let normal = deserialize_vec3(&p["normal"]);
+                if point.is_none() || normal.is_none() {
+                    None
+                } else {
+                    Some(Sdf::Plane { point: point.unwrap(), normal: normal.unwrap() })
+                }
+            } else if let Some(sdfs) = m.get("union") {
+                list(sdfs).map(Sdf::Union)
+            } else if let Some(sdfs) = m.get("intersection") {
+                list(sdfs).map(Sdf::Intersection)
+            } else if let Some(sdfs) = m.get("difference") {
+                match list(sdfs) {
+                    Some(ref sdfs) if sdfs.len() == 2 =>
+                        Some(Sdf::Difference(Box::new(sdfs[0].clone()), Box::new(sdfs[1].clone()))),
+                    _ => None
+                }
+            } else if let Some(&Value::Object(ref u)) = m.get("smooth_union") {
+                let sdfs = list(&u["of"]);
+                let k    = u["k"].as_f64();
+                if sdfs.is_none() || k.is_none() {
+                    None
+                } else {
+                    Some(Sdf::SmoothUnion(sdfs.unwrap(), k.unwrap()))
+                }
+            } else if let Some(&Value::Object(ref t)) = m.get("translate") {
+                let offset = deserialize_vec3(&t["offset"]);
+                let sdf    = child(t);
+                if offset.is_none() || sdf.is_none() {
+                    None
+                } else {
+                    Some(Sdf::Translate(offset.unwrap(), sdf.unwrap()))
+                }
+            } else if let Some(&Value::Object(ref r)) = m.get("repeat") {
+                let period = deserialize_vec3(&r["period"]);
+                let sdf    = child(r);
+                if period.is_none() || sdf.is_none() {
+                    None
+                } else {
+                    Some(Sdf::Repeat(period.unwrap(), sdf.unwrap()))
+                }
+            } else if let Some(&Value::Object(ref t)) = m.get("twist") {
+                // "rate" in degrees per unit up y
+                let rate = t["rate"].as_f64();
+                let sdf  = child(t);
+                if rate.is_none() || sdf.is_none() {
+                    None
+                } else {
+                    Some(Sdf::Twist(rate.unwrap().to_radians(), sdf.unwrap()))
+                }
+            } else {
+                None
+            }
+        },
+        _ => None
+    }
+}
+
+// a "distance" function, whose surface must lie between "min" and "max";
+// "step" below 1 (the default) shortens the steps along rays, for
+// functions that overstep, like twists
+pub fn deserialize_sdf_surface(v: &Value) -> Option<Box<Hitable + Send + Sync>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let sdf      = deserialize_sdf(&m["distance"]);
+            let min      = deserialize_vec3(&m["min"]);
+            let max      = deserialize_vec3(&m["max"]);
+            let material = deserialize_material(&m["material"]);
+            let step     = match m.get("step") {
+                None => Some(1.0),
+                Some(s) => s.as_f64()
+            };
+            if sdf.is_none() || min.is_none() || max.is_none() ||
+                material.is_none() || step.is_none() {
+                    None
+                } else {
+                    Some(Box::new(SdfSurface::new(sdf.unwrap(), AABB::new(min.unwrap(), max.unwrap()),
+                                                  material.unwrap(), step.unwrap())))
+                }
+        },
+        _ => None
+    }
+}
+
 pub fn deserialize_triangle_mesh_data(v: &Value) ->
     Option<(Vec<Vec3>, Vec<usize>)>
 {
@@ -279,12 +655,236 @@ pub fn deserialize_triangle_mesh(v: &Value) -> Option<Box<Hitable + Send + Sync>
     }
 }
 
+pub fn deserialize_volume(v: &Value) -> Option<Box<Hitable + Send + Sync>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let boundary = deserialize_hitable(&m["boundary"]);
+            let medium = deserialize_medium(&m["medium"]);
+            if boundary.is_none() || medium.is_none() {
+                None
+            } else {
+                Some(Box::new(Volume::new(boundary.unwrap(),
+                                          medium.unwrap())))
+            }
+        },
+        _ => None
+    }
+}
+
+// moving by "offset", or from "transform" (the identity by default) at
+// time 0 to "transform1" at time 1
+pub fn deserialize_moving(v: &Value) -> Option<Box<Hitable + Send + Sync>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let hitable = deserialize_hitable(&m["hitable"]);
+            if let Some(offset) = m.get("offset") {
+                let offset = deserialize_vec3(offset);
+                return if hitable.is_none() || offset.is_none() {
+                    None
+                } else {
+                    Some(Box::new(sliding(hitable.unwrap(), &offset.unwrap())))
+                };
+            }
+            let start = match m.get("transform") {
+                None => Some(Matrix::identity()),
+                Some(t) => deserialize_matrix(t)
+            };
+            let end = deserialize_matrix(&m["transform1"]);
+            if hitable.is_none() || start.is_none() || end.is_none() {
+                None
+            } else {
+                moving(hitable.unwrap(), &start.unwrap(), &end.unwrap())
+                    .map(|i| Box::new(i) as Box<Hitable + Send + Sync>)
+            }
+        },
+        _ => None
+    }
+}
+
+// {"a": ..., "b": ...}, two closed objects
+pub fn deserialize_csg(v: &Value, operation: CsgOperation) -> Option<Box<Hitable + Send + Sync>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let a = deserialize_hitable(&m["a"]);
+            let b = deserialize_hitable(&m["b"]);
+            if a.is_none() || b.is_none() {
+                None
+            } else {
+                Some(Box::new(Csg::new(operation, a.unwrap(), b.unwrap())))
+            }
+        },
+        _ => None
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// instancing. Scenes name the objects they share in a top-level
+// "definitions" object, which is read before the objects that place
+// them, and kept here in the meantime.
+
+thread_local! {
+    static DEFINITIONS: RefCell<HashMap<String, Arc<Hitable + Send + Sync>>> = RefCell::new(HashMap::new());
+}
+
+pub fn deserialize_definitions(v: &Value) -> Option<HashMap<String, Arc<Hitable + Send + Sync>>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let mut result = HashMap::new();
+            for (name, definition) in m.iter() {
+                let hitable = deserialize_hitable(definition);
+                if hitable.is_none() {
+                    return None;
+                }
+                result.insert(name.clone(), Arc::from(hitable.unwrap()));
+            }
+            Some(result)
+        },
+        _ => None
+    }
+}
+
+// a transform is a matrix, as 16 numbers row by row or 4 rows of 4;
+// one step, like {"translate": [x, y, z]}; or a list of steps, applied
+// in order
+pub fn deserialize_matrix(v: &Value) -> Option<Matrix>
+{
+    match v {
+        &Value::Array(ref ns) if ns.iter().all(|x| x.is_object()) => {
+            let mut result = Matrix::identity();
+            for step in ns.iter() {
+                let m = deserialize_transform_step(step);
+                if m.is_none() {
+                    return None;
+                }
+                result = m.unwrap() * result;
+            }
+            Some(result)
+        },
+        &Value::Array(ref ns) if ns.len() == 16 => {
+            let numbers: Vec<Option<f64>> = ns.iter().map(|x| x.as_f64()).collect();
+            if numbers.iter().any(|x| x.is_none()) {
+                None
+            } else {
+                let mut m = [[0.0; 4]; 4];
+                for (i, x) in numbers.iter().enumerate() {
+                    m[i / 4][i % 4] = x.unwrap();
+                }
+                Some(Matrix::new(m))
+            }
+        },
+        &Value::Array(_) => serde_json::from_value(v.clone()).ok(),
+        &Value::Object(_) => deserialize_transform_step(v),
+        _ => None
+    }
+}
+
+// {"translate": [x, y, z]}, {"scale": s or [x, y, z]},
+// {"rotate": {"axis": [x, y, z], "angle": degrees}},
+// {"look_at": {"look_from": ..., "look_at": ..., "vup": ...}}
+// or {"matrix": ...}
+pub fn deserialize_transform_step(v: &Value) -> Option<Matrix>
+{
+    match v {
+        &Value::Object(ref m) => {
+            if let Some(offset) = m.get("translate") {
+                deserialize_vec3(offset).map(|offset| Matrix::translate(&offset))
+            } else if let Some(factors) = m.get("scale") {
+                match factors.as_f64() {
+                    Some(s) => Some(Matrix::scale(&Vec3::new(s, s, s))),
+                    None => deserialize_vec3(factors).map(|factors| Matrix::scale(&factors))
+                }
+            } else if let Some(&Value::Object(ref r)) = m.get("rotate") {
+                let axis = deserialize_vec3(&r["axis"]);
+                let angle = r["angle"].as_f64();
+                if axis.is_none() || angle.is_none() {
+                    None
+                } else {
+                    Some(Matrix::rotate(&axis.unwrap(), angle.unwrap().to_radians()))
+                }
+            } else if let Some(&Value::Object(ref l)) = m.get("look_at") {
+                let look_from = deserialize_vec3(&l["look_from"]);
+                let look_at = deserialize_vec3(&l["look_at"]);
+                let vup = match l.get("vup") {
+                    None => Some(Vec3::new(0.0, 1.0, 0.0)),
+                    Some(v) => deserialize_vec3(v)
+                };
+                if look_from.is_none() || look_at.is_none() || vup.is_none() {
+                    None
+                } else {
+                    Some(Matrix::look_at(&look_from.unwrap(), &look_at.unwrap(), &vup.unwrap()))
+                }
+            } else if let Some(matrix) = m.get("matrix") {
+                deserialize_matrix(matrix)
+            } else {
+                None
+            }
+        },
+        _ => None
+    }
+}
+
+pub fn deserialize_keyframe(v: &Value) -> Option<(f64, Matrix)>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let time = m["time"].as_f64();
+            let transform = deserialize_matrix(&m["transform"]);
+            if time.is_none() || transform.is_none() {
+                None
+            } else {
+                Some((time.unwrap(), transform.unwrap()))
+            }
+        },
+        _ => None
+    }
+}
+
+pub fn deserialize_instance(v: &Value) -> Option<Box<Hitable + Send + Sync>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let hitable = m["definition"].as_str().and_then(|name| {
+                DEFINITIONS.with(|d| d.borrow().get(name).cloned())
+            });
+            let keyframes = match (m.get("transform"), m.get("keyframes")) {
+                (None, None) => Some(vec![(0.0, Matrix::identity())]),
+                (Some(t), None) => deserialize_matrix(t).map(|t| vec![(0.0, t)]),
+                (None, Some(&Value::Array(ref ks))) => {
+                    let mut ks = Vec::from_iter(ks.iter().map(deserialize_keyframe));
+                    if ks.iter().any(|k| k.is_none()) {
+                        None
+                    } else {
+                        Some(ks.drain(..).map(|k| k.unwrap()).collect())
+                    }
+                },
+                _ => None
+            };
+            // the definition's own, unless given
+            let material = match m.get("material") {
+                None => Some(None),
+                Some(v) => deserialize_material(v).map(Some)
+            };
+            if hitable.is_none() || keyframes.is_none() || material.is_none() {
+                None
+            } else {
+                Instance::keyframed(hitable.unwrap(), &keyframes.unwrap(), material.unwrap())
+                    .map(|i| Box::new(i) as Box<Hitable + Send + Sync>)
+            }
+        },
+        _ => None
+    }
+}
+
 pub fn deserialize_hitable_list(v: &Value) -> Option<Box<Hitable + Send + Sync>>
 {
     match v {
         &Value::Array(ref m) => {
             let mut objs = Vec::from_iter(m.iter().map(deserialize_hitable));
-            if objs.iter().any(|x| !x.is_none()) {
+            if objs.iter().any(|x| x.is_none()) {
                 None
             } else {
                 Some(Box::new(HitableList::new(
@@ -311,6 +911,10 @@ pub fn deserialize_background(v: &Value) -> Option<Box<Background + Send + Sync>
                 let name = class.unwrap();
                 if name == "constant" {
                     deserialize_constant_background(object)
+                } else if name == "environment_map" {
+                    deserialize_environment_map(object)
+                } else if name == "preetham" {
+                    deserialize_preetham_sky(object)
                 } else {
                     None
                 }
@@ -344,6 +948,61 @@ pub fn deserialize_constant_background(v: &Value) -> Option<Box<Background + Sen
     }
 }
 
+pub fn deserialize_preetham_sky(v: &Value) -> Option<Box<Background + Send + Sync>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let sun_direction = deserialize_vec3(&m["sun_direction"]);
+            let turbidity = match m.get("turbidity") {
+                None => Some(3.0),
+                Some(v) => v.as_f64()
+            };
+            let ground_albedo = match m.get("ground_albedo") {
+                None => Some(Vec3::new(0.3, 0.3, 0.3)),
+                Some(v) => deserialize_vec3(v)
+            };
+            let intensity = match m.get("intensity") {
+                None => Some(1.0),
+                Some(v) => v.as_f64()
+            };
+            if sun_direction.is_none() || turbidity.is_none() ||
+                ground_albedo.is_none() || intensity.is_none() ||
+                sun_direction.unwrap().length_squared() == 0.0 {
+                    None
+                } else {
+                    Some(Box::new(PreethamSky::new(&sun_direction.unwrap(), turbidity.unwrap(),
+                                                   &ground_albedo.unwrap(), intensity.unwrap())))
+                }
+        },
+        _ => None
+    }
+}
+
+// rotation is in degrees about +y
+pub fn deserialize_environment_map(v: &Value) -> Option<Box<Background + Send + Sync>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let file_name = m["file_name"].as_str();
+            let rotation = match m.get("rotation") {
+                None => Some(0.0),
+                Some(v) => v.as_f64()
+            };
+            let intensity = match m.get("intensity") {
+                None => Some(1.0),
+                Some(v) => v.as_f64()
+            };
+            if file_name.is_none() || rotation.is_none() || intensity.is_none() {
+                None
+            } else {
+                EnvironmentMap::load(file_name.unwrap(), rotation.unwrap(), intensity.unwrap())
+                    .map(|e| Box::new(e) as Box<Background + Send + Sync>)
+            }
+        },
+        _ => None
+    }
+}
+
 pub fn deserialize_material(v: &Value) -> Option<Box<Material>>
 {
     match v {
@@ -376,6 +1035,107 @@ pub fn deserialize_material(v: &Value) -> Option<Box<Material>>
     }
 }
 
+// the phase function parameters are optional: isotropic and white by default
+fn deserialize_phase_function(m: &serde_json::Map<String, Value>) -> Option<(f64, Vec3)>
+{
+    let g = m.get("g").map_or(Some(0.0), |x| x.as_f64());
+    let color = m.get("color").map_or(Some(Vec3::new(1.0, 1.0, 1.0)), |x| deserialize_vec3(x));
+    if g.is_none() || color.is_none() {
+        None
+    } else {
+        Some((g.unwrap(), color.unwrap()))
+    }
+}
+
+pub fn deserialize_homogeneous_medium(v: &Value) -> Option<Box<Medium>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let sigma_a = m["sigma_a"].as_f64();
+            let sigma_s = m["sigma_s"].as_f64();
+            let phase = deserialize_phase_function(m);
+            if sigma_a.is_none() || sigma_s.is_none() || phase.is_none() {
+                None
+            } else {
+                let (g, color) = phase.unwrap();
+                Some(Homogeneous::new(sigma_a.unwrap(), sigma_s.unwrap(), g, &color))
+            }
+        },
+        _ => None
+    }
+}
+
+pub fn deserialize_grid_medium(v: &Value) -> Option<Box<Medium>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let min = deserialize_vec3(&m["min"]);
+            let max = deserialize_vec3(&m["max"]);
+            let resolution = match &m["resolution"] {
+                &Value::Array(ref a) if a.len() == 3 => {
+                    let r = Vec::from_iter(a.iter().map(|x| x.as_u64()));
+                    if r.iter().any(|x| x.is_none() || *x == Some(0)) {
+                        None
+                    } else {
+                        Some([r[0].unwrap() as usize, r[1].unwrap() as usize, r[2].unwrap() as usize])
+                    }
+                }, &_ => None
+            };
+            let density = match &m["density"] {
+                &Value::Array(ref a) => {
+                    let mut d = Vec::from_iter(a.iter().map(|x| x.as_f64()));
+                    if d.iter().any(|x| x.is_none()) {
+                        None
+                    } else {
+                        Some(d.drain(..).map(|x| x.unwrap()).collect::<Vec<_>>())
+                    }
+                }, &_ => None
+            };
+            let sigma_a = m["sigma_a"].as_f64();
+            let sigma_s = m["sigma_s"].as_f64();
+            let phase = deserialize_phase_function(m);
+            if min.is_none() || max.is_none() || resolution.is_none() ||
+                density.is_none() || sigma_a.is_none() || sigma_s.is_none() ||
+                phase.is_none() {
+                    return None;
+                }
+            let resolution = resolution.unwrap();
+            let density = density.unwrap();
+            if density.len() != resolution[0] * resolution[1] * resolution[2] {
+                return None;
+            }
+            let (g, color) = phase.unwrap();
+            Some(Grid::new(AABB::new(min.unwrap(), max.unwrap()),
+                           resolution, density,
+                           sigma_a.unwrap(), sigma_s.unwrap(), g, &color))
+        },
+        _ => None
+    }
+}
+
+pub fn deserialize_medium(v: &Value) -> Option<Box<Medium>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let class = m["class"].as_str();
+            let object = &m["object"];
+            if class.is_none() {
+                None
+            } else {
+                let name = class.unwrap();
+                if name == "homogeneous" {
+                    deserialize_homogeneous_medium(object)
+                } else if name == "grid" {
+                    deserialize_grid_medium(object)
+                } else {
+                    None
+                }
+            }
+        },
+        _ => None
+    }
+}
+
 pub fn deserialize_hitable(v: &Value) -> Option<Box<Hitable + Send + Sync>>
 {
     match v {
@@ -390,10 +1150,36 @@ pub fn deserialize_hitable(v: &Value) -> Option<Box<Hitable + Send + Sync>>
                     deserialize_rectangle(object)
                 } else if name == "sphere" {
                     deserialize_sphere(object)
+                } else if name == "plane" {
+                    deserialize_plane(object)
+                } else if name == "disc" {
+                    deserialize_disc(object)
+                } else if name == "cylinder" {
+                    deserialize_cylinder(object, false)
+                } else if name == "cone" {
+                    deserialize_cylinder(object, true)
+                } else if name == "box" {
+                    deserialize_cuboid(object)
+                } else if name == "torus" {
+                    deserialize_torus(object)
+                } else if name == "sdf" {
+                    deserialize_sdf_surface(object)
                 } else if name == "hitable_list" {
                     deserialize_hitable_list(object)
                 } else if name == "triangle_mesh" {
                     deserialize_triangle_mesh(object)
+                } else if name == "volume" {
+                    deserialize_volume(object)
+                } else if name == "moving" {
+                    deserialize_moving(object)
+                } else if name == "instance" {
+                    deserialize_instance(object)
+                } else if name == "union" {
+                    deserialize_csg(object, CsgOperation::Union)
+                } else if name == "intersection" {
+                    deserialize_csg(object, CsgOperation::Intersection)
+                } else if name == "difference" {
+                    deserialize_csg(object, CsgOperation::Difference)
                 } else {
                     None
                 }
@@ -403,6 +1189,153 @@ pub fn deserialize_hitable(v: &Value) -> Option<Box<Hitable + Send + Sync>>
     }
 }
 
+//////////////////////////////////////////////////////////////////////////////
+
+pub fn deserialize_ambient_occlusion(v: &Value) -> Option<Box<Integrator>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            m["distance"]
+                .as_f64()
+                .map(|d| AmbientOcclusion::new(d))
+        },
+        _ => None
+    }
+}
+
+pub fn deserialize_direct_lighting(v: &Value) -> Option<Box<Integrator>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            m["max_specular_depth"]
+                .as_u64()
+                .map(|d| DirectLighting::new(d as usize))
+        },
+        _ => None
+    }
+}
+
+pub fn deserialize_photon_mapping(v: &Value) -> Option<Box<Integrator>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let photons = m.get("photons").map_or(Some(100000), |x| x.as_u64().map(|x| x as usize));
+            let radius = m.get("radius").map_or(Some(None), |x| x.as_f64().map(Some));
+            let alpha = m.get("alpha").map_or(Some(0.7), |x| x.as_f64());
+            let final_gather = m.get("final_gather").map_or(Some(16), |x| x.as_u64().map(|x| x as usize));
+            if photons.is_none() || radius.is_none() || alpha.is_none() || final_gather.is_none() {
+                None
+            } else {
+                Some(PhotonMapping::new(photons.unwrap(),
+                                        radius.unwrap(),
+                                        alpha.unwrap(),
+                                        final_gather.unwrap()))
+            }
+        },
+        _ => None
+    }
+}
+
+pub fn deserialize_mlt(v: &Value) -> Option<Box<Integrator>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let integrator = m.get("integrator").map_or(Some(PathTracer::new()), |x| deserialize_integrator(x));
+            let large_step_probability = m.get("large_step_probability").map_or(Some(0.3), |x| x.as_f64());
+            let sigma = m.get("sigma").map_or(Some(0.01), |x| x.as_f64());
+            let bootstrap_samples = m.get("bootstrap_samples").map_or(Some(None), |x| x.as_u64().map(|x| Some(x as usize)));
+            if integrator.is_none() || large_step_probability.is_none() ||
+                sigma.is_none() || bootstrap_samples.is_none() {
+                None
+            } else {
+                Some(Mlt::new(integrator.unwrap(),
+                              large_step_probability.unwrap(),
+                              sigma.unwrap(),
+                              bootstrap_samples.unwrap()))
+            }
+        },
+        _ => None
+    }
+}
+
+pub fn deserialize_integrator(v: &Value) -> Option<Box<Integrator>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let class = m["class"].as_str();
+            let object = &m["object"];
+            if class.is_none() {
+                None
+            } else {
+                let name = class.unwrap();
+                if name == "ambient_occlusion" {
+                    deserialize_ambient_occlusion(object)
+                } else if name == "direct_lighting" {
+                    deserialize_direct_lighting(object)
+                } else if name == "photon_mapping" {
+                    deserialize_photon_mapping(object)
+                } else if name == "mlt" {
+                    deserialize_mlt(object)
+                } else {
+                    None
+                }
+            }
+        },
+        &Value::String(ref m) => {
+            if m == &"path".to_string() {
+                Some(PathTracer::new())
+            } else if m == &"bsdf".to_string() {
+                Some(BsdfTracer::new())
+            } else if m == &"bdpt".to_string() {
+                Some(Bdpt::new())
+            } else if m == &"mlt".to_string() {
+                Some(Mlt::new(PathTracer::new(), 0.3, 0.01, None))
+            } else if m == &"photon_mapping".to_string() {
+                Some(PhotonMapping::new(100000, None, 0.7, 16))
+            } else if m == &"direct_lighting".to_string() {
+                Some(DirectLighting::new(5))
+            } else if m == &"ambient_occlusion".to_string() {
+                Some(AmbientOcclusion::new(1e20))
+            } else {
+                None
+            }
+        }
+        _ => None
+    }
+}
+
+// every field is optional and falls back to RenderSettings::default()
+pub fn deserialize_render_settings(v: &Value) -> Option<RenderSettings>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let mut result = RenderSettings::default();
+            if let Some(d) = m.get("min_depth") {
+                match d.as_u64() {
+                    Some(d) => result.min_depth = d as usize,
+                    None => return None
+                }
+            }
+            if let Some(d) = m.get("max_depth") {
+                match d.as_u64() {
+                    Some(d) => result.max_depth = d as usize,
+                    None => return None
+                }
+            }
+            if let Some(s) = m.get("spectral") {
+                match s.as_bool() {
+                    Some(s) => result.spectral = s,
+                    None => return None
+                }
+            }
+            Some(result)
+        },
+        _ => None
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
 pub fn deserialize_scene_object_list(v: &Value) -> Option<Vec<Box<Hitable + Send + Sync>>>
 {
     match v {
@@ -418,20 +1351,155 @@ pub fn deserialize_scene_object_list(v: &Value) -> Option<Vec<Box<Hitable + Send
     }
 }
 
+pub fn deserialize_point_light(v: &Value) -> Option<Box<Light>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let position = deserialize_vec3(&m["position"]);
+            let intensity = deserialize_vec3(&m["intensity"]);
+            if position.is_none() || intensity.is_none() {
+                None
+            } else {
+                Some(PointLight::new(&position.unwrap(), &intensity.unwrap()))
+            }
+        },
+        _ => None
+    }
+}
+
+// the angles are in degrees, and default to pbrt's
+pub fn deserialize_spot_light(v: &Value) -> Option<Box<Light>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let position = deserialize_vec3(&m["position"]);
+            let direction = deserialize_vec3(&m["direction"]);
+            let intensity = deserialize_vec3(&m["intensity"]);
+            let cone_angle = match m.get("cone_angle") {
+                None => Some(30.0),
+                Some(v) => v.as_f64()
+            };
+            let cone_delta = match m.get("cone_delta") {
+                None => Some(5.0),
+                Some(v) => v.as_f64()
+            };
+            if position.is_none() || direction.is_none() || intensity.is_none() ||
+                cone_angle.is_none() || cone_delta.is_none() ||
+                direction.unwrap().length_squared() == 0.0 {
+                    None
+                } else {
+                    Some(SpotLight::new(&position.unwrap(), &direction.unwrap(),
+                                        &intensity.unwrap(),
+                                        cone_angle.unwrap(), cone_delta.unwrap()))
+                }
+        },
+        _ => None
+    }
+}
+
+pub fn deserialize_directional_light(v: &Value) -> Option<Box<Light>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let direction = deserialize_vec3(&m["direction"]);
+            let irradiance = deserialize_vec3(&m["irradiance"]);
+            if direction.is_none() || irradiance.is_none() ||
+                direction.unwrap().length_squared() == 0.0 {
+                    None
+                } else {
+                    Some(DirectionalLight::new(&direction.unwrap(), &irradiance.unwrap()))
+                }
+        },
+        _ => None
+    }
+}
+
+pub fn deserialize_light(v: &Value) -> Option<Box<Light>>
+{
+    match v {
+        &Value::Object(ref m) => {
+            let class = m["class"].as_str();
+            let object = &m["object"];
+            if class.is_none() {
+                None
+            } else {
+                let name = class.unwrap();
+                if name == "point" {
+                    deserialize_point_light(object)
+                } else if name == "spot" {
+                    deserialize_spot_light(object)
+                } else if name == "directional" {
+                    deserialize_directional_light(object)
+                } else {
+                    None
+                }
+            }
+        },
+        _ => None
+    }
+}
+
+pub fn deserialize_scene_lights(v: &Value) -> Option<Vec<Box<Light>>>
+{
+    match v {
+        &Value::Array(ref m) => {
+            let mut lights = Vec::from_iter(m.iter().map(deserialize_light));
+            if lights.iter().any(|x| x.is_none()) {
+                None
+            } else {
+                Some(lights.drain(..).map(|x| x.unwrap()).collect())
+            }
+        },
+        _ => None
+    }
+}
+
 pub fn deserialize_scene(v: &Value) -> Option<Scene>
 {
     match v {
         &Value::Object(ref m) => {
+            let definitions = match m.get("definitions") {
+                None => Some(HashMap::new()),
+                Some(v) => deserialize_definitions(v)
+            };
+            if definitions.is_none() {
+                return None;
+            }
+            DEFINITIONS.with(|d| *d.borrow_mut() = definitions.unwrap());
             let list = deserialize_scene_object_list(&m["object_list"]);
+            DEFINITIONS.with(|d| d.borrow_mut().clear());
             let camera = deserialize_camera(&m["camera"]);
             let background = deserialize_background(&m["background"]);
+            // the integrator is optional, but if it's given it has to parse
+            let integrator = match m.get("integrator") {
+                None => Some(None),
+                Some(v) => deserialize_integrator(v).map(|i| Some(i))
+            };
+            let render_settings = match m.get("render_settings") {
+                None => Some(RenderSettings::default()),
+                Some(v) => deserialize_render_settings(v)
+            };
+            let medium = match m.get("medium") {
+                None => Some(None),
+                Some(v) => deserialize_medium(v).map(|m| Some(m))
+            };
+            let lights = match m.get("lights") {
+                None => Some(Vec::new()),
+                Some(v) => deserialize_scene_lights(v)
+            };
             if list.is_none() || camera.is_none() ||
-                background.is_none() {
+                background.is_none() || integrator.is_none() ||
+                render_settings.is_none() || medium.is_none() ||
+                lights.is_none() {
                     None
                 } else {
                     Some(Scene::new(&camera.unwrap(),
                                     background.unwrap(),
-                                    list.unwrap()))
+                                    list.unwrap(),
+                                    lights.unwrap(),
+                                    integrator.unwrap(),
+                                    render_settings.unwrap(),
+                                    medium.unwrap()))
                 }
         },
         _ => None
@@ -4,13 +4,38 @@ use vector;
 use ray::Ray;
 use hitable::*;
 use random::*;
+use spectrum;
+use spectrum::Spectrum;
 
 use std::fmt;
 use std::fmt::Debug;
 
+// the refraction index can vary with wavelength, given as a table or
+// by Cauchy's or Sellmeier's equation. Glass like that disperses light,
+// so paths through it follow a single wavelength, which spreads white
+// light into its colors. See spectrum::single_wavelength.
+
 #[derive(Debug)]
 pub struct Dielectric {
-    refraction_index: f64
+    refraction_index: Spectrum,
+    dispersive: bool
+}
+
+// n = a + b / lambda^2, with lambda in micrometers
+pub fn cauchy(a: f64, b: f64) -> Spectrum {
+    Spectrum::tabulate(|lambda| {
+        let l = lambda * 1e-3;
+        a + b / (l * l)
+    })
+}
+
+// n^2 = 1 + sum_i b_i lambda^2 / (lambda^2 - c_i), with lambda in
+// micrometers, as glass catalogs give it
+pub fn sellmeier(b: &[f64; 3], c: &[f64; 3]) -> Spectrum {
+    Spectrum::tabulate(|lambda| {
+        let l2 = (lambda * 1e-3).powi(2);
+        (1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>()).sqrt()
+    })
 }
 
 fn schlick(cosine: f64, ref_idx: f64) -> f64 {
@@ -36,33 +61,33 @@ impl Material for Dielectric {
     }
     
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Scatter {
+        let (refraction_index, w) = self.refraction_index();
         let dot = r_in.direction().dot(&rec.normal);
         let reflected = vector::reflect(&r_in.direction(), &rec.normal);
         let outward_normal;
         let ni_over_nt;
         let cosine;
         
-        let w = Vec3::new(1.0, 1.0, 1.0);
         if dot > 0.0 {
             outward_normal = -rec.normal;
-            ni_over_nt = self.refraction_index;
-            cosine = self.refraction_index * dot / r_in.direction().length();
+            ni_over_nt = refraction_index;
+            cosine = refraction_index * dot / r_in.direction().length();
         } else {
             outward_normal = rec.normal;
-            ni_over_nt = 1.0 / self.refraction_index;
+            ni_over_nt = 1.0 / refraction_index;
             cosine = -dot / r_in.direction().length();
         }
         match vector::refract(&r_in.direction(), &outward_normal, ni_over_nt) {
             Some(refracted) => {
-                let reflected_prob = schlick(cosine, self.refraction_index); 
+                let reflected_prob = schlick(cosine, refraction_index); 
                 if rand_double() < reflected_prob {
-                    Scatter::Bounce(w, Ray::new(rec.p, reflected))
+                    Scatter::Bounce(w, Ray::with_time(rec.p, reflected, r_in.time()))
                 } else {
-                    Scatter::Bounce(w, Ray::new(rec.p, refracted))
+                    Scatter::Bounce(w, Ray::with_time(rec.p, refracted, r_in.time()))
                 }
             },
             None => {
-                Scatter::Bounce(w, Ray::new(rec.p, reflected))
+                Scatter::Bounce(w, Ray::with_time(rec.p, reflected, r_in.time()))
             }
         }
     }
@@ -72,13 +97,93 @@ impl Material for Dielectric {
     }
 
     fn is_emitter(&self) -> bool { false }
+
+    fn emission(&self) -> Vec3 { Vec3::zero() }
 }
 
 
 impl Dielectric {
     pub fn new(refraction_index: f64) -> Box<Material> {
+        Dielectric::with_spectrum(Spectrum::constant(refraction_index))
+    }
+
+    pub fn with_spectrum(refraction_index: Spectrum) -> Box<Material> {
         Box::new(Dielectric {
+            dispersive: !refraction_index.is_constant(),
             refraction_index: refraction_index
         })
     }
+
+    // the index the current path sees, and the weight of seeing it
+    fn refraction_index(&self) -> (f64, Vec3) {
+        if self.dispersive {
+            let (lambda, weight) = spectrum::single_wavelength();
+            (self.refraction_index.value_at(lambda), weight)
+        } else {
+            (self.refraction_index.value_at(spectrum::LAMBDA_MIN), Vec3::new(1.0, 1.0, 1.0))
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn glass_disperses_light() {
+    use sampling;
+
+    // glass entered at 45 degrees: short wavelengths bend more
+    let glass = Dielectric::with_spectrum(Spectrum::from_samples(vec![(400.0, 1.6), (700.0, 1.5)]));
+    let ray = Ray::new(Vec3::new(-1.0, 1.0, 0.0), Vec3::new(1.0, -1.0, 0.0));
+    let hr = HitRecord::hit(1.0, Vec3::zero(), Vec3::new(0.0, 1.0, 0.0), &*glass);
+    // the sine of the refracted direction, in each channel
+    let refracted_sin = |wavelengths: Option<Vec3>| {
+        let mut sum = Vec3::zero();
+        let mut weights = Vec3::zero();
+        for _ in 0..3000 {
+            spectrum::begin_path(wavelengths);
+            let refracted = match glass.scatter(&ray, &hr) {
+                Scatter::Bounce(w, r) if r.direction().y() < 0.0 => Some((w, r)),
+                _ => None
+            };
+            let channels = spectrum::end_path(&Vec3::new(1.0, 1.0, 1.0));
+            if let Some((w, r)) = refracted {
+                assert!(w == Vec3::new(1.0, 1.0, 1.0));
+                sum = sum + channels * vector::unit_vector(&r.direction()).x();
+                weights = weights + channels;
+            }
+        }
+        sum / weights
+    };
+
+    // spectral paths follow their hero wavelength
+    let blue = refracted_sin(Some(Vec3::new(420.0, 533.3, 646.7)));
+    let red = refracted_sin(Some(Vec3::new(680.0, 453.3, 566.7)));
+    assert!((blue.x() - 0.5f64.sqrt() / (1.6 - 0.1 * 20.0 / 300.0)).abs() < 1e-8);
+    assert!((red.x() - 0.5f64.sqrt() / (1.6 - 0.1 * 280.0 / 300.0)).abs() < 1e-8);
+
+    // RGB paths, their channel's
+    let rgb = refracted_sin(None);
+    assert!(rgb.z() < rgb.y() && rgb.y() < rgb.x());
+
+    // events outside of paths weight themselves
+    let mut itor = (0..30000).map(|_| match glass.scatter(&ray, &hr) {
+        Scatter::Bounce(w, _) => w.x() + w.y() + w.z(),
+        _ => 0.0
+    });
+    assert!((sampling::avstdev(&mut itor).0 - 3.0).abs() < 1e-8);
+}
+
+#[test]
+fn glass_equations_give_catalog_indices() {
+    // N-BK7 at the helium d, hydrogen F and hydrogen C lines
+    let bk7 = sellmeier(&[1.03961212, 0.231792344, 1.01046945],
+                        &[0.00600069867, 0.0200179144, 103.560653]);
+    assert!((bk7.value_at(587.56) - 1.5168).abs() < 1e-4);
+    assert!((bk7.value_at(486.13) - 1.5224).abs() < 1e-4);
+    assert!((bk7.value_at(656.27) - 1.5143).abs() < 1e-4);
+
+    let glass = cauchy(1.5, 0.004);
+    assert!((glass.value_at(400.0) - 1.525).abs() < 1e-4);
+    assert!((glass.value_at(500.0) - 1.516).abs() < 1e-4);
+    assert!(!glass.is_constant());
 }
@@ -0,0 +1,59 @@
+use integrator::*;
+use material;
+use ray::Ray;
+use vector::Vec3;
+use vector;
+
+//////////////////////////////////////////////////////////////////////////////
+// direct lighting only: follows specular bounces until it reaches a
+// surface that can be importance sampled, then estimates the light
+// arriving there directly from emitters and the background.
+
+pub struct DirectLighting {
+    pub max_specular_depth: usize
+}
+
+impl Integrator for DirectLighting {
+    fn color(&self, ray: &Ray, context: &RenderContext) -> Vec3 {
+        let mut current_ray = *ray;
+        let mut current_attenuation = Vec3::new(1.0, 1.0, 1.0);
+
+        for _depth in 0..self.max_specular_depth {
+            match context.world.hit(&current_ray, 0.00001, 1e20) {
+                None => {
+                    let unit_direction = vector::unit_vector(&current_ray.direction());
+                    return context.background.get_background(&unit_direction) * current_attenuation;
+                },
+                Some(hr) => {
+                    if let Some(le) = emission(&hr) {
+                        return le * current_attenuation;
+                    }
+                    if hr.material.wants_importance_sampling() {
+                        return estimate_direct(context, &current_ray, &hr) * current_attenuation;
+                    }
+                    match hr.material.scatter(&current_ray, &hr) {
+                        material::Scatter::Bounce(next_attenuation, scattered) => {
+                            current_attenuation = current_attenuation * next_attenuation;
+                            current_ray = scattered;
+                        },
+                        material::Scatter::Emit(emission) => {
+                            return emission * current_attenuation;
+                        },
+                        material::Scatter::Absorb => {
+                            return Vec3::new(0.0, 0.0, 0.0)
+                        }
+                    }
+                }
+            }
+        }
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+}
+
+impl DirectLighting {
+    pub fn new(max_specular_depth: usize) -> Box<Integrator> {
+        Box::new(DirectLighting {
+            max_specular_depth: max_specular_depth
+        })
+    }
+}
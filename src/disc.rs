@@ -1,3 +1,7 @@
+use aabb::AABB;
+use area_light::*;
+use hitable::*;
+use material::*;
 use vector::Vec3;
 use vector;
 use plane::Plane;
@@ -31,8 +35,7 @@ pub enum Visibility {
 
 impl Disc {
     pub fn plane(&self) -> Plane {
-        Plane::new(vector::dot(&self.center, &self.normal),
-                   &self.normal)
+        Plane::from_point_and_normal(&self.center, &self.normal)
     }
 
     pub fn tangent_space(&self) -> (Vec3, Vec3) {
@@ -65,6 +68,12 @@ impl Disc {
         self.center + u_vec * (u * self.radius) + v_vec * (v * self.radius)
     }
     
+    pub fn bounding_box(&self) -> AABB {
+        let extent = |n: f64| self.radius * (1.0 - n * n).max(0.0).sqrt();
+        let e = Vec3::new(extent(self.normal.x()), extent(self.normal.y()), extent(self.normal.z()));
+        AABB::new(self.center - e, self.center + e)
+    }
+
     pub fn sphere(&self) -> SphereGeom {
         SphereGeom::new(self.center, self.radius)
     }
@@ -109,6 +118,60 @@ impl Disc {
     }
 }
 
+//////////////////////////////////////////////////////////////////////////////
+// a disc as an object in the scene, seen from both sides
+
+#[derive(Debug)]
+pub struct DiscSurface {
+    pub disc: Disc,
+    pub material: Box<Material>
+}
+
+impl DiscSurface {
+    pub fn new(center: Vec3, normal: Vec3, radius: f64, material: Box<Material>) -> DiscSurface {
+        DiscSurface {
+            disc: Disc::new(center, vector::unit_vector(&normal), radius),
+            material: material
+        }
+    }
+}
+
+impl Hitable for DiscSurface {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'a>> {
+        let disc = &self.disc;
+        let denom = r.direction().dot(&disc.normal);
+        if denom == 0.0 {
+            return None;
+        }
+        let t = (disc.center - r.origin()).dot(&disc.normal) / denom;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+        let p = r.point_at_parameter(t);
+        if (p - disc.center).length() > disc.radius {
+            None
+        } else {
+            Some(HitRecord::hit(t, p, disc.normal, &*self.material))
+        }
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.disc.bounding_box())
+    }
+
+    fn area_lights(&self) -> Vec<AreaLight> {
+        if self.material.is_emitter() {
+            vec![AreaLight::with_spectrum(LightShape::Disc {
+                center: self.disc.center,
+                normal: self.disc.normal,
+                radius: self.disc.radius
+            }, self.material.emission_spectrum())]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
 //////////////////////////////////////////////////////////////////////////////
 
 #[test]
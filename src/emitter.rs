@@ -3,6 +3,7 @@ use vector::Vec3;
 use vector;
 use ray::Ray;
 use hitable::*;
+use spectrum::Spectrum;
 
 use std::fmt;
 use std::fmt::Debug;
@@ -13,7 +14,7 @@ use sampling;
 
 #[derive(Debug)]
 pub struct Emitter {
-    pub emission: Vec3
+    pub emission: Spectrum
 }
 
 // isotropic emitter
@@ -35,7 +36,7 @@ impl Material for Emitter {
     
     fn scatter(&self, _ray_in: &Ray, _rec: &HitRecord) -> Scatter
     {
-        Scatter::Emit(self.emission)
+        Scatter::Emit(self.emission.values())
     }
 
     fn debug(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -43,12 +44,20 @@ impl Material for Emitter {
     }
 
     fn is_emitter(&self) -> bool { true }
+
+    fn emission(&self) -> Vec3 { self.emission.values() }
+
+    fn emission_spectrum(&self) -> Spectrum { self.emission.clone() }
 }
 
 impl Emitter {
     pub fn new(emission: &Vec3) -> Box<Material> {
+        Emitter::with_spectrum(Spectrum::from_rgb(emission))
+    }
+
+    pub fn with_spectrum(emission: Spectrum) -> Box<Material> {
         Box::new(Emitter {
-            emission: *emission
+            emission: emission
         })
     }
 }
@@ -0,0 +1,376 @@
+use background::Background;
+use random::*;
+use sampling;
+use sampling::Distribution2D;
+use vector::*;
+
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::Read;
+
+//////////////////////////////////////////////////////////////////////////////
+// image-based lighting from an equirectangular (latitude-longitude)
+// image. The top row looks straight up (+y), the middle of the image
+// looks along -z, and +x is three quarters of the way across.
+//
+// Pixels are looked up without filtering, so the image is piecewise
+// constant over the sphere, and sampling picks pixels in proportion to
+// luminance times solid angle, which makes sample_direction's density
+// exactly proportional to what it's sampling.
+
+pub struct EnvironmentMap {
+    pub width: usize,
+    pub height: usize,
+    // row by row, from the top
+    pub pixels: Vec<Vec3>,
+    // about +y, in radians
+    pub rotation: f64,
+    pub intensity: f64,
+    distribution: Distribution2D
+}
+
+impl EnvironmentMap {
+    pub fn new(width: usize, height: usize, pixels: Vec<Vec3>,
+               rotation: f64, intensity: f64) -> EnvironmentMap {
+        let weights: Vec<f64> = pixels.iter().enumerate().map(|(k, p)| {
+            let theta = PI * ((k / width) as f64 + 0.5) / height as f64;
+            luminance(p).max(0.0) * theta.sin()
+        }).collect();
+        let distribution = Distribution2D::new(&weights, width, height);
+        EnvironmentMap {
+            width: width,
+            height: height,
+            pixels: pixels,
+            rotation: rotation,
+            intensity: intensity,
+            distribution: distribution
+        }
+    }
+
+    // reads a Radiance .hdr or a .pfm file. rotation is in degrees.
+    pub fn load(file_name: &str, rotation: f64, intensity: f64) -> Option<EnvironmentMap> {
+        let (width, height, pixels) = read_image(file_name)?;
+        Some(EnvironmentMap::new(width, height, pixels, rotation.to_radians(), intensity))
+    }
+
+    // image coordinates in [0, 1]^2 of a unit vector
+    fn to_uv(&self, unit_direction: &Vec3) -> (f64, f64) {
+        let d = rotate(unit_direction, &Vec3::new(0.0, 1.0, 0.0), -self.rotation);
+        let u = 0.5 + d.x().atan2(-d.z()) / (2.0 * PI);
+        let v = (d.x() * d.x() + d.z() * d.z()).sqrt().atan2(d.y()) / PI;
+        (u, v)
+    }
+
+    fn direction_at(&self, u: f64, v: f64) -> Vec3 {
+        let phi = 2.0 * PI * (u - 0.5);
+        let theta = PI * v;
+        let d = Vec3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos());
+        rotate(&d, &Vec3::new(0.0, 1.0, 0.0), self.rotation)
+    }
+
+    fn pixel_at(&self, u: f64, v: f64) -> (usize, usize) {
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+        (i, j)
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn get_background(&self, unit_direction: &Vec3) -> Vec3 {
+        let (u, v) = self.to_uv(unit_direction);
+        let (i, j) = self.pixel_at(u, v);
+        self.pixels[j * self.width + i] * self.intensity
+    }
+
+    fn sample_direction(&self) -> (Vec3, f64) {
+        match self.distribution.sample() {
+            // a black image: nothing to importance-sample
+            None => (sampling::random_3d_direction(), 1.0 / (4.0 * PI)),
+            Some((i, j, pmf)) => {
+                let u = (i as f64 + rand_double()) / self.width as f64;
+                let v = (j as f64 + rand_double()) / self.height as f64;
+                let sin_theta = (PI * v).sin();
+                let pdf = if sin_theta <= 0.0 {
+                    0.0
+                } else {
+                    pmf * (self.width * self.height) as f64 / (2.0 * PI * PI * sin_theta)
+                };
+                (self.direction_at(u, v), pdf)
+            }
+        }
+    }
+
+    fn direction_pdf(&self, unit_direction: &Vec3) -> f64 {
+        if self.distribution.marginal.total <= 0.0 {
+            return 1.0 / (4.0 * PI);
+        }
+        let (u, v) = self.to_uv(unit_direction);
+        let (i, j) = self.pixel_at(u, v);
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 {
+            0.0
+        } else {
+            self.distribution.pmf(i, j) * (self.width * self.height) as f64 / (2.0 * PI * PI * sin_theta)
+        }
+    }
+
+    // exactly, from the solid angles of the pixels
+    fn average_luminance(&self) -> f64 {
+        let total: f64 = self.pixels.iter().enumerate().map(|(k, p)| {
+            let j = k / self.width;
+            let cos_top = (PI * j as f64 / self.height as f64).cos();
+            let cos_bottom = (PI * (j + 1) as f64 / self.height as f64).cos();
+            luminance(p) * (cos_top - cos_bottom) * 2.0 * PI / self.width as f64
+        }).sum();
+        total * self.intensity / (4.0 * PI)
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// reading high dynamic range images: width, height, and the pixels row
+// by row from the top.
+
+pub fn read_image(file_name: &str) -> Option<(usize, usize, Vec<Vec3>)> {
+    let mut bytes = Vec::new();
+    match File::open(file_name) {
+        Ok(mut f) => if f.read_to_end(&mut bytes).is_err() { return None; },
+        Err(_) => return None
+    }
+    let lower = file_name.to_lowercase();
+    if lower.ends_with(".pfm") {
+        read_pfm(&bytes)
+    } else if lower.ends_with(".hdr") {
+        read_hdr(&bytes)
+    } else {
+        None
+    }
+}
+
+// the next whitespace-separated word at bytes[*pos..], skipping #
+// comments; leaves pos at the whitespace after it.
+fn next_token(bytes: &[u8], pos: &mut usize) -> Option<String> {
+    loop {
+        while *pos < bytes.len() && (bytes[*pos] as char).is_whitespace() {
+            *pos += 1;
+        }
+        if *pos < bytes.len() && bytes[*pos] == b'#' {
+            while *pos < bytes.len() && bytes[*pos] != b'\n' {
+                *pos += 1;
+            }
+        } else {
+            break;
+        }
+    }
+    let start = *pos;
+    while *pos < bytes.len() && !(bytes[*pos] as char).is_whitespace() {
+        *pos += 1;
+    }
+    if start == *pos {
+        None
+    } else {
+        String::from_utf8(bytes[start..*pos].to_vec()).ok()
+    }
+}
+
+// portable float map: "PF" (color) or "Pf" (gray), the size, and a
+// scale whose sign gives the byte order, then rows of 32-bit floats
+// from the bottom.
+pub fn read_pfm(bytes: &[u8]) -> Option<(usize, usize, Vec<Vec3>)> {
+    let mut pos = 0;
+    let channels = match next_token(bytes, &mut pos)?.as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return None
+    };
+    let width: usize = next_token(bytes, &mut pos)?.parse().ok()?;
+    let height: usize = next_token(bytes, &mut pos)?.parse().ok()?;
+    let scale: f64 = next_token(bytes, &mut pos)?.parse().ok()?;
+    // a single whitespace character ends the header
+    pos += 1;
+    if width == 0 || height == 0 || bytes.len() < pos + width * height * channels * 4 {
+        return None;
+    }
+    let little_endian = scale < 0.0;
+    let float_at = |k: usize| {
+        let b = [bytes[pos + 4 * k], bytes[pos + 4 * k + 1], bytes[pos + 4 * k + 2], bytes[pos + 4 * k + 3]];
+        (if little_endian { f32::from_le_bytes(b) } else { f32::from_be_bytes(b) }) as f64
+    };
+    let mut pixels = Vec::with_capacity(width * height);
+    for j in (0..height).rev() {
+        for i in 0..width {
+            let k = (j * width + i) * channels;
+            pixels.push(if channels == 3 {
+                Vec3::new(float_at(k), float_at(k + 1), float_at(k + 2))
+            } else {
+                Vec3::new(float_at(k), float_at(k), float_at(k))
+            });
+        }
+    }
+    Some((width, height, pixels))
+}
+
+fn rgbe_to_vec3(rgbe: &[u8]) -> Vec3 {
+    if rgbe[3] == 0 {
+        Vec3::zero()
+    } else {
+        let f = 2.0f64.powi(rgbe[3] as i32 - (128 + 8));
+        Vec3::new((rgbe[0] as f64 + 0.5) * f, (rgbe[1] as f64 + 0.5) * f, (rgbe[2] as f64 + 0.5) * f)
+    }
+}
+
+// Radiance RGBE, flat or with the usual run-length encoded scanlines,
+// in the standard "-Y height +X width" orientation.
+pub fn read_hdr(bytes: &[u8]) -> Option<(usize, usize, Vec<Vec3>)> {
+    if !bytes.starts_with(b"#?") {
+        return None;
+    }
+    // header lines up to an empty one, then the resolution line
+    let mut pos = 0;
+    loop {
+        let end = pos + bytes[pos..].iter().position(|b| *b == b'\n')?;
+        let line = String::from_utf8_lossy(&bytes[pos..end]).to_string();
+        pos = end + 1;
+        if line.trim().is_empty() {
+            break;
+        }
+        if line.starts_with("FORMAT=") && line.trim() != "FORMAT=32-bit_rle_rgbe" {
+            return None;
+        }
+    }
+    let end = pos + bytes[pos..].iter().position(|b| *b == b'\n')?;
+    let resolution = String::from_utf8_lossy(&bytes[pos..end]).to_string();
+    pos = end + 1;
+    let words: Vec<&str> = resolution.split_whitespace().collect();
+    if words.len() != 4 || words[0] != "-Y" || words[2] != "+X" {
+        return None;
+    }
+    let height: usize = words[1].parse().ok()?;
+    let width: usize = words[3].parse().ok()?;
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let mut pixels = Vec::with_capacity(width * height);
+    let mut scanline = vec![0u8; width * 4];
+    for _ in 0..height {
+        let rle = (8..0x8000).contains(&width) && bytes.len() >= pos + 4 &&
+            bytes[pos] == 2 && bytes[pos + 1] == 2 &&
+            ((bytes[pos + 2] as usize) << 8 | bytes[pos + 3] as usize) == width;
+        if rle {
+            pos += 4;
+            // each component separately, in runs and literal stretches
+            for c in 0..4 {
+                let mut i = 0;
+                while i < width {
+                    let count = *bytes.get(pos)? as usize;
+                    pos += 1;
+                    if count > 128 {
+                        let count = count - 128;
+                        let value = *bytes.get(pos)?;
+                        pos += 1;
+                        if count > width - i {
+                            return None;
+                        }
+                        for _ in 0..count {
+                            scanline[i * 4 + c] = value;
+                            i += 1;
+                        }
+                    } else {
+                        if count == 0 || count > width - i || bytes.len() < pos + count {
+                            return None;
+                        }
+                        for k in 0..count {
+                            scanline[i * 4 + c] = bytes[pos + k];
+                            i += 1;
+                        }
+                        pos += count;
+                    }
+                }
+            }
+        } else {
+            if bytes.len() < pos + width * 4 {
+                return None;
+            }
+            scanline.copy_from_slice(&bytes[pos..pos + width * 4]);
+            pos += width * 4;
+        }
+        pixels.extend(scanline.chunks(4).map(rgbe_to_vec3));
+    }
+    Some((width, height, pixels))
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn environment_maps_are_read_and_sampled_by_luminance() {
+    use std::env;
+    use std::io::Write;
+
+    // a 4x2 pfm, written bottom row first, with a bright pixel
+    let (width, height) = (4, 2);
+    let top = [0.1, 0.2, 8.0, 0.1];
+    let bottom = [0.05, 0.05, 0.0, 0.05];
+    let mut data = b"PF\n4 2\n-1.0\n".to_vec();
+    for v in bottom.iter().chain(top.iter()) {
+        for _ in 0..3 {
+            data.extend_from_slice(&(*v as f32).to_le_bytes());
+        }
+    }
+    let file_name = env::temp_dir().join("loom_environment_map_test.pfm");
+    File::create(&file_name).unwrap().write_all(&data).unwrap();
+    let map = EnvironmentMap::load(file_name.to_str().unwrap(), 30.0, 2.0).unwrap();
+    assert!(map.width == width && map.height == height);
+    assert!((map.pixels[2].x() - 8.0).abs() < 1e-6);
+    assert!(map.pixels[6] == Vec3::zero());
+
+    // directions map back to where they came from
+    for _ in 0..100 {
+        let d = sampling::random_3d_direction();
+        let (u, v) = map.to_uv(&d);
+        assert!((map.direction_at(u, v) - d).length() < 1e-8);
+    }
+    let up = map.get_background(&Vec3::new(0.0, 1.0, 0.0));
+    assert!(up.x() > 0.0);
+
+    // samples' densities agree with direction_pdf, and weight the
+    // radiance so that it averages out to the integral over the sphere
+    let n = 100000;
+    let mut itor = (0..n).map(|_| {
+        let (d, pdf) = map.sample_direction();
+        assert!((pdf - map.direction_pdf(&d)).abs() < 1e-8 * pdf);
+        luminance(&map.get_background(&d)) / pdf
+    });
+    let (integral, _) = sampling::avstdev(&mut itor);
+    assert!((integral / (4.0 * PI * map.average_luminance()) - 1.0).abs() < 0.01);
+
+    // and the bright pixel gets most of them
+    let bright = (0..10000).filter(|_| {
+        let (d, _) = map.sample_direction();
+        map.get_background(&d).x() > 1.0
+    }).count();
+    assert!(bright > 9000);
+}
+
+#[test]
+fn hdr_files_are_decoded() {
+    // one flat scanline of width 2, then one run-length encoded
+    // scanline of width 8 elsewhere
+    let mut flat = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n".to_vec();
+    flat.extend_from_slice(&[128, 64, 0, 129, 0, 0, 0, 0]);
+    let (width, height, pixels) = read_hdr(&flat).unwrap();
+    assert!(width == 2 && height == 1);
+    assert!((pixels[0] - Vec3::new(1.0, 0.5, 0.0)).length() < 0.01);
+    assert!(pixels[1] == Vec3::zero());
+
+    let mut rle = b"#?RADIANCE\n\n-Y 1 +X 8\n".to_vec();
+    rle.extend_from_slice(&[2, 2, 0, 8]);
+    // red: a run of 8; green: 8 literals; blue: two runs; exponent
+    rle.extend_from_slice(&[136, 128]);
+    rle.extend_from_slice(&[8, 0, 16, 32, 48, 64, 80, 96, 112]);
+    rle.extend_from_slice(&[132, 0, 132, 255]);
+    rle.extend_from_slice(&[136, 128]);
+    let (width, height, pixels) = read_hdr(&rle).unwrap();
+    assert!(width == 8 && height == 1);
+    assert!((pixels[3] - Vec3::new(128.5, 48.5, 0.5) / 256.0).length() < 1e-8);
+    assert!((pixels[7] - Vec3::new(128.5, 112.5, 255.5) / 256.0).length() < 1e-8);
+}
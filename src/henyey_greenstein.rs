@@ -0,0 +1,113 @@
+use material::*;
+use vector::Vec3;
+use vector;
+use ray::Ray;
+use hitable::*;
+use random::*;
+use spectrum;
+
+use std::fmt;
+use std::fmt::Debug;
+use std::f64::consts::PI;
+
+// testing imports
+#[allow(unused_imports)]
+use sampling;
+
+// the Henyey-Greenstein phase function, dressed up as a material so
+// that scattering events inside participating media look to the
+// integrators just like surface hits. g is the average cosine between
+// the incoming and scattered directions: positive g scatters forward,
+// negative g backward, and g = 0 is isotropic. The normal of a medium
+// hit is meaningless and ignored.
+//
+// color is the fraction of light that survives a scattering event,
+// the medium's single-scattering albedo.
+
+#[derive(Debug)]
+pub struct HenyeyGreenstein {
+    pub g: f64,
+    pub color: Vec3
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f64, color: &Vec3) -> Box<Material> {
+        Box::new(HenyeyGreenstein {
+            g: g,
+            color: *color
+        })
+    }
+
+    // density with respect to solid angle
+    fn phase(&self, ray_in: &Ray, ray_out: &Ray) -> f64 {
+        let cos = vector::unit_vector(&ray_in.direction())
+            .dot(&vector::unit_vector(&ray_out.direction()));
+        let g = self.g;
+        let denom = 1.0 + g * g - 2.0 * g * cos;
+        (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+    }
+}
+
+impl Material for HenyeyGreenstein {
+    fn wants_importance_sampling(&self) -> bool { true }
+
+    fn albedo(&self, ray_in: &Ray, ray_out: &Ray, _surface_normal: &Vec3) -> Vec3 {
+        spectrum::upsample(&self.color) * (PI * self.phase(ray_in, ray_out))
+    }
+
+    fn bsdf(&self, ray_in: &Ray, ray_out: &Ray, _surface_normal: &Vec3) -> f64 {
+        2.0 * PI * self.phase(ray_in, ray_out)
+    }
+
+    fn scatter(&self, ray_in: &Ray, rec: &HitRecord) -> Scatter {
+        let g = self.g;
+        let u = rand_double();
+        let cos = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            (1.0 + g * g - s * s) / (2.0 * g)
+        };
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        let phi = 2.0 * PI * rand_double();
+        let forward = vector::unit_vector(&ray_in.direction());
+        let (t1, t2) = vector::tangent_space(&forward);
+        let direction = forward * cos + (t1 * phi.cos() + t2 * phi.sin()) * sin;
+        Scatter::Bounce(spectrum::upsample(&self.color), Ray::with_time(rec.p, direction, ray_in.time()))
+    }
+
+    fn debug(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt(f)
+    }
+
+    fn is_emitter(&self) -> bool { false }
+
+    fn emission(&self) -> Vec3 { Vec3::zero() }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn phase_function_is_a_pdf_with_mean_cosine_g() {
+    let ray_in = Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0));
+    for g in [-0.5, 0.0, 0.7].iter() {
+        let m = HenyeyGreenstein::new(*g, &Vec3::new(1.0, 1.0, 1.0));
+
+        // bsdf / 2 pi integrates to one over the sphere
+        let n = 200000;
+        let mut itor = (0..n).map(|_| {
+            let out = Ray::new(Vec3::zero(), sampling::random_3d_direction());
+            m.bsdf(&ray_in, &out, &Vec3::zero()) * 2.0
+        });
+        let (average, _) = sampling::avstdev(&mut itor);
+        assert!((average - 1.0).abs() < 0.05);
+
+        let hr = HitRecord::hit(1.0, Vec3::zero(), Vec3::zero(), &*m);
+        let mut itor = (0..n).map(|_| match m.scatter(&ray_in, &hr) {
+            Scatter::Bounce(_, r) => vector::unit_vector(&r.direction()).z(),
+            _ => panic!("phase functions always scatter")
+        });
+        let (average, _) = sampling::avstdev(&mut itor);
+        assert!((average - g).abs() < 0.01);
+    }
+}
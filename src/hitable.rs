@@ -2,12 +2,16 @@ use vector::Vec3;
 use ray::Ray;
 use material::Material;
 use aabb::AABB;
+use area_light::AreaLight;
 
 pub struct HitRecord<'a> {
     pub t: f64,
     pub p: Vec3,
     pub normal: Vec3,
-    pub material: &'a Material
+    pub material: &'a Material,
+    // which of the scene's objects was hit, if they've been numbered
+    // (see aov.rs); zero otherwise
+    pub object_id: usize
 }
 
 impl<'a> HitRecord<'a> {
@@ -16,7 +20,8 @@ impl<'a> HitRecord<'a> {
             t: t,
             p: p,
             normal: normal,
-            material: material
+            material: material,
+            object_id: 0
         }
     }
 }
@@ -24,5 +29,28 @@ impl<'a> HitRecord<'a> {
 pub trait Hitable : Send + Sync {
     fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'a>>;
     fn bounding_box(&self) -> Option<AABB>;
-    fn importance_distribution(&self) -> Option<AABB>;
+    // the emitting surfaces of this object, for light sampling
+    fn area_lights(&self) -> Vec<AreaLight>;
+
+    // the stretches of the line through r that are inside this object,
+    // as the hits entering and leaving them, in order, behind the ray's
+    // origin as well as ahead of it. Only closed objects have an inside,
+    // for volumes and constructive solid geometry to use.
+    //
+    // By default, the surface's crossings, walked from far behind and
+    // taken in pairs.
+    fn intervals<'a>(&'a self, r: &Ray) -> Vec<(HitRecord<'a>, HitRecord<'a>)> {
+        let mut result = Vec::new();
+        let mut t = -1e20;
+        while let Some(enter) = self.hit(r, t, 1e20) {
+            match self.hit(r, enter.t + 0.00001, 1e20) {
+                None => break,
+                Some(exit) => {
+                    t = exit.t + 0.00001;
+                    result.push((enter, exit));
+                }
+            }
+        }
+        result
+    }
 }
@@ -1,5 +1,6 @@
 use aabb;
 use aabb::AABB;
+use area_light::AreaLight;
 use hitable::*;
 use ray::Ray;
 use std::option::Option;
@@ -51,8 +52,7 @@ impl Hitable for HitableList {
         self.bbox
     }
 
-    
-    fn importance_distribution(&self) -> Option<AABB> {
-        panic!("importance_distribution called on hitable_list!");
+    fn area_lights(&self) -> Vec<AreaLight> {
+        self.v.iter().flat_map(|h| h.area_lights()).collect()
     }
 }
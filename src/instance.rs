@@ -0,0 +1,251 @@
+use aabb::AABB;
+use aabb;
+use area_light::*;
+use hitable::*;
+use material::Material;
+use matrix::Matrix;
+use matrix;
+use ray::Ray;
+use vector::Vec3;
+use vector;
+
+use std::sync::Arc;
+
+//////////////////////////////////////////////////////////////////////////////
+// a shared object placed in the scene by a transform, so that one mesh
+// can show up many times while being stored once. Rays are taken into
+// the object's space, and what they hit is brought back out.
+//
+// Instances can move: given several keyframes, the transform is
+// interpolated element by element between the two around the ray's
+// time, and held before the first and after the last. That's right for
+// translations and scales, and close enough for rotations that are
+// small between keyframes.
+//
+// An instance can also give the object its own material. Its area
+// lights are the object's, moved into place, as long as it holds
+// still, keeps the object's material, and doesn't squash spheres or
+// discs.
+
+pub struct Instance {
+    pub hitable: Arc<Hitable + Send + Sync>,
+    // (time, transform, its inverse), by time
+    pub keyframes: Vec<(f64, Matrix, Matrix)>,
+    pub material: Option<Box<Material>>
+}
+
+impl Instance {
+    // None if any transform can't be inverted
+    pub fn new(hitable: Arc<Hitable + Send + Sync>, transform: &Matrix,
+               material: Option<Box<Material>>) -> Option<Instance> {
+        Instance::keyframed(hitable, &[(0.0, *transform)], material)
+    }
+
+    pub fn keyframed(hitable: Arc<Hitable + Send + Sync>, keyframes: &[(f64, Matrix)],
+                     material: Option<Box<Material>>) -> Option<Instance> {
+        if keyframes.is_empty() {
+            return None;
+        }
+        let mut frames = Vec::new();
+        for &(time, transform) in keyframes.iter() {
+            frames.push((time, transform, transform.inverse()?));
+        }
+        frames.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        Some(Instance {
+            hitable: hitable,
+            keyframes: frames,
+            material: material
+        })
+    }
+
+    // the transform and its inverse at time
+    pub fn transform_at(&self, time: f64) -> Option<(Matrix, Matrix)> {
+        let first = &self.keyframes[0];
+        let last = &self.keyframes[self.keyframes.len() - 1];
+        if time <= first.0 {
+            return Some((first.1, first.2));
+        }
+        if time >= last.0 {
+            return Some((last.1, last.2));
+        }
+        let i = self.keyframes.iter().position(|k| k.0 > time).unwrap();
+        let (t0, m0, _) = self.keyframes[i - 1];
+        let (t1, m1, _) = self.keyframes[i];
+        let m = matrix::lerp(&m0, &m1, (time - t0) / (t1 - t0));
+        m.inverse().map(|inv| (m, inv))
+    }
+
+    // brings a hit in the object's space back out
+    fn to_world<'a>(&'a self, mut hr: HitRecord<'a>, transform: &Matrix, inverse: &Matrix) -> HitRecord<'a> {
+        hr.p = transform.transform_point(&hr.p);
+        hr.normal = vector::unit_vector(&Matrix::transform_normal_by_inverse(inverse, &hr.normal));
+        if let Some(ref material) = self.material {
+            hr.material = &**material;
+        }
+        hr
+    }
+}
+
+// how much m scales every direction by, if it scales them all alike
+// without shearing
+fn uniform_scale(m: &Matrix) -> Option<f64> {
+    let (x, y, z) = (m.transform_vector(&Vec3::new(1.0, 0.0, 0.0)),
+                     m.transform_vector(&Vec3::new(0.0, 1.0, 0.0)),
+                     m.transform_vector(&Vec3::new(0.0, 0.0, 1.0)));
+    let s = x.length();
+    let eps = 1e-9 * s;
+    if (y.length() - s).abs() > eps || (z.length() - s).abs() > eps ||
+        x.dot(&y).abs() > eps * s || y.dot(&z).abs() > eps * s || z.dot(&x).abs() > eps * s {
+        None
+    } else {
+        Some(s)
+    }
+}
+
+// an area light moved by m, unless m squashes one of its round shapes
+pub fn transform_light(light: &AreaLight, m: &Matrix) -> Option<AreaLight> {
+    let shape = match light.shape {
+        LightShape::Sphere { center, radius } => {
+            let s = uniform_scale(m)?;
+            LightShape::Sphere { center: m.transform_point(&center), radius: radius * s }
+        },
+        LightShape::Disc { center, normal, radius } => {
+            let s = uniform_scale(m)?;
+            LightShape::Disc {
+                center: m.transform_point(&center),
+                normal: vector::unit_vector(&m.transform_vector(&normal)),
+                radius: radius * s
+            }
+        },
+        LightShape::Rectangle { bottom_left, right, up } => LightShape::Rectangle {
+            bottom_left: m.transform_point(&bottom_left),
+            right: m.transform_vector(&right),
+            up: m.transform_vector(&up)
+        },
+        LightShape::Triangle { vertices } => LightShape::Triangle {
+            vertices: [m.transform_point(&vertices[0]),
+                       m.transform_point(&vertices[1]),
+                       m.transform_point(&vertices[2])]
+        },
+        LightShape::Cylinder { base, top, radius } => {
+            let s = uniform_scale(m)?;
+            LightShape::Cylinder {
+                base: m.transform_point(&base),
+                top: m.transform_point(&top),
+                radius: radius * s
+            }
+        },
+        LightShape::Cone { base, apex, radius } => {
+            let s = uniform_scale(m)?;
+            LightShape::Cone {
+                base: m.transform_point(&base),
+                apex: m.transform_point(&apex),
+                radius: radius * s
+            }
+        },
+        LightShape::Torus { center, axis, major_radius, minor_radius } => {
+            let s = uniform_scale(m)?;
+            LightShape::Torus {
+                center: m.transform_point(&center),
+                axis: vector::unit_vector(&m.transform_vector(&axis)),
+                major_radius: major_radius * s,
+                minor_radius: minor_radius * s
+            }
+        }
+    };
+    Some(AreaLight::with_spectrum(shape, light.spectrum.clone()))
+}
+
+impl Hitable for Instance {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'a>> {
+        let (transform, inverse) = self.transform_at(r.time())?;
+        self.hitable.hit(&inverse.transform_ray(r), t_min, t_max)
+            .map(|hr| self.to_world(hr, &transform, &inverse))
+    }
+
+    fn intervals<'a>(&'a self, r: &Ray) -> Vec<(HitRecord<'a>, HitRecord<'a>)> {
+        match self.transform_at(r.time()) {
+            None => Vec::new(),
+            Some((transform, inverse)) => {
+                self.hitable.intervals(&inverse.transform_ray(r)).into_iter().map(|(enter, exit)| {
+                    (self.to_world(enter, &transform, &inverse), self.to_world(exit, &transform, &inverse))
+                }).collect()
+            }
+        }
+    }
+
+    // the object's box, corners and all, at every keyframe. Corners move
+    // in straight lines between keyframes, so that covers the motion.
+    fn bounding_box(&self) -> Option<AABB> {
+        let b = self.hitable.bounding_box()?;
+        let mut boxes = self.keyframes.iter().map(|k| k.1.transform_aabb(&b));
+        let first = boxes.next().unwrap();
+        Some(boxes.fold(first, |a, b| aabb::surrounding_box(&a, &b)))
+    }
+
+    fn area_lights(&self) -> Vec<AreaLight> {
+        let m = self.keyframes[0].1;
+        if self.keyframes.iter().any(|k| k.1 != m) || self.material.is_some() {
+            return Vec::new();
+        }
+        self.hitable.area_lights().iter().filter_map(|light| transform_light(light, &m)).collect()
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn instances_place_shared_objects() {
+    use emitter::Emitter;
+    use lambertian::Lambertian;
+    use sphere::Sphere;
+
+    let sphere: Arc<Hitable + Send + Sync> = Arc::new(Sphere::new(
+        Vec3::zero(), 1.0, Emitter::new(&Vec3::new(1.0, 1.0, 1.0))));
+    // twice as big, and moved to (5, 0, 0)
+    let m = Matrix::new([[2.0, 0.0, 0.0, 5.0],
+                         [0.0, 2.0, 0.0, 0.0],
+                         [0.0, 0.0, 2.0, 0.0],
+                         [0.0, 0.0, 0.0, 1.0]]);
+    let instance = Instance::new(sphere.clone(), &m, None).unwrap();
+    let ray = Ray::new(Vec3::new(5.0, 0.0, 10.0), Vec3::new(0.0, 0.0, -1.0));
+    let hr = instance.hit(&ray, 0.0, 1e20).unwrap();
+    assert!((hr.t - 8.0).abs() < 1e-8);
+    assert!((hr.p - Vec3::new(5.0, 0.0, 2.0)).length() < 1e-8);
+    assert!((hr.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-8);
+    assert!(hr.material.is_emitter());
+    let b = instance.bounding_box().unwrap();
+    assert!(b.min() == Vec3::new(3.0, -2.0, -2.0) && b.max() == Vec3::new(7.0, 2.0, 2.0));
+    match instance.area_lights()[0].shape {
+        LightShape::Sphere { center, radius } => assert!(center == Vec3::new(5.0, 0.0, 0.0) && radius == 2.0),
+        _ => panic!("expected a sphere")
+    }
+
+    // the same sphere, squashed and painted: no light, and normals
+    // follow the squashing
+    let squash = Matrix::new([[4.0, 0.0, 0.0, 0.0],
+                              [0.0, 1.0, 0.0, 0.0],
+                              [0.0, 0.0, 1.0, 0.0],
+                              [0.0, 0.0, 0.0, 1.0]]);
+    let painted = Instance::new(sphere.clone(), &squash,
+                                Some(Lambertian::new(&Vec3::new(0.5, 0.5, 0.5)))).unwrap();
+    let diagonal = Ray::new(Vec3::new(2.0, 0.0, 10.0), Vec3::new(0.0, 0.0, -1.0));
+    let hr = painted.hit(&diagonal, 0.0, 1e20).unwrap();
+    assert!(!hr.material.is_emitter());
+    let expected = vector::unit_vector(&Vec3::new(0.5 / 4.0, 0.0, 0.75f64.sqrt()));
+    assert!((hr.normal - expected).length() < 1e-8);
+    assert!(painted.area_lights().is_empty());
+
+    // keyframes slide it from x = 0 at time 0 to x = 10 at time 1
+    let slide = |x: f64| Matrix::new([[1.0, 0.0, 0.0, x],
+                                      [0.0, 1.0, 0.0, 0.0],
+                                      [0.0, 0.0, 1.0, 0.0],
+                                      [0.0, 0.0, 0.0, 1.0]]);
+    let moving = Instance::keyframed(sphere, &[(1.0, slide(10.0)), (0.0, slide(0.0))], None).unwrap();
+    let at = |x: f64, time: f64| Ray::with_time(Vec3::new(x, 0.0, 10.0), Vec3::new(0.0, 0.0, -1.0), time);
+    assert!(moving.hit(&at(5.0, 0.5), 0.0, 1e20).is_some());
+    assert!(moving.hit(&at(5.0, 0.0), 0.0, 1e20).is_none());
+    assert!(moving.hit(&at(10.0, 2.0), 0.0, 1e20).is_some());
+    let b = moving.bounding_box().unwrap();
+    assert!(b.min().x() == -1.0 && b.max().x() == 11.0);
+}
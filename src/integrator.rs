@@ -0,0 +1,248 @@
+use background::Background;
+use camera::Camera;
+use hitable::*;
+use light::LightList;
+use material::*;
+use random::*;
+use ray::Ray;
+use render_settings::RenderSettings;
+use vector::Vec3;
+use vector;
+
+use std::f64::consts::PI;
+
+//////////////////////////////////////////////////////////////////////////////
+// An integrator estimates the radiance arriving along a camera ray.
+// Everything it needs to know about the scene lives in the
+// RenderContext, so different estimators can be compared on the same
+// scene without touching the render loop.
+
+pub struct RenderContext<'a> {
+    pub world: &'a Hitable,
+    pub background: &'a Background,
+    pub lights: &'a LightList,
+    pub settings: &'a RenderSettings
+}
+
+pub trait Integrator: Send + Sync {
+    fn color(&self, ray: &Ray, context: &RenderContext) -> Vec3;
+
+    // called by every render thread before each pass over the image.
+    // Integrators that need to precompute something per pass (photon
+    // maps, say) return the integrator to use for that pass.
+    fn begin_pass(&self, _pass: usize, _context: &RenderContext) -> Option<Box<Integrator>> {
+        None
+    }
+
+    // integrators that decide for themselves where on the film their
+    // samples land (Metropolis, say) add a whole pass to image here,
+    // scaled like one sample per pixel, and return true.
+    fn render_pass(&self, _image: &mut [Vec<Vec3>], _camera: &Camera, _context: &RenderContext) -> bool {
+        false
+    }
+
+    // whether color() can be called with wavelengths set, in spectral
+    // mode. Integrators that carry light between pixels or passes (photon
+    // maps, Markov chains) would mix up wavelengths and can't.
+    fn supports_spectral(&self) -> bool {
+        true
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// helpers shared by the integrators.
+
+// materials report bsdf() as a density with respect to the uniform
+// measure on the hemisphere (so it integrates to 1 over 2 pi
+// steradians), and albedo() as pi times the cosine-weighted bsdf.
+// these convert to the usual solid-angle quantities.
+
+pub fn bsdf_pdf(material: &Material, ray_in: &Ray, ray_out: &Ray, normal: &Vec3) -> f64 {
+    material.bsdf(ray_in, ray_out, normal) / (2.0 * PI)
+}
+
+pub fn bsdf_value(material: &Material, ray_in: &Ray, ray_out: &Ray, normal: &Vec3) -> Vec3 {
+    material.albedo(ray_in, ray_out, normal) / PI
+}
+
+// materials are two-sided, so shading happens on the side of the
+// surface the ray came from.
+pub fn facing_normal(ray: &Ray, normal: &Vec3) -> Vec3 {
+    if ray.direction().dot(normal) > 0.0 { -*normal } else { *normal }
+}
+
+pub fn emission(hr: &HitRecord) -> Option<Vec3> {
+    if hr.material.is_emitter() {
+        Some(hr.material.emission())
+    } else {
+        None
+    }
+}
+
+// throughput-based russian roulette. Returns false when the path
+// should be terminated; otherwise rescales the attenuation by the
+// survival probability so the estimator stays unbiased.
+pub fn russian_roulette(context: &RenderContext, depth: usize, attenuation: &mut Vec3) -> bool {
+    if depth < context.settings.min_depth {
+        return true;
+    }
+    let a = *attenuation;
+    let survival = a.x().max(a.y()).max(a.z()).min(1.0);
+    if survival <= 0.0 || rand_double() >= survival {
+        false
+    } else {
+        *attenuation = a / survival;
+        true
+    }
+}
+
+// Veach's power heuristic with beta = 2
+pub fn power_heuristic(f_pdf: f64, g_pdf: f64) -> f64 {
+    let f2 = f_pdf * f_pdf;
+    let g2 = g_pdf * g_pdf;
+    if f2 + g2 == 0.0 { 0.0 } else { f2 / (f2 + g2) }
+}
+
+// the light arriving along ray: the emission of the first thing it
+// hits, or the background if it hits nothing.
+pub fn incident_light(context: &RenderContext, ray: &Ray) -> Vec3 {
+    match context.world.hit(ray, 0.00001, 1e20) {
+        None => context.background.get_background(&vector::unit_vector(&ray.direction())),
+        Some(hr) => emission(&hr).unwrap_or_else(Vec3::zero)
+    }
+}
+
+// whether ray reaches the point at distance along it (which may be
+// infinite) without hitting anything. ray's direction is a unit vector.
+pub fn unoccluded(context: &RenderContext, ray: &Ray, distance: f64) -> bool {
+    context.world.hit(ray, 0.00001, (distance * (1.0 - 0.00001)).min(1e20)).is_none()
+}
+
+// next-event estimation: samples a direction towards the lights and
+// traces a shadow ray along it. The contribution is weighted against
+// the bsdf sampling strategy, so integrators should weight the
+// emission and background their bsdf samples reach with
+// emitter_weight.
+pub fn estimate_light_sample(context: &RenderContext, ray_in: &Ray, hr: &HitRecord) -> Vec3 {
+    let normal = facing_normal(ray_in, &hr.normal);
+    match context.lights.sample_li(&hr.p) {
+        None => Vec3::zero(),
+        Some(sample) => {
+            let shadow_ray = Ray::with_time(hr.p, sample.direction, ray_in.time());
+            let scatter_pdf = bsdf_pdf(hr.material, ray_in, &shadow_ray, &normal);
+            if scatter_pdf <= 0.0 {
+                return Vec3::zero();
+            }
+            let f = bsdf_value(hr.material, ray_in, &shadow_ray, &normal);
+            if sample.delta {
+                // bsdf samples never reach delta lights, so there's
+                // nothing to weight against
+                return if unoccluded(context, &shadow_ray, sample.distance) {
+                    f * sample.radiance / sample.pdf
+                } else {
+                    Vec3::zero()
+                };
+            }
+            // the sample's density covers every light along the
+            // direction, so whichever is hit first counts
+            let le = incident_light(context, &shadow_ray);
+            f * le * (power_heuristic(sample.pdf, scatter_pdf) / sample.pdf)
+        }
+    }
+}
+
+// MIS weight for emission or background reached by a bsdf sample taken
+// at p with density scatter_pdf.
+pub fn emitter_weight(context: &RenderContext, p: &Vec3, direction: &Vec3, scatter_pdf: f64) -> f64 {
+    let light_pdf = context.lights.pdf_li(p, &vector::unit_vector(direction));
+    power_heuristic(scatter_pdf, light_pdf)
+}
+
+// one light sample and one bsdf sample, combined with MIS, of the
+// radiance reflected at hr towards the origin of ray_in. Emission
+// from the surface itself is not included.
+pub fn estimate_direct(context: &RenderContext, ray_in: &Ray, hr: &HitRecord) -> Vec3 {
+    let normal = facing_normal(ray_in, &hr.normal);
+    let mut result = estimate_light_sample(context, ray_in, hr);
+
+    if let Scatter::Bounce(attenuation, scattered) = hr.material.scatter(ray_in, hr) {
+        let scatter_pdf = bsdf_pdf(hr.material, ray_in, &scattered, &normal);
+        if scatter_pdf > 0.0 {
+            let le = incident_light(context, &scattered);
+            let w = emitter_weight(context, &hr.p, &scattered.direction(), scatter_pdf);
+            result = result + attenuation * le * w;
+        }
+    }
+    result
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn direct_light_from_the_background_is_weighted_right() {
+    use background;
+    use hitable_list::HitableList;
+    use lambertian::Lambertian;
+    use sampling;
+    use std::sync::Arc;
+
+    // an empty scene under a white sky: a lambertian surface reflects
+    // its albedo, whether the sky is reached by light or bsdf samples
+    let world = HitableList::new(Vec::new());
+    let background: Arc<Background + Send + Sync> = Arc::new(background::constant(Vec3::new(1.0, 1.0, 1.0)));
+    let lights = LightList::collect(&[], Vec::new(), &background);
+    let settings = RenderSettings::default();
+    let context = RenderContext {
+        world: &world,
+        background: &*background,
+        lights: &lights,
+        settings: &settings
+    };
+    let material = Lambertian::new(&Vec3::new(0.5, 0.5, 0.5));
+    let hr = HitRecord::hit(1.0, Vec3::zero(), Vec3::new(0.0, 1.0, 0.0), &*material);
+    let ray_in = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+
+    let n = 100000;
+    let mut itor = (0..n).map(|_| estimate_direct(&context, &ray_in, &hr).x());
+    let (average, _) = sampling::avstdev(&mut itor);
+    assert!((average - 0.5).abs() < 0.01);
+}
+
+#[test]
+fn point_lights_are_reached_by_shadow_rays() {
+    use background;
+    use delta_light::PointLight;
+    use hitable_list::HitableList;
+    use lambertian::Lambertian;
+    use sphere::Sphere;
+    use std::sync::Arc;
+
+    // a point light 2 above a lambertian surface gives an irradiance
+    // of 1 there, which it reflects as albedo / pi
+    let background: Arc<Background + Send + Sync> = Arc::new(background::constant(Vec3::zero()));
+    let light = PointLight::new(&Vec3::new(0.0, 2.0, 0.0), &Vec3::new(4.0, 4.0, 4.0));
+    let lights = LightList::collect(&[], vec![light], &background);
+    let settings = RenderSettings::default();
+    let material = Lambertian::new(&Vec3::new(0.5, 0.5, 0.5));
+    let hr = HitRecord::hit(1.0, Vec3::zero(), Vec3::new(0.0, 1.0, 0.0), &*material);
+    let ray_in = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+
+    let empty = HitableList::new(Vec::new());
+    let context = RenderContext {
+        world: &empty,
+        background: &*background,
+        lights: &lights,
+        settings: &settings
+    };
+    let radiance = estimate_direct(&context, &ray_in, &hr);
+    assert!((radiance.x() - 0.5 / PI).abs() < 1e-8);
+
+    // and nothing gets past a blocker
+    let blocked = HitableList::new(vec![
+        Box::new(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 0.5, Lambertian::new(&Vec3::new(0.5, 0.5, 0.5))))]);
+    let context = RenderContext {
+        world: &blocked,
+        ..context
+    };
+    assert!(estimate_light_sample(&context, &ray_in, &hr) == Vec3::zero());
+}
@@ -0,0 +1,117 @@
+use aabb::AABB;
+use vector::Vec3;
+
+use std::cmp::Ordering;
+
+// testing imports
+#[allow(unused_imports)]
+use sampling;
+
+//////////////////////////////////////////////////////////////////////////////
+// a balanced, implicit kd-tree over points. Items are rearranged so
+// that the median of every range [lo, hi) sits at (lo + hi) / 2, with
+// smaller coordinates on the split axis to its left.
+
+pub struct KdTree<T> {
+    items: Vec<(Vec3, T)>,
+    axes: Vec<usize>
+}
+
+// yeah, this will do weird things with NaNs in the picture.
+#[inline]
+fn ffcmp(a: f64, b: f64) -> Ordering {
+    if      a < b { Ordering::Less }
+    else if a > b { Ordering::Greater }
+    else          { Ordering::Equal }
+}
+
+fn build<T>(items: &mut [(Vec3, T)], axes: &mut [usize]) {
+    if items.len() <= 1 {
+        return;
+    }
+    let bbox = AABB::from_points(&items.iter().map(|i| i.0).collect::<Vec<_>>());
+    let extent = bbox.max() - bbox.min();
+    let axis = if extent.x() > extent.y() && extent.x() > extent.z() { 0 }
+               else if extent.y() > extent.z() { 1 }
+               else { 2 };
+    // it'd be faster to pivot rather than sort, see bvh.rs
+    items.sort_unstable_by(|a, b| ffcmp(a.0[axis], b.0[axis]));
+    let mid = items.len() / 2;
+    axes[mid] = axis;
+    let (left_items, right_items) = items.split_at_mut(mid);
+    let (left_axes, right_axes) = axes.split_at_mut(mid);
+    build(left_items, left_axes);
+    build(&mut right_items[1..], &mut right_axes[1..]);
+}
+
+impl<T> KdTree<T> {
+    pub fn new(mut items: Vec<(Vec3, T)>) -> KdTree<T> {
+        let mut axes = vec![0; items.len()];
+        build(&mut items, &mut axes);
+        KdTree {
+            items: items,
+            axes: axes
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    // calls f on every item within radius of p
+    pub fn for_each_within<F>(&self, p: &Vec3, radius: f64, f: &mut F)
+        where F: FnMut(&Vec3, &T)
+    {
+        self.visit(0, self.items.len(), p, radius * radius, radius, f);
+    }
+
+    fn visit<F>(&self, lo: usize, hi: usize, p: &Vec3, radius2: f64, radius: f64, f: &mut F)
+        where F: FnMut(&Vec3, &T)
+    {
+        if lo >= hi {
+            return;
+        }
+        let mid = lo + (hi - lo) / 2;
+        let item = &self.items[mid];
+        if (item.0 - *p).length_squared() <= radius2 {
+            f(&item.0, &item.1);
+        }
+        if hi - lo == 1 {
+            return;
+        }
+        let axis = self.axes[mid];
+        let d = p[axis] - item.0[axis];
+        if d <= radius {
+            self.visit(lo, mid, p, radius2, radius, f);
+        }
+        if d >= -radius {
+            self.visit(mid + 1, hi, p, radius2, radius, f);
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn it_works() {
+    let points: Vec<_> = (0..2000).map(|i| (sampling::random_in_unit_sphere(), i)).collect();
+    let tree = KdTree::new(points.clone());
+    assert_eq!(tree.len(), points.len());
+
+    for _ in 0..100 {
+        let p = sampling::random_in_unit_sphere();
+        let mut found = Vec::new();
+        tree.for_each_within(&p, 0.2, &mut |_, i| found.push(*i));
+        found.sort();
+        let mut expected: Vec<_> = points.iter()
+            .filter(|x| (x.0 - p).length() <= 0.2)
+            .map(|x| x.1)
+            .collect();
+        expected.sort();
+        assert_eq!(found, expected);
+    }
+}
@@ -2,6 +2,7 @@ use material::*;
 use vector::Vec3;
 use vector;
 use sampling;
+use spectrum;
 use ray::Ray;
 use hitable::*;
 
@@ -22,7 +23,7 @@ pub struct Lambertian {
 impl Material for Lambertian {
     fn wants_importance_sampling(&self) -> bool { true }
     fn albedo(&self, _ray_in: &Ray, ray_out: &Ray, surface_normal: &Vec3) -> Vec3 {
-        self.albedo * surface_normal.dot(&ray_out.direction())
+        spectrum::upsample(&self.albedo) * surface_normal.dot(&ray_out.direction())
     }
     
     fn bsdf(&self, _ray_in: &Ray, ray_out: &Ray, surface_normal: &Vec3) -> f64 {
@@ -45,7 +46,7 @@ impl Material for Lambertian {
         } else {
             target = rec.p + sampling::random_3d_direction() + rec.normal;
         }
-        Scatter::Bounce(self.albedo, Ray::new(rec.p, target - rec.p))
+        Scatter::Bounce(spectrum::upsample(&self.albedo), Ray::with_time(rec.p, target - rec.p, ray_in.time()))
     }
 
     fn debug(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -53,6 +54,8 @@ impl Material for Lambertian {
     }
 
     fn is_emitter(&self) -> bool { false }
+
+    fn emission(&self) -> Vec3 { Vec3::zero() }
 }
 
 impl Lambertian {
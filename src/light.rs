@@ -0,0 +1,392 @@
+use aabb;
+use area_light::*;
+use background::Background;
+use hitable::Hitable;
+use sampling::Distribution1D;
+use sampling;
+use vector::Vec3;
+use vector;
+
+use std::f64::consts::PI;
+use std::f64;
+use std::sync::Arc;
+
+//////////////////////////////////////////////////////////////////////////////
+// whatever the integrators can sample light from: the emitting surfaces
+// of the scene, the point, spot and directional lights of delta_light,
+// and the background. Integrators get at them through a LightList,
+// which chooses among them in proportion to their power.
+
+// incident light at a point, sampled by a light
+#[derive(Debug, Clone, Copy)]
+pub struct LightSample {
+    // unit vector from the point towards the light
+    pub direction: Vec3,
+    // to the sampled point on the light; infinite for the background
+    // and directional lights
+    pub distance: f64,
+    // for delta lights, the radiance is already integrated over the
+    // light: it's the irradiance they cast at the point
+    pub radiance: Vec3,
+    // with respect to solid angle, or the probability of the sample for
+    // delta lights
+    pub pdf: f64,
+    // whether the light can only be reached through this sample, and
+    // never by a ray that happens to hit it
+    pub delta: bool
+}
+
+// light leaving a light, for starting paths on it
+#[derive(Debug, Clone, Copy)]
+pub struct EmissionSample {
+    pub p: Vec3,
+    // zero for point lights, which have no surface
+    pub normal: Vec3,
+    pub direction: Vec3,
+    // radiance, or intensity for point lights
+    pub radiance: Vec3,
+    // with respect to area
+    pub pdf_pos: f64,
+    // with respect to solid angle
+    pub pdf_dir: f64
+}
+
+impl EmissionSample {
+    // the foreshortening of the light leaving along direction
+    pub fn cos(&self) -> f64 {
+        if self.normal == Vec3::zero() {
+            1.0
+        } else {
+            self.direction.dot(&self.normal).abs()
+        }
+    }
+}
+
+pub trait Light: Send + Sync {
+    fn sample_li(&self, p: &Vec3) -> Option<LightSample>;
+
+    // solid-angle density with which sample_li generates the unit
+    // vector direction from p. Zero for delta lights.
+    fn pdf_li(&self, p: &Vec3, direction: &Vec3) -> f64;
+
+    // used to choose lights in proportion to how much light they emit:
+    // the flux over 2 pi, which is area times radiance for two-sided
+    // area lights.
+    fn power(&self) -> f64;
+
+    // lights infinitely far away, like the background
+    fn is_infinite(&self) -> bool {
+        false
+    }
+
+    // lights that no ray can hit, like point lights
+    fn is_delta(&self) -> bool {
+        false
+    }
+
+    // called once the extent of the scene is known, before power()
+    fn preprocess(&mut self, _world_center: &Vec3, _world_radius: f64) {
+    }
+
+    // lights with nowhere for paths to start from return None
+    fn sample_le(&self) -> Option<EmissionSample> {
+        None
+    }
+
+    // radiance (intensity for point lights) leaving the light along
+    // the unit vector direction, for paths started with sample_le
+    fn emitted(&self, _direction: &Vec3) -> Vec3 {
+        Vec3::zero()
+    }
+
+    // solid-angle density with which sample_le sends paths along
+    // direction from a point with the given normal
+    fn pdf_le_direction(&self, _normal: &Vec3, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    // how far from p along the unit vector direction a ray meets the
+    // light, for lights with a surface to meet
+    fn distance(&self, _p: &Vec3, _direction: &Vec3) -> Option<f64> {
+        None
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// the background, as a light. It's sampled the way the background
+// itself says to, and paths start from it like from a directional
+// light, across a disc that covers the scene.
+
+pub struct BackgroundLight {
+    pub background: Arc<Background + Send + Sync>,
+    pub power: f64,
+    pub world_center: Vec3,
+    pub world_radius: f64
+}
+
+impl BackgroundLight {
+    pub fn new(background: Arc<Background + Send + Sync>) -> BackgroundLight {
+        BackgroundLight {
+            background: background,
+            power: 0.0,
+            world_center: Vec3::zero(),
+            world_radius: 1.0
+        }
+    }
+}
+
+impl Light for BackgroundLight {
+    fn sample_li(&self, _p: &Vec3) -> Option<LightSample> {
+        let (direction, pdf) = self.background.sample_direction();
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction: direction,
+            distance: f64::INFINITY,
+            radiance: self.background.get_background(&direction),
+            pdf: pdf,
+            delta: false
+        })
+    }
+
+    fn pdf_li(&self, _p: &Vec3, direction: &Vec3) -> f64 {
+        self.background.direction_pdf(direction)
+    }
+
+    fn power(&self) -> f64 {
+        self.power
+    }
+
+    fn is_infinite(&self) -> bool {
+        true
+    }
+
+    // the scene catches 4 pi^2 R^2 L of the background's light
+    fn preprocess(&mut self, world_center: &Vec3, world_radius: f64) {
+        self.power = 2.0 * PI * world_radius * world_radius * self.background.average_luminance();
+        self.world_center = *world_center;
+        self.world_radius = world_radius;
+    }
+
+    // a direction the background would sample, and a point on the disc
+    // facing it just outside the scene
+    fn sample_le(&self) -> Option<EmissionSample> {
+        let (to_background, pdf_dir) = self.background.sample_direction();
+        if pdf_dir <= 0.0 {
+            return None;
+        }
+        let direction = -to_background;
+        let (t1, t2) = vector::tangent_space(&direction);
+        let d = sampling::random_in_unit_disk() * self.world_radius;
+        let p = self.world_center + to_background * self.world_radius + t1 * d.x() + t2 * d.y();
+        Some(EmissionSample {
+            p: p,
+            normal: direction,
+            direction: direction,
+            radiance: self.background.get_background(&to_background),
+            pdf_pos: 1.0 / (PI * self.world_radius * self.world_radius),
+            pdf_dir: pdf_dir
+        })
+    }
+
+    fn emitted(&self, direction: &Vec3) -> Vec3 {
+        self.background.get_background(&-*direction)
+    }
+
+    fn pdf_le_direction(&self, _normal: &Vec3, direction: &Vec3) -> f64 {
+        self.background.direction_pdf(&-*direction)
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+pub struct LightList {
+    pub lights: Vec<Box<Light>>,
+    // chooses lights to sample incident light from, by power
+    pub distribution: Distribution1D,
+    // chooses lights to start light subpaths from, by power: all but
+    // the background, which they couldn't be joined to
+    pub emission_distribution: Distribution1D,
+    // the sphere around the scene's bounded objects
+    pub world_center: Vec3,
+    pub world_radius: f64
+}
+
+impl LightList {
+    pub fn new(lights: Vec<Box<Light>>) -> LightList {
+        let distribution = Distribution1D::new(lights.iter().map(|l| l.power()).collect());
+        let emission_distribution = Distribution1D::new(lights.iter().map(|l| {
+            if l.is_infinite() && !l.is_delta() { 0.0 } else { l.power() }
+        }).collect());
+        LightList {
+            lights: lights,
+            distribution: distribution,
+            emission_distribution: emission_distribution,
+            world_center: Vec3::zero(),
+            world_radius: 1.0
+        }
+    }
+
+    // the emitting surfaces among objects, the lights declared on their
+    // own, and the background. Has to happen before the objects
+    // disappear into a BVH.
+    pub fn collect(objects: &[Box<Hitable + Send + Sync>],
+                   scene_lights: Vec<Box<Light>>,
+                   background: &Arc<Background + Send + Sync>) -> LightList {
+        let mut lights: Vec<Box<Light>> = objects.iter()
+            .flat_map(|h| h.area_lights())
+            .map(|l| Box::new(l) as Box<Light>)
+            .collect();
+        lights.extend(scene_lights);
+        lights.push(Box::new(BackgroundLight::new(background.clone())));
+        let bounds = objects.iter().fold(None, |acc, h| match (acc, h.bounding_box()) {
+            (None, b) => b,
+            (Some(a), Some(b)) => Some(aabb::surrounding_box(&a, &b)),
+            (Some(a), None) => Some(a)
+        });
+        let (world_center, world_radius) = bounds.map_or((Vec3::zero(), 1.0), |b| {
+            ((b.min() + b.max()) / 2.0, (b.max() - b.min()).length() / 2.0)
+        });
+        for light in lights.iter_mut() {
+            light.preprocess(&world_center, world_radius);
+        }
+        let mut result = LightList::new(lights);
+        result.world_center = world_center;
+        result.world_radius = world_radius;
+        result
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    // picks one of the lights by power and samples it. The density of
+    // the sample is that of the mixture of all the lights, since a
+    // direction can reach more than one of them; delta lights can only
+    // be reached by being picked.
+    pub fn sample_li(&self, p: &Vec3) -> Option<LightSample> {
+        let (i, pmf) = self.distribution.sample()?;
+        let mut sample = self.lights[i].sample_li(p)?;
+        sample.pdf = if sample.delta { pmf * sample.pdf } else { self.pdf_li(p, &sample.direction) };
+        if sample.pdf > 0.0 { Some(sample) } else { None }
+    }
+
+    // solid-angle density with which sample_li generates direction
+    pub fn pdf_li(&self, p: &Vec3, direction: &Vec3) -> f64 {
+        self.lights.iter().enumerate().map(|(i, light)| {
+            let pmf = self.distribution.pmf(i);
+            if pmf > 0.0 { pmf * light.pdf_li(p, direction) } else { 0.0 }
+        }).sum()
+    }
+
+    // picks one of the lights by power and starts a path on it. pdf_pos
+    // includes the probability of having picked that light.
+    pub fn sample_le(&self) -> Option<(&Light, EmissionSample)> {
+        self.sample_le_from(&self.emission_distribution)
+    }
+
+    // the same, but from any of the lights, the background included:
+    // photons can come from it, though light subpaths can't be joined
+    // to it
+    pub fn sample_photon(&self) -> Option<(&Light, EmissionSample)> {
+        self.sample_le_from(&self.distribution)
+    }
+
+    fn sample_le_from(&self, distribution: &Distribution1D) -> Option<(&Light, EmissionSample)> {
+        let (i, pmf) = distribution.sample()?;
+        let mut sample = self.lights[i].sample_le()?;
+        sample.pdf_pos *= pmf;
+        Some((&*self.lights[i], sample))
+    }
+
+    // the area density with which sample_le starts a path on an area
+    // light with the given emission.
+    pub fn emission_pdf_pos(&self, emission: &Vec3) -> f64 {
+        light_origin_pdf(emission, self.emission_distribution.total)
+    }
+
+    // whether the emitter a ray from p along the unit vector direction
+    // hit at distance is one of the lights. Emitting objects that make
+    // no area lights are never sampled, nor start paths.
+    pub fn is_light_at(&self, p: &Vec3, direction: &Vec3, distance: f64) -> bool {
+        self.lights.iter().any(|l| {
+            l.distance(p, direction).is_some_and(|d| (d - distance).abs() <= 1e-6 * distance.max(1.0))
+        })
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn lights_are_chosen_by_power() {
+    use background;
+    use sampling;
+    use hitable_list::HitableList;
+    use sphere::Sphere;
+    use emitter::Emitter;
+    use lambertian::Lambertian;
+
+    // a bright light, a dim one nested in a list, and a dark background
+    let objects: Vec<Box<Hitable + Send + Sync>> = vec![
+        Box::new(Sphere::new(Vec3::new(0.0, 2.0, 0.0), 0.5, Emitter::new(&Vec3::new(4.0, 4.0, 4.0)))),
+        Box::new(HitableList::new(vec![
+            Box::new(Sphere::new(Vec3::new(0.0, -2.0, 0.0), 0.5, Emitter::new(&Vec3::new(1.0, 1.0, 1.0)))),
+            Box::new(Sphere::new(Vec3::new(2.0, 0.0, 0.0), 0.5, Lambertian::new(&Vec3::new(0.5, 0.5, 0.5))))]))];
+    let background: Arc<Background + Send + Sync> = Arc::new(background::constant(Vec3::zero()));
+    let lights = LightList::collect(&objects, Vec::new(), &background);
+    assert!(lights.len() == 3);
+    assert!((lights.distribution.pmf(0) - 0.8).abs() < 1e-8);
+    assert!((lights.distribution.pmf(1) - 0.2).abs() < 1e-8);
+    assert!(lights.distribution.pmf(2) == 0.0);
+
+    let p = Vec3::zero();
+    let n = 10000;
+    let above = (0..n).filter(|_| {
+        let sample = lights.sample_li(&p).unwrap();
+        assert!((sample.pdf - lights.pdf_li(&p, &sample.direction)).abs() < 1e-8);
+        // the point sampled is on the near side of either light
+        let q = p + sample.direction * sample.distance;
+        assert!(((q - Vec3::new(0.0, 2.0f64.copysign(q.y()), 0.0)).length() - 0.5).abs() < 1e-8);
+        assert!(sample.distance >= 1.5 - 1e-8 && sample.distance <= 3.75f64.sqrt() + 1e-8);
+        sample.direction.y() > 0.0
+    }).count();
+    assert!((above as f64 / n as f64 - 0.8).abs() < 0.02);
+
+    // the mixture density integrates to one over the sphere
+    let mut itor = (0..200000).map(|_| {
+        lights.pdf_li(&p, &sampling::random_3d_direction()) * 4.0 * PI
+    });
+    let (integral, _) = sampling::avstdev(&mut itor);
+    assert!((integral - 1.0).abs() < 0.05);
+
+    // and paths start on the lights in the same proportion
+    for _ in 0..100 {
+        let (_, sample) = lights.sample_le().unwrap();
+        assert!((sample.pdf_pos - lights.emission_pdf_pos(&sample.radiance)).abs() < 1e-8);
+    }
+}
+
+#[test]
+fn the_background_is_a_light() {
+    use background;
+
+    let background: Arc<Background + Send + Sync> = Arc::new(background::constant(Vec3::new(1.0, 1.0, 1.0)));
+    let lights = LightList::collect(&[], Vec::new(), &background);
+    assert!(lights.len() == 1);
+    assert!(lights.sample_le().is_none());
+    // but photons come from it, in through the sphere around the scene
+    let (_, photon) = lights.sample_photon().unwrap();
+    assert!(((photon.p - lights.world_center).length() - 1.0) >= -1e-8);
+    assert!((photon.p - lights.world_center).dot(&photon.direction) < 0.0);
+    assert!((photon.pdf_pos - 1.0 / PI).abs() < 1e-8);
+    assert!(photon.radiance == Vec3::new(1.0, 1.0, 1.0));
+    let sample = lights.sample_li(&Vec3::zero()).unwrap();
+    assert!(sample.distance == f64::INFINITY);
+    assert!((sample.pdf - 1.0 / (4.0 * PI)).abs() < 1e-8);
+    assert!(sample.radiance == Vec3::new(1.0, 1.0, 1.0));
+}
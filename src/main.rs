@@ -10,200 +10,246 @@ extern crate bincode;
 extern crate rayon;
 
 pub mod aabb;
+pub mod aov;
+pub mod ambient_occlusion;
+pub mod area_light;
 pub mod background;
+pub mod bdpt;
+pub mod bsdf_tracer;
 pub mod bvh;
 pub mod camera;
+pub mod csg;
+pub mod cuboid;
+pub mod cylinder;
+pub mod denoise;
+pub mod delta_light;
 pub mod deserialize;
 pub mod dielectric;
+pub mod direct_lighting;
 pub mod disc;
 pub mod emitter;
+pub mod environment_map;
 pub mod hitable;
+pub mod henyey_greenstein;
 pub mod hitable_list;
+pub mod instance;
+pub mod integrator;
+pub mod kd_tree;
 pub mod lambertian;
+pub mod light;
 pub mod material;
+pub mod matrix;
+pub mod medium;
 pub mod metal;
 pub mod mixture;
+pub mod mlt;
+pub mod motion;
+pub mod path_tracer;
+pub mod photon_mapping;
 // pub mod phong;
 pub mod plane;
+pub mod polynomial;
 pub mod random;
 pub mod ray;
 pub mod rectangle;
+pub mod render_settings;
 pub mod scene;
+pub mod sdf;
 pub mod sampling;
+pub mod sky_model;
 pub mod sphere;
+pub mod spectrum;
 pub mod sphere_geometry;
 pub mod triangle_mesh;
 pub mod vector;
+pub mod volume;
 pub mod tests;
+pub mod torus;
 pub mod ward;
 
-use aabb::AABB;
-use background::*;
+use aov::*;
+use background::Background;
 use bvh::BVH;
 use camera::Camera;
+use denoise::*;
 use deserialize::*;
-use disc::*;
 use getopts::Options;
-use hitable::*;
-use rand::Rng;
-use random::*;
-use ray::Ray;
+use hitable::Hitable;
+use integrator::*;
+use light::LightList;
+use path_tracer::PathTracer;
 use vector::Vec3;
-    
+use volume::GlobalVolume;
+
 use std::cmp;
 use std::env;
 use std::fs::File;
 use std::io::BufReader;
 use std::io::BufWriter;
 use std::io::Write;
+use std::sync::Arc;
 
 use rayon::prelude::*;
 
 //////////////////////////////////////////////////////////////////////////////
 
-fn color(ray: &Ray, world: &Hitable,
-         background: &Background,
-         lights: &Vec<AABB>) -> Vec3 where
-{
-    let mut current_ray = *ray;
-    let mut current_attenuation = Vec3::new(1.0, 1.0, 1.0);
-        
-    for _depth in 0..50 {
-        if current_attenuation.length() < 1e-8 {
-            return Vec3::new(0.0, 0.0, 0.0)
-        }
-        
-        match world.hit(&current_ray, 0.00001, 1e20) {
-            None => {
-                let unit_direction = vector::unit_vector(&current_ray.direction());
-                return background.get_background(&unit_direction) * current_attenuation;
-            },
-            Some(hr) => {
-                if !hr.material.wants_importance_sampling() || lights.len() == 0 {
-                    match hr.material.scatter(&current_ray, &hr) {
-                        material::Scatter::Bounce(next_attenuation, scattered) => {
-                            current_attenuation = current_attenuation * next_attenuation;
-                            current_ray = scattered;
-                        },
-                        material::Scatter::Emit(emission) => {
-                            // println!("Hit light!");
-                            return emission * current_attenuation;
-                        },
-                        material::Scatter::Absorb => {
-                            return Vec3::new(0.0, 0.0, 0.0)
-                        }
-                    }
-                    continue;
-                }
-
-                let this_hemi = Disc::new(hr.p, hr.normal, 1.0);
-
-                let light = {
-                    let chosen_light = &lights[rand_range(0, lights.len())];
-                    let chosen_disc = chosen_light.project_to_disc_on_sphere(&hr.p);
-
-                    // sample from that disc
-                    let gx_sample        = this_hemi.hemi_disc_subtended_angle(&chosen_disc);
-                    let gx               = gx_sample.0;
-                    let sample_direction = gx_sample.1;
-
-                    if gx == 0.0 {
-                        (0.0, sample_direction)
-                    } else {
-                        (2.0 * std::f64::consts::PI / gx_sample.0, sample_direction)
-                    }
-                };
-                let scatter = {
-                    match hr.material.scatter(&current_ray, &hr) {
-                        material::Scatter::Bounce(_attenuation, scattered) => {
-                            (hr.material.bsdf(&current_ray, &scattered, &hr.normal),
-                             scattered.direction())
-                        }
-                        material::Scatter::Emit(_emission) => {
-                            panic!("Whaaaaa emit?!")
-                        },
-                        material::Scatter::Absorb => {
-                            panic!("Whaaaaa absorb?!")
-                        }
-                    }
-                };
-                let light_p = if light.0 < 1e-4 { 0.0 } else { light.0 };
-                let light_d = light.1;
-                let scatter_p = if scatter.0 < 1e-4 { 0.0 } else { scatter.0 };
-                let scatter_d = scatter.1;
-
-                // Veach's balance heuristic for a one-sample MIS estimator
-                // gives these weights:
-                let s         = light_p + scatter_p;
-                let light_w   = light_p   / s;
-                let scatter_w = scatter_p / s;
-
-                // println!("{} {}", light_p, scatter_p);
-
-                // the classic Veach one-sample MIS estimator is
-                //    (light_w / light_p) * light_f + (scatter_w / scatter_p) * scatter_f
-
-                let next_values = if (light_p > 0.0) && rand_double() < 0.5 { // sample from lights
-                    ((light_w / light_p)     * 2.0, Ray::new(hr.p, light_d))
-                } else if scatter_p > 0.0 {
-                    ((scatter_w / scatter_p) * 2.0, Ray::new(hr.p, scatter_d))
-                } else {
-                    return Vec3::new(0.0, 0.0, 0.0);
-                };
-                let albedo = hr.material.albedo(&current_ray, &next_values.1, &hr.normal);
-                current_ray = next_values.1;
-                current_attenuation = current_attenuation * albedo * next_values.0;
-            }
-        }
-    }
-    current_attenuation
-}
-
 //////////////////////////////////////////////////////////////////////////////
 // my own bastardized version of a float file format, horrendously inefficient
 
-fn write_image_to_file(image: &Vec<Vec<Vec3>>, samples_so_far: usize, subsample: usize, file_prefix: &String)
+fn write_image_to_file(image: &Vec<Vec<Vec3>>, counts: &[Vec<usize>], subsample: usize, file_prefix: &String)
 {
     println!("Writing output to {}",
              format!("{}.linear_rgb", file_prefix));
     let mut f = BufWriter::new(File::create(format!("{}.linear_rgb", file_prefix)).unwrap());
     let ny = image.len()/subsample;
     let nx = image[0].len()/subsample;
-    let ns = samples_so_far as f64;
     f.write_fmt(format_args!("{} {}\n", nx, ny)).unwrap();
 
     for super_j in (0..ny).rev() {
         for super_i in 0..nx {
             let mut super_pixel = Vec3::zero();
+            let mut samples = 0;
             let top   = cmp::min(image.len(),    (super_j+1)*subsample);
             let right = cmp::min(image[0].len(), (super_i+1)*subsample);
-            let h = top   - super_j*subsample;
-            let w = right - super_i*subsample;
             for j in (super_j*subsample..top).rev() {
                 for i in super_i*subsample..right {
                     super_pixel = super_pixel + image[j][i];
+                    samples += counts[j][i];
                 }
             }
-            let mut out_col = super_pixel / (ns * (w as f64) * (h as f64));
+            let out_col = super_pixel / cmp::max(samples, 1) as f64;
             f.write_fmt(format_args!("{} {} {}\n", out_col[0], out_col[1], out_col[2])).unwrap();
         }
     }
 }
 
-fn update_all_pixels(output_image: &mut Vec<Vec<Vec3>>,
+// a heatmap of how many samples each pixel got, relative to the most
+// any pixel got, in the same format as the image.
+fn write_heatmap_to_file(counts: &[Vec<usize>], file_prefix: &str)
+{
+    let file_name = format!("{}.samples.linear_rgb", file_prefix);
+    println!("Writing sample counts to {}", file_name);
+    let mut f = BufWriter::new(File::create(file_name).unwrap());
+    let max_count = counts.iter().flat_map(|row| row.iter()).fold(1, |a, b| cmp::max(a, *b));
+    f.write_fmt(format_args!("{} {}\n", counts[0].len(), counts.len())).unwrap();
+    for row in counts.iter().rev() {
+        for count in row.iter() {
+            let v = *count as f64 / max_count as f64;
+            f.write_fmt(format_args!("{} {} {}\n", v, v, v)).unwrap();
+        }
+    }
+}
+
+// the per-pixel averages of an image of sums
+fn average_image(image: &[Vec<Vec3>], counts: &[Vec<usize>]) -> Vec<Vec<Vec3>>
+{
+    image.iter().zip(counts.iter()).map(|(row, count_row)| {
+        row.iter().zip(count_row.iter()).map(|(p, c)| *p / cmp::max(*c, 1) as f64).collect()
+    }).collect()
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// adaptive sampling: besides the sum of its samples, every pixel keeps
+// the sum of their squared luminances and how many there were. Once a
+// pixel has MIN_ADAPTIVE_SAMPLES, it stops getting new ones as soon as
+// the 95% confidence interval of its mean luminance is within
+// threshold of the mean, relative to it.
+
+const MIN_ADAPTIVE_SAMPLES: usize = 16;
+
+struct PixelStatistics {
+    sum: Vec<Vec<Vec3>>,
+    sum_squares: Vec<Vec<f64>>,
+    counts: Vec<Vec<usize>>,
+    // sums of the output variables, one image per entry of AOV_NAMES,
+    // if they're wanted
+    aovs: Vec<Vec<Vec<Vec3>>>,
+    // the object id each pixel's first sample saw, if they're wanted
+    object_ids: Vec<Vec<Option<usize>>>
+}
+
+impl PixelStatistics {
+    fn new(nx: usize, ny: usize, with_aovs: bool) -> PixelStatistics {
+        let n_aovs = if with_aovs { AOV_NAMES.len() } else { 0 };
+        PixelStatistics {
+            sum: vec![vec![Vec3::zero(); nx]; ny],
+            sum_squares: vec![vec![0.0; nx]; ny],
+            counts: vec![vec![0; nx]; ny],
+            aovs: vec![vec![vec![Vec3::zero(); nx]; ny]; n_aovs],
+            object_ids: if with_aovs { vec![vec![None; nx]; ny] } else { Vec::new() }
+        }
+    }
+
+    fn add_aovs(&mut self, i: usize, j: usize, ray: &ray::Ray, context: &RenderContext) {
+        if self.aovs.is_empty() {
+            return;
+        }
+        let (aovs, object_id) = first_hit_aovs(ray, context);
+        for (k, v) in aovs.iter().enumerate() {
+            self.aovs[k][j][i] = self.aovs[k][j][i] + *v;
+        }
+        self.object_ids[j][i].get_or_insert(object_id);
+    }
+
+    fn add(&mut self, i: usize, j: usize, color: &Vec3) {
+        let l = vector::luminance(color);
+        self.sum[j][i] = self.sum[j][i] + *color;
+        self.sum_squares[j][i] += l * l;
+        self.counts[j][i] += 1;
+    }
+
+    fn converged(&self, i: usize, j: usize, threshold: f64) -> bool {
+        let n = self.counts[j][i];
+        if n < MIN_ADAPTIVE_SAMPLES {
+            return false;
+        }
+        let n = n as f64;
+        let mean = vector::luminance(&self.sum[j][i]) / n;
+        let variance = ((self.sum_squares[j][i] / n - mean * mean) * n / (n - 1.0)).max(0.0);
+        1.96 * (variance / n).sqrt() <= threshold * mean
+    }
+}
+
+fn update_all_pixels(stats: &mut PixelStatistics,
                      camera: &Camera,
-                     bvh_world: &Hitable,
-                     background: &Background,
-                     lights: &Vec<AABB>,
+                     integrator: &Integrator,
+                     context: &RenderContext,
                      nx: usize,
                      ny: usize,
-                     rng: &mut rand::ThreadRng) {
+                     adaptive_threshold: Option<f64>) {
     for j in (0..ny).rev() {
         for i in 0..nx {
-            let u = ((i as f64) + rng.gen::<f64>()) / (nx as f64);
-            let v = ((j as f64) + rng.gen::<f64>()) / (ny as f64);
+            if let Some(threshold) = adaptive_threshold {
+                if stats.converged(i, j, threshold) {
+                    continue;
+                }
+            }
+            let u = ((i as f64) + random::rand_double()) / (nx as f64);
+            let v = ((j as f64) + random::rand_double()) / (ny as f64);
             let r = camera.get_ray(u, v);
-            output_image[j][i] = output_image[j][i] + color(&r, bvh_world, background, lights);
+            spectrum::begin_path(if context.settings.spectral {
+                Some(spectrum::sample_wavelengths())
+            } else {
+                None
+            });
+            let color = spectrum::end_path(&integrator.color(&r, context));
+            stats.add(i, j, &color);
+            stats.add_aovs(i, j, &r, context);
+        }
+    }
+}
+
+// output variables for integrators that place their own samples
+fn update_all_aovs(stats: &mut PixelStatistics,
+                   camera: &Camera,
+                   context: &RenderContext,
+                   nx: usize,
+                   ny: usize) {
+    for j in 0..ny {
+        for i in 0..nx {
+            let u = ((i as f64) + random::rand_double()) / (nx as f64);
+            let v = ((j as f64) + random::rand_double()) / (ny as f64);
+            stats.add_aovs(i, j, &camera.get_ray(u, v), context);
         }
     }
 }
@@ -213,7 +259,11 @@ struct ImageSummaries {
     w: usize,
     h: usize,
     s: usize,
-    data: Vec<Vec<Vec3>>
+    data: Vec<Vec<Vec3>>,
+    // samples taken per pixel, which differ under adaptive sampling
+    counts: Vec<Vec<usize>>,
+    aovs: Vec<Vec<Vec<Vec3>>>,
+    object_ids: Vec<Vec<Option<usize>>>
 }
 
 fn combine_summaries(summary1: &ImageSummaries,
@@ -231,6 +281,15 @@ fn combine_summaries(summary1: &ImageSummaries,
                        summary1.data.len(), summary2.data.len()));
     }
     let mut result = Vec::new();
+    let mut counts = Vec::new();
+    let aovs = summary1.aovs.iter().zip(summary2.aovs.iter()).map(|(aov1, aov2)| {
+        aov1.iter().zip(aov2.iter()).map(|(row1, row2)| {
+            row1.iter().zip(row2.iter()).map(|(v1, v2)| *v1 + *v2).collect()
+        }).collect()
+    }).collect();
+    let object_ids = summary1.object_ids.iter().zip(summary2.object_ids.iter()).map(|(row1, row2)| {
+        row1.iter().zip(row2.iter()).map(|(id1, id2)| id1.or(*id2)).collect()
+    }).collect();
     for i in 0..summary1.data.len() {
         let l1 = summary1.data[i].len();
         let l2 = summary2.data[i].len();
@@ -241,13 +300,19 @@ fn combine_summaries(summary1: &ImageSummaries,
         }
         let row1 = summary1.data[i].iter();
         let row2 = summary2.data[i].iter();
-        result.push(row1.zip(row2).map(|(v1, v2)| *v1 + *v2).collect())
+        result.push(row1.zip(row2).map(|(v1, v2)| *v1 + *v2).collect());
+        let counts1 = summary1.counts[i].iter();
+        let counts2 = summary2.counts[i].iter();
+        counts.push(counts1.zip(counts2).map(|(c1, c2)| c1 + c2).collect());
     }
     ImageSummaries {
         w: summary1.w,
         h: summary1.h,
         s: summary1.s + summary2.s,
-        data: result
+        data: result,
+        counts: counts,
+        aovs: aovs,
+        object_ids: object_ids
     }
 }
 
@@ -263,35 +328,76 @@ fn write_image(args: &Args)
     let json_value = serde_json::from_reader(br).unwrap();
 
     let scene          = deserialize_scene(&json_value).unwrap();
-    let background     = scene.background;
+    let mut background: Arc<Background + Send + Sync> = Arc::from(scene.background);
     let camera         = scene.camera;
-    let lights: Vec<_> = scene.object_list
-        .iter()
-        .map(|h| h.importance_distribution())
-        .filter(|h| h.is_some())
-        .map(|h| h.unwrap())
-        .collect();
+    let mut settings   = scene.render_settings;
+    if let Some(d) = args.min_depth {
+        settings.min_depth = d;
+    }
+    if let Some(d) = args.max_depth {
+        settings.max_depth = d;
+    }
+    if args.spectral {
+        settings.spectral = true;
+    }
+    settings.shutter_open = camera.shutter_open;
+    settings.shutter_close = camera.shutter_close;
+    // the command line wins over the scene file
+    let integrator     = match args.integrator {
+        Some(ref name) => deserialize_integrator(&serde_json::Value::String(name.clone()))
+            .unwrap_or_else(|| panic!("Unknown integrator {}", name)),
+        None => scene.integrator.unwrap_or_else(|| PathTracer::new())
+    };
+    if settings.spectral {
+        if !integrator.supports_spectral() {
+            panic!("This integrator can't render in spectral mode, use path, bsdf, bdpt or direct_lighting");
+        }
+        background = Arc::new(spectrum::UpsampledBackground { background: background });
+    }
+    let mut object_list = scene.object_list;
+    let with_aovs = args.aovs || args.denoise;
+    if with_aovs {
+        object_list = object_list.into_iter().enumerate().map(|(i, h)| {
+            Box::new(Identified::new(h, i + 1)) as Box<Hitable + Send + Sync>
+        }).collect();
+    }
+    let lights = LightList::collect(&object_list, scene.lights, &background);
     
-    let bvh_world      = BVH::build(scene.object_list);
+    let bvh_world      = match scene.medium {
+        Some(medium) => Box::new(GlobalVolume::new(BVH::build(object_list), medium)),
+        None => BVH::build(object_list)
+    };
     let ny             = args.h.unwrap_or(200);
     let nx             = args.w.unwrap_or_else(|| ((ny as f64) * camera.params.aspect).round() as usize);
     let n_threads      = args.n.unwrap_or(1);
     let ns             = args.s.unwrap_or(100) / n_threads;
-    let background_ref = &*background;
-    let bvh_world_ref  = &*bvh_world;
+    let context        = RenderContext {
+        world: &*bvh_world,
+        background: &*background,
+        lights: &lights,
+        settings: &settings
+    };
+    let integrator_ref = &*integrator;
     println!("With {} threads", n_threads);
 
     let output_summaries: Vec<_> = (0..n_threads).into_par_iter().map(|i| {
-        let mut output_image = Vec::<Vec<Vec3>>::new();
-        for _j in 0..ny {
-            output_image.push(vec![Vec3::zero(); nx]);
-        }
-        let mut rng = rand::thread_rng();
-            
+        let mut stats = PixelStatistics::new(nx, ny, with_aovs);
         for s in 1..ns+1 {
-            update_all_pixels(&mut output_image,
-                              &camera, bvh_world_ref, background_ref, &lights,
-                              nx, ny, &mut rng);
+            let pass_integrator = integrator_ref.begin_pass(s-1, &context);
+            let pass_integrator = pass_integrator.as_ref().map_or(integrator_ref, |x| &**x);
+            if pass_integrator.render_pass(&mut stats.sum, &camera, &context) {
+                // these passes cover the whole image, there's nothing to adapt
+                for row in stats.counts.iter_mut() {
+                    for count in row.iter_mut() {
+                        *count += 1;
+                    }
+                }
+                update_all_aovs(&mut stats, &camera, &context, nx, ny);
+            } else {
+                update_all_pixels(&mut stats,
+                                  &camera, pass_integrator, &context,
+                                  nx, ny, args.adaptive_threshold);
+            }
             if i == 0 {
                 eprint!("\r                          \r{} / {} done", s, ns);
             }
@@ -304,7 +410,10 @@ fn write_image(args: &Args)
             w: nx,
             h: ny,
             s: ns,
-            data: output_image
+            data: stats.sum,
+            counts: stats.counts,
+            aovs: stats.aovs,
+            object_ids: stats.object_ids
         }
     }).collect();
     
@@ -313,8 +422,39 @@ fn write_image(args: &Args)
         summary = combine_summaries(&summary, &new_summary);
     }
 
-    println!("Using {} samples", summary.s);
-    write_image_to_file(&summary.data, summary.s, 1, &output_name);
+    let total_samples: usize = summary.counts.iter().map(|row| row.iter().sum::<usize>()).sum();
+    println!("Using {} samples ({:.1} per pixel on average)", summary.s,
+             total_samples as f64 / (nx * ny) as f64);
+    write_image_to_file(&summary.data, &summary.counts, 1, &output_name);
+    if args.heatmap {
+        write_heatmap_to_file(&summary.counts, &output_name);
+    }
+    if args.aovs {
+        for (name, aov) in AOV_NAMES.iter().zip(summary.aovs.iter()) {
+            write_image_to_file(aov, &summary.counts, 1, &format!("{}.{}", output_name, name));
+        }
+        let object_ids: Vec<Vec<Vec3>> = summary.object_ids.iter().map(|row| {
+            row.iter().map(|id| {
+                let id = id.unwrap_or(0) as f64;
+                Vec3::new(id, id, id)
+            }).collect()
+        }).collect();
+        write_image_to_file(&object_ids, &vec![vec![1; nx]; ny], 1, &format!("{}.object_id", output_name));
+    }
+    if args.denoise {
+        let image = average_image(&summary.data, &summary.counts);
+        let depth = average_image(&summary.aovs[0], &summary.counts);
+        let normal = average_image(&summary.aovs[1], &summary.counts);
+        let albedo = average_image(&summary.aovs[2], &summary.counts);
+        let features = Features {
+            albedo: &albedo,
+            normal: &normal,
+            depth: &depth
+        };
+        let file_name = format!("{}.denoised.linear_rgb", output_name);
+        println!("Writing denoised output to {}", file_name);
+        write_linear_rgb(&denoise(&image, &features, &DenoiseSettings::default()), &file_name);
+    }
 }
 
 //////////////////////////////////////////////////////////////////////////////
@@ -326,6 +466,14 @@ struct Args {
     pub n: Option<usize>,
     pub o: Option<String>,
     pub i: Option<String>,
+    pub integrator: Option<String>,
+    pub min_depth: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub spectral: bool,
+    pub adaptive_threshold: Option<f64>,
+    pub heatmap: bool,
+    pub aovs: bool,
+    pub denoise: bool,
     pub parallel: bool
 }
 
@@ -342,6 +490,14 @@ fn main() {
     opts.optopt("n", "nthreads", "number of threads, default 1", "NAME");
     opts.optopt("o", "output", "set output file name", "NAME");
     opts.optopt("i", "input", "set input file name", "NAME");
+    opts.optopt("", "integrator", "light transport algorithm: path (default), bsdf, bdpt, mlt, photon_mapping, direct_lighting or ambient_occlusion", "NAME");
+    opts.optopt("", "min-depth", "bounces before russian roulette can end a path, default 3", "N");
+    opts.optopt("", "max-depth", "maximum number of bounces, default 50", "N");
+    opts.optflag("", "spectral", "trace sampled wavelengths instead of RGB");
+    opts.optopt("", "adaptive-threshold", "stop sampling a pixel once its 95% confidence interval is within this fraction of its mean", "X");
+    opts.optflag("", "heatmap", "also write the number of samples per pixel to <output>.samples.linear_rgb");
+    opts.optflag("", "aovs", "also write depth, normal, albedo, position and object_id images to <output>.<name>.linear_rgb");
+    opts.optflag("", "denoise", "also write a denoised image to <output>.denoised.linear_rgb");
     opts.optflag("p", "parallel", "write out pixel statistics, suited for parallel processing");
 
     opts.optflag("?", "help", "print this help menu");
@@ -359,6 +515,14 @@ fn main() {
 
         o: matches.opt_str("o"),
         i: matches.opt_str("i"),
+        integrator: matches.opt_str("integrator"),
+        min_depth: matches.opt_str("min-depth").and_then(|x| x.parse::<usize>().ok()),
+        max_depth: matches.opt_str("max-depth").and_then(|x| x.parse::<usize>().ok()),
+        spectral: matches.opt_present("spectral"),
+        adaptive_threshold: matches.opt_str("adaptive-threshold").and_then(|x| x.parse::<f64>().ok()),
+        heatmap: matches.opt_present("heatmap"),
+        aovs: matches.opt_present("aovs"),
+        denoise: matches.opt_present("denoise"),
         parallel: matches.opt_present("p")
     }));
 }
@@ -1,6 +1,7 @@
 use ray::Ray;
 use vector::Vec3;
 use hitable::HitRecord;
+use spectrum::Spectrum;
 use std::fmt;
 
 //////////////////////////////////////////////////////////////////////////////
@@ -24,6 +25,13 @@ pub trait Material: Send + Sync {
     fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Scatter;
     fn debug(&self, f: &mut fmt::Formatter) -> fmt::Result;
     fn is_emitter(&self) -> bool;
+    // radiance leaving the surface; zero for everything but emitters.
+    // In spectral mode, at the current path's wavelengths.
+    fn emission(&self) -> Vec3;
+    // all of the emission, for lights to keep
+    fn emission_spectrum(&self) -> Spectrum {
+        Spectrum::from_rgb(&self.emission())
+    }
 }
 
 impl fmt::Debug for Material {
@@ -1,15 +1,277 @@
-use vector::V;
+use aabb::AABB;
+use ray::Ray;
+use vector::*;
 
-use std::Ops;
+use std::ops;
 
-pub struct M {
-    pub m: [f64; 16];
+//////////////////////////////////////////////////////////////////////////////
+// 4x4 matrices, for affine transforms of points (with w = 1), vectors
+// (w = 0) and normals. Stored by rows, and serialized as 4 rows of 4,
+// like scene files write them.
+//
+// Transforms compose by multiplying: (a * b) applies b first, then a.
 
-    pub fn i() -> M {
-        M { m: [1.0, 0.0, 0.0, 0.0,
-                0.0, 1.0, 0.0, 0.0,
-                0.0, 0.0, 1.0, 0.0,
-                0.0, 0.0, 0.0, 1.0] }
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(transparent)]
+pub struct Matrix {
+    pub m: [[f64; 4]; 4]
+}
+
+impl Matrix {
+    pub fn new(m: [[f64; 4]; 4]) -> Matrix {
+        Matrix { m: m }
+    }
+
+    pub fn identity() -> Matrix {
+        Matrix::new([[1.0, 0.0, 0.0, 0.0],
+                     [0.0, 1.0, 0.0, 0.0],
+                     [0.0, 0.0, 1.0, 0.0],
+                     [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    pub fn translate(offset: &Vec3) -> Matrix {
+        Matrix::new([[1.0, 0.0, 0.0, offset.x()],
+                     [0.0, 1.0, 0.0, offset.y()],
+                     [0.0, 0.0, 1.0, offset.z()],
+                     [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    pub fn scale(factors: &Vec3) -> Matrix {
+        Matrix::new([[factors.x(), 0.0, 0.0, 0.0],
+                     [0.0, factors.y(), 0.0, 0.0],
+                     [0.0, 0.0, factors.z(), 0.0],
+                     [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    // by theta radians about axis, counterclockwise looking down it
+    pub fn rotate(axis: &Vec3, theta: f64) -> Matrix {
+        let k = unit_vector(axis);
+        let (sin, cos) = theta.sin_cos();
+        // the columns are where the axes go
+        let x = rotate_sincos(&Vec3::new(1.0, 0.0, 0.0), &k, sin, cos);
+        let y = rotate_sincos(&Vec3::new(0.0, 1.0, 0.0), &k, sin, cos);
+        let z = rotate_sincos(&Vec3::new(0.0, 0.0, 1.0), &k, sin, cos);
+        Matrix::from_frame(&x, &y, &z, &Vec3::zero())
+    }
+
+    // puts the origin at look_from, facing look_at down -z with +y
+    // towards vup, the way the camera looks. Its inverse takes the
+    // world into that view.
+    pub fn look_at(look_from: &Vec3, look_at: &Vec3, vup: &Vec3) -> Matrix {
+        let w = unit_vector(&(*look_from - *look_at));
+        let u = unit_vector(&cross(vup, &w));
+        let v = cross(&w, &u);
+        Matrix::from_frame(&u, &v, &w, look_from)
+    }
+
+    // a rigid transform taking the origin to origin and +z along axis,
+    // for shapes that are simplest set up around the z axis
+    pub fn frame_along(origin: &Vec3, axis: &Vec3) -> Matrix {
+        let z = unit_vector(axis);
+        let (x, y) = tangent_space(&z);
+        Matrix::from_frame(&x, &y, &z, origin)
+    }
+
+    // takes the x, y and z axes to x, y and z, and the origin to origin
+    pub fn from_frame(x: &Vec3, y: &Vec3, z: &Vec3, origin: &Vec3) -> Matrix {
+        Matrix::new([[x.x(), y.x(), z.x(), origin.x()],
+                     [x.y(), y.y(), z.y(), origin.y()],
+                     [x.z(), y.z(), z.z(), origin.z()],
+                     [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    pub fn transpose(&self) -> Matrix {
+        let mut result = [[0.0; 4]; 4];
+        for (i, row) in result.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = self.m[j][i];
+            }
+        }
+        Matrix::new(result)
+    }
+
+    // Gauss-Jordan elimination with partial pivoting. None when the
+    // matrix is singular.
+    pub fn inverse(&self) -> Option<Matrix> {
+        let mut a = self.m;
+        let mut inv = Matrix::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap()).unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let d = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= d;
+                inv[col][j] *= d;
+            }
+            for i in 0..4 {
+                if i != col {
+                    let f = a[i][col];
+                    for j in 0..4 {
+                        a[i][j] -= f * a[col][j];
+                        inv[i][j] -= f * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Matrix::new(inv))
+    }
+
+    pub fn transform_point(&self, p: &Vec3) -> Vec3 {
+        let m = &self.m;
+        let x = m[0][0] * p.x() + m[0][1] * p.y() + m[0][2] * p.z() + m[0][3];
+        let y = m[1][0] * p.x() + m[1][1] * p.y() + m[1][2] * p.z() + m[1][3];
+        let z = m[2][0] * p.x() + m[2][1] * p.y() + m[2][2] * p.z() + m[2][3];
+        let w = m[3][0] * p.x() + m[3][1] * p.y() + m[3][2] * p.z() + m[3][3];
+        if w == 1.0 { Vec3::new(x, y, z) } else { Vec3::new(x, y, z) / w }
+    }
+
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+                  m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+                  m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z())
+    }
+
+    // normals go through the inverse transpose; this is the inverse, so
+    // that callers who have it don't invert again. Not normalized.
+    pub fn transform_normal_by_inverse(inverse: &Matrix, n: &Vec3) -> Vec3 {
+        inverse.transpose().transform_vector(n)
+    }
+
+    // None if the matrix can't be inverted
+    pub fn transform_normal(&self, n: &Vec3) -> Option<Vec3> {
+        self.inverse().map(|inverse| Matrix::transform_normal_by_inverse(&inverse, n))
+    }
+
+    // an affine map keeps the ray's parameter: the point at t on the
+    // transformed ray is the transformed point at t
+    pub fn transform_ray(&self, r: &Ray) -> Ray {
+        Ray::with_time(self.transform_point(&r.origin()), self.transform_vector(&r.direction()), r.time())
+    }
+
+    // the box around the transformed corners of b
+    pub fn transform_aabb(&self, b: &AABB) -> AABB {
+        let (lo, hi) = (b.min(), b.max());
+        let corners: Vec<Vec3> = (0..8).map(|i| {
+            self.transform_point(&Vec3::new(if i & 1 == 0 { lo.x() } else { hi.x() },
+                                            if i & 2 == 0 { lo.y() } else { hi.y() },
+                                            if i & 4 == 0 { lo.z() } else { hi.z() }))
+        }).collect();
+        AABB::from_points(&corners)
+    }
+}
+
+impl ops::Mul for Matrix {
+    type Output = Matrix;
+
+    fn mul(self, rhs: Matrix) -> Matrix {
+        let mut result = [[0.0; 4]; 4];
+        for (i, row) in result.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Matrix::new(result)
     }
+}
+
+// element by element
+pub fn lerp(a: &Matrix, b: &Matrix, u: f64) -> Matrix {
+    let mut result = [[0.0; 4]; 4];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, x) in row.iter_mut().enumerate() {
+            *x = a.m[i][j] * (1.0 - u) + b.m[i][j] * u;
+        }
+    }
+    Matrix::new(result)
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn matrices_invert_and_transform() {
+    // scale by (2, 3, 4), then move by (1, 2, 3)
+    let m = Matrix::new([[2.0, 0.0, 0.0, 1.0],
+                         [0.0, 3.0, 0.0, 2.0],
+                         [0.0, 0.0, 4.0, 3.0],
+                         [0.0, 0.0, 0.0, 1.0]]);
+    let inv = m.inverse().unwrap();
+    let close = |a: &Matrix, b: &Matrix| {
+        (0..4).all(|i| (0..4).all(|j| (a.m[i][j] - b.m[i][j]).abs() < 1e-12))
+    };
+    assert!(close(&(m * inv), &Matrix::identity()));
+    assert!(close(&(inv * m), &Matrix::identity()));
+
+    let p = Vec3::new(1.0, 1.0, 1.0);
+    assert!(m.transform_point(&p) == Vec3::new(3.0, 5.0, 7.0));
+    assert!(m.transform_vector(&p) == Vec3::new(2.0, 3.0, 4.0));
+    assert!((inv.transform_point(&m.transform_point(&p)) - p).length() < 1e-12);
+
+    // normals stay perpendicular to the surface
+    let tangent = Vec3::new(1.0, -1.0, 0.0);
+    let normal = Vec3::new(1.0, 1.0, 0.0);
+    let n = Matrix::transform_normal_by_inverse(&inv, &normal);
+    assert!(n.dot(&m.transform_vector(&tangent)).abs() < 1e-12);
+
+    // a rotation mixes its rows, and still inverts
+    let r = Matrix::new([[0.0, -1.0, 0.0, 0.0],
+                         [1.0, 0.0, 0.0, 0.0],
+                         [0.0, 0.0, 1.0, 0.0],
+                         [0.0, 0.0, 0.0, 1.0]]);
+    assert!(close(&r.inverse().unwrap(), &r.transpose()));
+    assert!(Matrix::new([[0.0; 4]; 4]).inverse().is_none());
+}
+
+#[test]
+fn matrices_build_transforms() {
+    use std::f64::consts::PI;
+
+    let close = |a: &Vec3, b: &Vec3| (*a - *b).length() < 1e-12;
+    let p = Vec3::new(1.0, 2.0, 3.0);
+    assert!(close(&Matrix::translate(&Vec3::new(1.0, 1.0, 1.0)).transform_point(&p), &Vec3::new(2.0, 3.0, 4.0)));
+    assert!(close(&Matrix::translate(&Vec3::new(1.0, 1.0, 1.0)).transform_vector(&p), &p));
+    assert!(close(&Matrix::scale(&Vec3::new(2.0, 1.0, 0.5)).transform_point(&p), &Vec3::new(2.0, 2.0, 1.5)));
+
+    // a quarter turn about z takes x to y
+    let r = Matrix::rotate(&Vec3::new(0.0, 0.0, 2.0), PI / 2.0);
+    assert!(close(&r.transform_vector(&Vec3::new(1.0, 0.0, 0.0)), &Vec3::new(0.0, 1.0, 0.0)));
+    assert!(close(&r.transform_vector(&Vec3::new(0.0, 0.0, 1.0)), &Vec3::new(0.0, 0.0, 1.0)));
+
+    // scale, then move
+    let m = Matrix::translate(&Vec3::new(0.0, 0.0, 1.0)) * Matrix::scale(&Vec3::new(2.0, 2.0, 2.0));
+    assert!(close(&m.transform_point(&p), &Vec3::new(2.0, 4.0, 7.0)));
+
+    // looking from (0, 0, 5) at the origin: -z points at it
+    let l = Matrix::look_at(&Vec3::new(0.0, 0.0, 5.0), &Vec3::zero(), &Vec3::new(0.0, 1.0, 0.0));
+    assert!(close(&l.transform_point(&Vec3::new(0.0, 0.0, -5.0)), &Vec3::zero()));
+    assert!(close(&l.transform_vector(&Vec3::new(0.0, 1.0, 0.0)), &Vec3::new(0.0, 1.0, 0.0)));
+
+    // a unit cube turned 45 degrees about y fits in a wider box
+    let b = Matrix::rotate(&Vec3::new(0.0, 1.0, 0.0), PI / 4.0)
+        .transform_aabb(&AABB::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0)));
+    assert!(close(&b.max(), &Vec3::new(2.0f64.sqrt(), 1.0, 2.0f64.sqrt())));
+    assert!(close(&b.min(), &-b.max()));
+
+    // frames are rigid and right-handed
+    let f = Matrix::frame_along(&p, &Vec3::new(1.0, 1.0, 0.0));
+    let (x, y, z) = (f.transform_vector(&Vec3::new(1.0, 0.0, 0.0)),
+                     f.transform_vector(&Vec3::new(0.0, 1.0, 0.0)),
+                     f.transform_vector(&Vec3::new(0.0, 0.0, 1.0)));
+    assert!(close(&f.transform_point(&Vec3::zero()), &p));
+    assert!(close(&z, &unit_vector(&Vec3::new(1.0, 1.0, 0.0))));
+    assert!(close(&cross(&x, &y), &z) && x.dot(&y).abs() < 1e-12);
+
+    // scaling normals the other way keeps them perpendicular
+    let s = Matrix::scale(&Vec3::new(1.0, 2.0, 1.0));
+    let n = s.transform_normal(&Vec3::new(1.0, 1.0, 0.0)).unwrap();
+    assert!(n.dot(&s.transform_vector(&Vec3::new(1.0, -1.0, 0.0))).abs() < 1e-12);
 
+    // and they go to and from json as rows
+    let json = ::serde_json::to_value(m).unwrap();
+    assert!(json[2] == ::serde_json::to_value([0.0, 0.0, 2.0, 1.0]).unwrap());
+    assert!(::serde_json::from_value::<Matrix>(json).unwrap() == m);
 }
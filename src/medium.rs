@@ -0,0 +1,185 @@
+use aabb::AABB;
+use henyey_greenstein::HenyeyGreenstein;
+use material::Material;
+use random::*;
+use ray::Ray;
+use vector::Vec3;
+
+//////////////////////////////////////////////////////////////////////////////
+// participating media. A medium decides where along a ray light
+// scatters, and hands out the phase function to scatter with.
+//
+// Absorption is folded into the phase function's color, as the
+// single-scattering albedo sigma_s / (sigma_a + sigma_s): a scattering
+// event is really a collision, which scatters with that probability
+// and absorbs otherwise. Carrying the probability as a weight instead
+// keeps the path alive.
+
+pub trait Medium: Send + Sync {
+    // the parameter along r of the first collision in (t_min, t_max),
+    // if there is one. Rays that make it through have been transmitted.
+    fn sample_distance(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<f64>;
+    fn phase_function(&self) -> &Material;
+}
+
+// distance along r (in units of t) to an exponentially distributed
+// collision with a medium of extinction sigma_t
+fn sample_exponential(r: &Ray, sigma_t: f64) -> f64 {
+    -(1.0 - rand_double()).ln() / (sigma_t * r.direction().length())
+}
+
+fn phase_function(sigma_a: f64, sigma_s: f64, g: f64, color: &Vec3) -> Box<Material> {
+    let sigma_t = sigma_a + sigma_s;
+    let albedo = if sigma_t > 0.0 { sigma_s / sigma_t } else { 0.0 };
+    HenyeyGreenstein::new(g, &(*color * albedo))
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+pub struct Homogeneous {
+    pub sigma_t: f64,
+    pub phase_function: Box<Material>
+}
+
+impl Homogeneous {
+    pub fn new(sigma_a: f64, sigma_s: f64, g: f64, color: &Vec3) -> Box<Medium> {
+        Box::new(Homogeneous {
+            sigma_t: sigma_a + sigma_s,
+            phase_function: phase_function(sigma_a, sigma_s, g, color)
+        })
+    }
+}
+
+impl Medium for Homogeneous {
+    fn sample_distance(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
+        if self.sigma_t <= 0.0 {
+            return None;
+        }
+        let t = t_min + sample_exponential(r, self.sigma_t);
+        if t < t_max { Some(t) } else { None }
+    }
+
+    fn phase_function(&self) -> &Material {
+        &*self.phase_function
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// a heterogeneous medium whose density is trilinearly interpolated from
+// a grid of samples spanning bounds, with x varying fastest. Outside of
+// bounds the density is zero. sigma_a and sigma_s are per unit density.
+//
+// Collisions are found by delta tracking (Woodcock tracking): tentative
+// collisions are drawn against the densest the medium gets, and each is
+// accepted as real with probability density / max density.
+
+pub struct Grid {
+    pub bounds: AABB,
+    pub resolution: [usize; 3],
+    pub density: Vec<f64>,
+    pub max_density: f64,
+    pub sigma_t: f64,
+    pub phase_function: Box<Material>
+}
+
+impl Grid {
+    pub fn new(bounds: AABB, resolution: [usize; 3], density: Vec<f64>,
+               sigma_a: f64, sigma_s: f64, g: f64, color: &Vec3) -> Box<Medium> {
+        if density.len() != resolution[0] * resolution[1] * resolution[2] {
+            panic!("Density grid has {} samples, expected {}x{}x{}",
+                   density.len(), resolution[0], resolution[1], resolution[2]);
+        }
+        let max_density = density.iter().fold(0.0, |a: f64, b| a.max(*b));
+        Box::new(Grid {
+            bounds: bounds,
+            resolution: resolution,
+            density: density,
+            max_density: max_density,
+            sigma_t: sigma_a + sigma_s,
+            phase_function: phase_function(sigma_a, sigma_s, g, color)
+        })
+    }
+
+    fn lookup(&self, x: usize, y: usize, z: usize) -> f64 {
+        self.density[(z * self.resolution[1] + y) * self.resolution[0] + x]
+    }
+
+    pub fn density_at(&self, p: &Vec3) -> f64 {
+        let min = self.bounds.min();
+        let max = self.bounds.max();
+        let mut cell = [0usize; 3];
+        let mut frac = [0.0; 3];
+        for a in 0..3 {
+            if p[a] < min[a] || p[a] > max[a] {
+                return 0.0;
+            }
+            let n = self.resolution[a];
+            // sample points sit at the centers of the grid cells
+            let x = ((p[a] - min[a]) / (max[a] - min[a]) * (n as f64) - 0.5)
+                .max(0.0).min((n - 1) as f64);
+            cell[a] = (x as usize).min(n.saturating_sub(2));
+            frac[a] = if n > 1 { x - cell[a] as f64 } else { 0.0 };
+        }
+        let mut result = 0.0;
+        for corner in 0..8 {
+            let mut w = 1.0;
+            let mut ix = [0usize; 3];
+            for a in 0..3 {
+                let high = (corner >> a) & 1 == 1;
+                ix[a] = if high { (cell[a] + 1).min(self.resolution[a] - 1) } else { cell[a] };
+                w *= if high { frac[a] } else { 1.0 - frac[a] };
+            }
+            if w > 0.0 {
+                result += w * self.lookup(ix[0], ix[1], ix[2]);
+            }
+        }
+        result
+    }
+}
+
+impl Medium for Grid {
+    fn sample_distance(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
+        let majorant = self.max_density * self.sigma_t;
+        if majorant <= 0.0 {
+            return None;
+        }
+        let mut t = t_min;
+        loop {
+            t += sample_exponential(r, majorant);
+            if t >= t_max {
+                return None;
+            }
+            let density = self.density_at(&r.point_at_parameter(t));
+            if rand_double() < density / self.max_density {
+                return Some(t);
+            }
+        }
+    }
+
+    fn phase_function(&self) -> &Material {
+        &*self.phase_function
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn transmittance_follows_beer_lambert() {
+    let homogeneous = Homogeneous::new(0.5, 0.5, 0.0, &Vec3::new(1.0, 1.0, 1.0));
+    // a constant grid should behave just like the homogeneous medium
+    let grid = Grid::new(AABB::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(3.0, 1.0, 1.0)),
+                         [2, 2, 2], vec![0.5; 8], 1.0, 1.0, 0.0, &Vec3::new(1.0, 1.0, 1.0));
+    // non-unit direction, to check distances are measured in space
+    let r = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0));
+    // sigma_t = 1 over a distance of 2
+    let expected = (-2.0f64).exp();
+
+    let n = 100000;
+    for medium in [homogeneous, grid].iter() {
+        let transmitted = (0..n)
+            .filter(|_| medium.sample_distance(&r, 0.0, 1.0).is_none())
+            .count();
+        let fraction = transmitted as f64 / n as f64;
+        assert!((fraction - expected).abs() < 0.01);
+    }
+}
@@ -2,6 +2,7 @@ use material::*;
 use vector::Vec3;
 use vector;
 use ray::Ray;
+use spectrum;
 use hitable::*;
 
 use std::fmt;
@@ -23,14 +24,14 @@ impl Material for Metal {
         0.0
     }
     fn albedo(&self, _ray: &Ray, _ray_out: &Ray, _surface_normal: &Vec3) -> Vec3 {
-        self.albedo
+        spectrum::upsample(&self.albedo)
     }
     
     fn scatter(&self, ray_in: &Ray, rec: &HitRecord) -> Scatter {
         let reflected = vector::reflect(&vector::unit_vector(&ray_in.direction()), &rec.normal);
-        let scattered = Ray::new(rec.p, reflected);
+        let scattered = Ray::with_time(rec.p, reflected, ray_in.time());
         if scattered.direction().dot(&rec.normal) > 0.0 {
-            Scatter::Bounce(self.albedo, scattered)
+            Scatter::Bounce(spectrum::upsample(&self.albedo), scattered)
         } else {
             Scatter::Absorb
         }
@@ -41,6 +42,8 @@ impl Material for Metal {
     }
 
     fn is_emitter(&self) -> bool { false }
+
+    fn emission(&self) -> Vec3 { Vec3::zero() }
 }
 
 impl Metal {
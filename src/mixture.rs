@@ -4,6 +4,7 @@ use vector;
 use ray::Ray;
 use hitable::*;
 use random::*;
+use spectrum::Spectrum;
 
 use std::fmt;
 use std::fmt::Debug;
@@ -50,6 +51,15 @@ impl Material for Mixture {
     fn is_emitter(&self) -> bool {
         self.mat_1.is_emitter() || self.mat_2.is_emitter()
     }
+
+    // scatter() picks mat_1 with probability u
+    fn emission(&self) -> Vec3 {
+        self.mat_1.emission() * self.u + self.mat_2.emission() * (1.0 - self.u)
+    }
+
+    fn emission_spectrum(&self) -> Spectrum {
+        Spectrum::lerp(&self.mat_2.emission_spectrum(), &self.mat_1.emission_spectrum(), self.u)
+    }
 }
 
 impl Mixture {
@@ -0,0 +1,289 @@
+use camera::Camera;
+use integrator::*;
+use rand;
+use rand::Rng;
+use random::*;
+use ray::Ray;
+use vector::Vec3;
+use vector;
+
+use std::cell::RefCell;
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+//////////////////////////////////////////////////////////////////////////////
+// primary sample space Metropolis light transport, after Kelemen et
+// al. 2002 and pbrt's lazy formulation of it.
+//
+// A path is a function of the stream of uniform numbers that
+// rand_double hands out. Instead of drawing fresh streams, a Markov
+// chain mutates the last accepted one, either a little (a small step:
+// every number gets a wrapped gaussian nudge) or completely (a large
+// step). The film position is read off the first two numbers of the
+// stream, so the chain wanders over the image and spends its time
+// where the image is bright. The normalization the chain can't know
+// about comes from a bootstrap phase of independent paths.
+
+#[derive(Clone, Copy, Debug)]
+struct PrimarySample {
+    value: f64,
+    last_modified: usize,
+    backup_value: f64,
+    backup_modified: usize
+}
+
+struct MltSampler {
+    rng: rand::ThreadRng,
+    samples: Vec<PrimarySample>,
+    sigma: f64,
+    large_step_probability: f64,
+    iteration: usize,
+    last_large_step: usize,
+    large_step: bool,
+    index: usize
+}
+
+impl MltSampler {
+    fn new(sigma: f64, large_step_probability: f64) -> MltSampler {
+        MltSampler {
+            rng: rand::thread_rng(),
+            samples: Vec::new(),
+            sigma: sigma,
+            large_step_probability: large_step_probability,
+            iteration: 0,
+            last_large_step: 0,
+            large_step: true,
+            index: 0
+        }
+    }
+
+    fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.gen::<f64>() < self.large_step_probability;
+        self.index = 0;
+    }
+
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    fn reject(&mut self) {
+        for s in self.samples.iter_mut() {
+            if s.last_modified == self.iteration {
+                s.value = s.backup_value;
+                s.last_modified = s.backup_modified;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    fn normal(&mut self) -> f64 {
+        // Box-Muller
+        let u1 = 1.0 - self.rng.gen::<f64>();
+        let u2 = self.rng.gen::<f64>();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+    }
+
+    // brings sample i up to date with the current iteration. Mutations
+    // are only applied to numbers that actually get used, so the small
+    // steps a number missed are caught up with in one go.
+    fn next(&mut self) -> f64 {
+        let i = self.index;
+        self.index += 1;
+        if i >= self.samples.len() {
+            self.samples.push(PrimarySample {
+                value: 0.0,
+                last_modified: 0,
+                backup_value: 0.0,
+                backup_modified: 0
+            });
+        }
+        if self.samples[i].last_modified < self.last_large_step {
+            let u = self.rng.gen::<f64>();
+            self.samples[i].value = u;
+            self.samples[i].last_modified = self.last_large_step;
+        }
+        let s = self.samples[i];
+        self.samples[i].backup_value = s.value;
+        self.samples[i].backup_modified = s.last_modified;
+        let value = if self.large_step {
+            self.rng.gen::<f64>()
+        } else {
+            let small_steps = (self.iteration - s.last_modified) as f64;
+            let v = s.value + self.normal() * self.sigma * small_steps.sqrt();
+            v - v.floor()
+        };
+        self.samples[i].value = value;
+        self.samples[i].last_modified = self.iteration;
+        value
+    }
+}
+
+// the sample source seen by rand_double while a path is traced
+struct SharedSampler(Rc<RefCell<MltSampler>>);
+
+impl SampleSource for SharedSampler {
+    fn next_sample(&mut self) -> f64 {
+        self.0.borrow_mut().next()
+    }
+}
+
+pub struct Mlt {
+    pub integrator: Box<Integrator>,
+    pub large_step_probability: f64,
+    pub sigma: f64,
+    // defaults to the number of pixels
+    pub bootstrap_samples: Option<usize>
+}
+
+// a sample on the film: pixel coordinates and radiance
+struct FilmSample {
+    i: usize,
+    j: usize,
+    radiance: Vec3
+}
+
+impl Mlt {
+    pub fn new(integrator: Box<Integrator>, large_step_probability: f64,
+               sigma: f64, bootstrap_samples: Option<usize>) -> Box<Integrator> {
+        Box::new(Mlt {
+            integrator: integrator,
+            large_step_probability: large_step_probability,
+            sigma: sigma,
+            bootstrap_samples: bootstrap_samples
+        })
+    }
+
+    // traces the path that sampler's current stream describes
+    fn evaluate(&self, sampler: &Rc<RefCell<MltSampler>>, camera: &Camera,
+                context: &RenderContext, nx: usize, ny: usize) -> FilmSample {
+        let previous = set_sample_source(Some(Box::new(SharedSampler(sampler.clone()))));
+        let u = rand_double();
+        let v = rand_double();
+        let ray: Ray = camera.get_ray(u, v);
+        let radiance = self.integrator.color(&ray, context);
+        set_sample_source(previous);
+        FilmSample {
+            i: ((u * nx as f64) as usize).min(nx - 1),
+            j: ((v * ny as f64) as usize).min(ny - 1),
+            radiance: radiance
+        }
+    }
+}
+
+fn splat(image: &mut [Vec<Vec3>], sample: &FilmSample, weight: f64) {
+    let l = vector::luminance(&sample.radiance);
+    if l > 0.0 && weight > 0.0 {
+        image[sample.j][sample.i] = image[sample.j][sample.i] + sample.radiance * (weight / l);
+    }
+}
+
+impl Integrator for Mlt {
+    fn color(&self, _ray: &Ray, _context: &RenderContext) -> Vec3 {
+        panic!("Metropolis light transport places its own samples, it needs render_pass");
+    }
+
+    fn render_pass(&self, image: &mut [Vec<Vec3>], camera: &Camera, context: &RenderContext) -> bool {
+        let ny = image.len();
+        let nx = image[0].len();
+        let bootstrap_samples = self.bootstrap_samples.unwrap_or(nx * ny);
+
+        // bootstrap: independent paths estimate the normalization b, and
+        // one of them, chosen in proportion to its luminance, seeds the
+        // chain so it doesn't have to burn in.
+        let mut total = 0.0;
+        let mut chosen = None;
+        for _ in 0..bootstrap_samples {
+            let sampler = Rc::new(RefCell::new(MltSampler::new(self.sigma, self.large_step_probability)));
+            sampler.borrow_mut().start_iteration();
+            sampler.borrow_mut().large_step = true;
+            let sample = self.evaluate(&sampler, camera, context, nx, ny);
+            sampler.borrow_mut().accept();
+            let l = vector::luminance(&sample.radiance);
+            if l <= 0.0 || !l.is_finite() {
+                continue;
+            }
+            total += l;
+            if rand::thread_rng().gen::<f64>() * total < l {
+                chosen = Some((sampler, sample));
+            }
+        }
+        let (sampler, mut current) = match chosen {
+            None => return true,
+            Some(c) => c
+        };
+        let b = total / (bootstrap_samples as f64);
+
+        // one mutation per pixel, splatting expected values: the
+        // proposal and the current state share each step's weight
+        // according to the acceptance probability.
+        for _ in 0..nx * ny {
+            sampler.borrow_mut().start_iteration();
+            let proposed = self.evaluate(&sampler, camera, context, nx, ny);
+            let l_current = vector::luminance(&current.radiance);
+            let l_proposed = vector::luminance(&proposed.radiance);
+            let accept = if l_proposed.is_finite() {
+                (l_proposed / l_current).min(1.0)
+            } else {
+                0.0
+            };
+            splat(image, &proposed, accept * b);
+            splat(image, &current, (1.0 - accept) * b);
+            if rand::thread_rng().gen::<f64>() < accept {
+                sampler.borrow_mut().accept();
+                current = proposed;
+            } else {
+                sampler.borrow_mut().reject();
+            }
+        }
+        true
+    }
+
+    fn supports_spectral(&self) -> bool {
+        false
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn sampler_mutates_and_restores() {
+    let sampler = Rc::new(RefCell::new(MltSampler::new(0.01, 0.3)));
+    let mut source = SharedSampler(sampler.clone());
+
+    sampler.borrow_mut().start_iteration();
+    sampler.borrow_mut().large_step = true;
+    let first: Vec<_> = (0..10).map(|_| source.next_sample()).collect();
+    sampler.borrow_mut().accept();
+
+    for _ in 0..100 {
+        sampler.borrow_mut().start_iteration();
+        let mutated: Vec<_> = (0..10).map(|_| source.next_sample()).collect();
+        assert!(mutated.iter().all(|u| *u >= 0.0 && *u < 1.0));
+        sampler.borrow_mut().reject();
+    }
+
+    // after rejecting everything, a small step starts from the
+    // original stream again
+    sampler.borrow_mut().start_iteration();
+    sampler.borrow_mut().large_step = false;
+    let small: Vec<_> = (0..10).map(|_| source.next_sample()).collect();
+    for (a, b) in first.iter().zip(small.iter()) {
+        let d = (a - b).abs();
+        assert!(d.min(1.0 - d) < 0.1);
+    }
+}
+
+#[test]
+fn installed_source_drives_rand_double() {
+    let sampler = Rc::new(RefCell::new(MltSampler::new(0.01, 0.3)));
+    sampler.borrow_mut().start_iteration();
+    let previous = set_sample_source(Some(Box::new(SharedSampler(sampler.clone()))));
+    let drawn: Vec<_> = (0..5).map(|_| rand_double()).collect();
+    set_sample_source(previous);
+
+    let recorded: Vec<_> = sampler.borrow().samples.iter().map(|s| s.value).collect();
+    assert_eq!(drawn, recorded);
+}
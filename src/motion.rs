@@ -0,0 +1,75 @@
+use hitable::*;
+use instance::Instance;
+use matrix::Matrix;
+use vector::Vec3;
+
+use std::sync::Arc;
+
+//////////////////////////////////////////////////////////////////////////////
+// objects moving for motion blur, from where the start transform puts
+// them at time 0 to where the end one does at time 1. They're instances
+// with those two keyframes, so they're interpolated the same way,
+// stay at their ends before and after, and only make area lights when
+// both ends are the same.
+
+// None if either transform can't be inverted
+pub fn moving(hitable: Box<Hitable + Send + Sync>, start: &Matrix, end: &Matrix) -> Option<Instance> {
+    Instance::keyframed(Arc::from(hitable), &[(0.0, *start), (1.0, *end)], None)
+}
+
+// sliding along offset
+pub fn sliding(hitable: Box<Hitable + Send + Sync>, offset: &Vec3) -> Instance {
+    moving(hitable, &Matrix::identity(), &Matrix::translate(offset)).unwrap()
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn objects_move_while_the_shutter_is_open() {
+    use cuboid::Cuboid;
+    use emitter::Emitter;
+    use lambertian::Lambertian;
+    use ray::Ray;
+    use sphere::Sphere;
+    use std::f64::consts::PI;
+
+    let sphere = || Box::new(Sphere::new(Vec3::zero(), 1.0, Lambertian::new(&Vec3::new(0.5, 0.5, 0.5))));
+    let slider = sliding(sphere(), &Vec3::new(4.0, 0.0, 0.0));
+    let ray_at = |x: f64, time: f64| Ray::with_time(Vec3::new(x, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), time);
+
+    // there at the start, gone by the end, and halfway in between
+    assert!(slider.hit(&ray_at(0.0, 0.0), 0.0, 1e20).is_some());
+    assert!(slider.hit(&ray_at(0.0, 1.0), 0.0, 1e20).is_none());
+    let hr = slider.hit(&ray_at(2.0, 0.5), 0.0, 1e20).unwrap();
+    assert!((hr.p - Vec3::new(2.0, 0.0, 1.0)).length() < 1e-8);
+    assert!((hr.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-8);
+    // and stays put outside [0, 1]
+    assert!(slider.hit(&ray_at(4.0, 3.0), 0.0, 1e20).is_some());
+
+    let b = slider.bounding_box().unwrap();
+    assert!(b.min() == Vec3::new(-1.0, -1.0, -1.0) && b.max() == Vec3::new(5.0, 1.0, 1.0));
+
+    // stretching to 3 long along x, so twice as long halfway there
+    let growing = moving(sphere(), &Matrix::identity(), &Matrix::scale(&Vec3::new(3.0, 1.0, 1.0))).unwrap();
+    let hr = growing.hit(&ray_at(1.5, 0.5), 0.0, 1e20).unwrap();
+    assert!((hr.p - Vec3::new(1.5, 0.0, 0.4375f64.sqrt())).length() < 1e-8);
+    assert!(growing.hit(&ray_at(1.5, 0.0), 0.0, 1e20).is_none());
+    let b = growing.bounding_box().unwrap();
+    assert!(b.min() == Vec3::new(-3.0, -1.0, -1.0) && b.max() == Vec3::new(3.0, 1.0, 1.0));
+
+    // turning a quarter turn about y keeps the box around both ends
+    let bar = Box::new(Cuboid::new(Vec3::new(-2.0, -0.1, -0.1), Vec3::new(2.0, 0.1, 0.1),
+                                   Lambertian::new(&Vec3::new(0.5, 0.5, 0.5))));
+    let turning = moving(bar, &Matrix::identity(), &Matrix::rotate(&Vec3::new(0.0, 1.0, 0.0), PI / 2.0)).unwrap();
+    let b = turning.bounding_box().unwrap();
+    assert!((b.min() - Vec3::new(-2.0, -0.1, -2.0)).length() < 1e-9);
+    assert!((b.max() - Vec3::new(2.0, 0.1, 2.0)).length() < 1e-9);
+    assert!(turning.hit(&ray_at(1.5, 0.0), 0.0, 1e20).is_some());
+    assert!(turning.hit(&Ray::with_time(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), 1.0),
+                        0.0, 1e20).map(|hr| hr.p.z()).is_some_and(|z| (z - 2.0).abs() < 1e-9));
+
+    // still ones keep their lights, and moving ones don't
+    let glowing = || Box::new(Sphere::new(Vec3::zero(), 1.0, Emitter::new(&Vec3::new(1.0, 1.0, 1.0))));
+    assert!(moving(glowing(), &Matrix::identity(), &Matrix::identity()).unwrap().area_lights().len() == 1);
+    assert!(sliding(glowing(), &Vec3::new(4.0, 0.0, 0.0)).area_lights().is_empty());
+}
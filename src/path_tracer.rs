@@ -0,0 +1,83 @@
+use integrator::*;
+use material;
+use ray::Ray;
+use vector::Vec3;
+use vector;
+
+//////////////////////////////////////////////////////////////////////////////
+// path tracer with next-event estimation: every vertex that can be
+// importance sampled gets an explicit shadow ray towards the lights,
+// and emission that the bsdf samples reach afterwards is weighted
+// with MIS so it isn't counted twice.
+
+pub struct PathTracer {}
+
+impl Integrator for PathTracer {
+    fn color(&self, ray: &Ray, context: &RenderContext) -> Vec3 {
+        let mut current_ray = *ray;
+        let mut current_attenuation = Vec3::new(1.0, 1.0, 1.0);
+        let mut result = Vec3::zero();
+
+        // camera rays and specular bounces can't be handled by light
+        // sampling, so whatever emission they hit counts in full.
+        let mut specular_bounce = true;
+        let mut last_scatter_pdf = 0.0;
+        let mut last_p = current_ray.origin();
+
+        for depth in 0..context.settings.max_depth {
+            if !russian_roulette(context, depth, &mut current_attenuation) {
+                break;
+            }
+
+            match context.world.hit(&current_ray, 0.00001, 1e20) {
+                None => {
+                    // the background is one of the lights, too
+                    let unit_direction = vector::unit_vector(&current_ray.direction());
+                    let w = if specular_bounce { 1.0 } else {
+                        emitter_weight(context, &last_p, &current_ray.direction(), last_scatter_pdf)
+                    };
+                    return result + context.background.get_background(&unit_direction) * current_attenuation * w;
+                },
+                Some(hr) => {
+                    if let Some(le) = emission(&hr) {
+                        let w = if specular_bounce { 1.0 } else {
+                            emitter_weight(context, &last_p, &current_ray.direction(), last_scatter_pdf)
+                        };
+                        return result + le * current_attenuation * w;
+                    }
+
+                    let sample_lights = hr.material.wants_importance_sampling();
+                    if sample_lights {
+                        result = result + current_attenuation * estimate_light_sample(context, &current_ray, &hr);
+                    }
+
+                    match hr.material.scatter(&current_ray, &hr) {
+                        material::Scatter::Bounce(next_attenuation, scattered) => {
+                            if sample_lights {
+                                let normal = facing_normal(&current_ray, &hr.normal);
+                                last_scatter_pdf = bsdf_pdf(hr.material, &current_ray, &scattered, &normal);
+                            }
+                            specular_bounce = !sample_lights;
+                            last_p = hr.p;
+                            current_attenuation = current_attenuation * next_attenuation;
+                            current_ray = scattered;
+                        },
+                        material::Scatter::Emit(emission) => {
+                            return result + emission * current_attenuation;
+                        },
+                        material::Scatter::Absorb => {
+                            return result;
+                        }
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+impl PathTracer {
+    pub fn new() -> Box<Integrator> {
+        Box::new(PathTracer {})
+    }
+}
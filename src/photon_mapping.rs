@@ -0,0 +1,390 @@
+use hitable::*;
+use integrator::*;
+use kd_tree::KdTree;
+use material::*;
+use random::*;
+use ray::Ray;
+use vector::Vec3;
+use vector;
+
+use std::f64::consts::PI;
+
+//////////////////////////////////////////////////////////////////////////////
+// progressive photon mapping, after Jensen's two-map photon mapping and
+// Knaus and Zwicker's probabilistic formulation of PPM.
+//
+// Every pass shoots a fresh batch of photons from the lights (the
+// background included, in through a disc that covers the scene) and
+// stores them where they land on surfaces that can be importance
+// sampled. Photons that reached such a surface only through specular
+// bounces also go into a separate caustic map. Camera rays follow
+// specular bounces to the first diffuse surface, where direct light is
+// estimated with next-event estimation, caustics are read from the
+// caustic map, and the remaining indirect light comes from a final
+// gather into the global map. The lookup radius shrinks from pass to
+// pass, so the average over passes converges without ever having to
+// keep more than one pass worth of photons around.
+
+#[derive(Clone, Copy, Debug)]
+struct Photon {
+    direction: Vec3,
+    power: Vec3
+}
+
+type PhotonList = Vec<(Vec3, Photon)>;
+
+pub struct PhotonMapping {
+    pub photons: usize,
+    pub radius: Option<f64>,
+    pub alpha: f64,
+    pub final_gather: usize
+}
+
+struct PhotonPass {
+    global_map: KdTree<Photon>,
+    caustic_map: KdTree<Photon>,
+    radius: f64,
+    final_gather: usize
+}
+
+fn trace_photons(context: &RenderContext, n: usize) -> (PhotonList, PhotonList) {
+    let mut global = Vec::new();
+    let mut caustic = Vec::new();
+
+    for _ in 0..n {
+        let sample = match context.lights.sample_photon() {
+            None => break,
+            Some((_, sample)) => sample
+        };
+        if sample.pdf_pos <= 0.0 || sample.pdf_dir <= 0.0 {
+            continue;
+        }
+        let power = sample.radiance * (sample.cos() / (sample.pdf_pos * sample.pdf_dir * (n as f64)));
+
+        // the maps hold photons from the whole time the shutter is open
+        let settings = context.settings;
+        let time = settings.shutter_open + rand_double() * (settings.shutter_close - settings.shutter_open);
+        let mut ray = Ray::with_time(sample.p, sample.direction, time);
+        let mut beta = Vec3::new(1.0, 1.0, 1.0);
+        let mut specular_only = true;
+        let mut bounced = false;
+        for depth in 0..context.settings.max_depth {
+            let hr = match context.world.hit(&ray, 0.00001, 1e20) {
+                None => break,
+                Some(hr) => hr
+            };
+            if hr.material.wants_importance_sampling() {
+                // photons are only stored on surfaces, not in media
+                if hr.normal != Vec3::zero() {
+                    let photon = Photon {
+                        direction: vector::unit_vector(&ray.direction()),
+                        power: power * beta
+                    };
+                    global.push((hr.p, photon));
+                    if specular_only && bounced {
+                        caustic.push((hr.p, photon));
+                    }
+                }
+                specular_only = false;
+            }
+            match hr.material.scatter(&ray, &hr) {
+                Scatter::Bounce(attenuation, scattered) => {
+                    beta = beta * attenuation;
+                    ray = scattered;
+                    bounced = true;
+                },
+                _ => break
+            }
+            if !russian_roulette(context, depth, &mut beta) {
+                break;
+            }
+        }
+    }
+    (global, caustic)
+}
+
+// radiance leaving hr towards the origin of ray_in, as estimated from
+// the photons within radius of the hit point.
+fn density_estimate(map: &KdTree<Photon>, radius: f64, ray_in: &Ray, hr: &HitRecord) -> Vec3 {
+    let normal = facing_normal(ray_in, &hr.normal);
+    let mut result = Vec3::zero();
+    map.for_each_within(&hr.p, radius, &mut |_, photon| {
+        let to_light = -photon.direction;
+        let cos = to_light.dot(&normal);
+        // photons that arrived on the other side don't count
+        if cos <= 0.0 {
+            return;
+        }
+        let f = bsdf_value(hr.material, ray_in, &Ray::new(hr.p, to_light), &normal) / cos;
+        result = result + f * photon.power;
+    });
+    result / (PI * radius * radius)
+}
+
+impl PhotonPass {
+    // the indirect light arriving at the diffuse surface hr, gathered
+    // by shooting bsdf samples into the global map. Emitters and the
+    // background are skipped, whether they're seen directly or through
+    // specular surfaces, since estimate_direct and the caustic map
+    // already account for them.
+    fn final_gather(&self, context: &RenderContext, ray_in: &Ray, hr: &HitRecord) -> Vec3 {
+        let mut result = Vec3::zero();
+        for _ in 0..self.final_gather {
+            let (mut attenuation, mut current_ray) = match hr.material.scatter(ray_in, hr) {
+                Scatter::Bounce(attenuation, scattered) => (attenuation, scattered),
+                _ => continue
+            };
+            for _depth in 0..context.settings.max_depth {
+                match context.world.hit(&current_ray, 0.00001, 1e20) {
+                    None => break,
+                    Some(gather_hr) => {
+                        if gather_hr.material.is_emitter() {
+                            break;
+                        }
+                        if gather_hr.material.wants_importance_sampling() {
+                            result = result + density_estimate(
+                                &self.global_map, self.radius, &current_ray, &gather_hr) * attenuation;
+                            break;
+                        }
+                        match gather_hr.material.scatter(&current_ray, &gather_hr) {
+                            Scatter::Bounce(next_attenuation, scattered) => {
+                                attenuation = attenuation * next_attenuation;
+                                current_ray = scattered;
+                            },
+                            _ => break
+                        }
+                    }
+                }
+            }
+        }
+        result / (self.final_gather as f64)
+    }
+}
+
+impl Integrator for PhotonPass {
+    fn color(&self, ray: &Ray, context: &RenderContext) -> Vec3 {
+        let mut current_ray = *ray;
+        let mut current_attenuation = Vec3::new(1.0, 1.0, 1.0);
+
+        for _depth in 0..context.settings.max_depth {
+            match context.world.hit(&current_ray, 0.00001, 1e20) {
+                None => {
+                    let unit_direction = vector::unit_vector(&current_ray.direction());
+                    return context.background.get_background(&unit_direction) * current_attenuation;
+                },
+                Some(hr) => {
+                    if let Some(le) = emission(&hr) {
+                        return le * current_attenuation;
+                    }
+                    if hr.material.wants_importance_sampling() {
+                        // without final gather, everything is read straight off
+                        // the global map, which holds the direct photons too.
+                        if self.final_gather == 0 {
+                            return density_estimate(&self.global_map, self.radius, &current_ray, &hr)
+                                * current_attenuation;
+                        }
+                        let result = estimate_direct(context, &current_ray, &hr) +
+                            density_estimate(&self.caustic_map, self.radius, &current_ray, &hr) +
+                            self.final_gather(context, &current_ray, &hr);
+                        return result * current_attenuation;
+                    }
+                    match hr.material.scatter(&current_ray, &hr) {
+                        Scatter::Bounce(next_attenuation, scattered) => {
+                            current_attenuation = current_attenuation * next_attenuation;
+                            current_ray = scattered;
+                        },
+                        Scatter::Emit(emission) => {
+                            return emission * current_attenuation;
+                        },
+                        Scatter::Absorb => {
+                            return Vec3::new(0.0, 0.0, 0.0)
+                        }
+                    }
+                }
+            }
+        }
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+}
+
+impl Integrator for PhotonMapping {
+    fn color(&self, _ray: &Ray, _context: &RenderContext) -> Vec3 {
+        panic!("photon mapping needs begin_pass to build its photon maps");
+    }
+
+    fn begin_pass(&self, pass: usize, context: &RenderContext) -> Option<Box<Integrator>> {
+        let initial_radius = self.radius.unwrap_or_else(|| {
+            // a small fraction of the scene size, or of its bounded part
+            // if it goes on forever
+            context.world.bounding_box().map_or(2.0 * context.lights.world_radius * 0.005,
+                                                |b| (b.max() - b.min()).length() * 0.005)
+        });
+        // r_{i+1}^2 = r_i^2 (i + alpha) / (i + 1)
+        let mut radius2 = initial_radius * initial_radius;
+        for i in 1..(pass + 1) {
+            radius2 *= ((i as f64) + self.alpha) / ((i as f64) + 1.0);
+        }
+        let (global, caustic) = trace_photons(context, self.photons);
+        Some(Box::new(PhotonPass {
+            global_map: KdTree::new(global),
+            caustic_map: KdTree::new(caustic),
+            radius: radius2.sqrt(),
+            final_gather: self.final_gather
+        }))
+    }
+
+    fn supports_spectral(&self) -> bool {
+        false
+    }
+}
+
+impl PhotonMapping {
+    pub fn new(photons: usize, radius: Option<f64>, alpha: f64, final_gather: usize) -> Box<Integrator> {
+        Box::new(PhotonMapping {
+            photons: photons,
+            radius: radius,
+            alpha: alpha,
+            final_gather: final_gather
+        })
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn photons_carry_the_lights_power() {
+    use area_light::*;
+    use background;
+    use hitable_list::HitableList;
+    use lambertian::Lambertian;
+    use light::LightList;
+    use render_settings::RenderSettings;
+    use sphere::Sphere;
+
+    // a light inside a closed, black-ish sphere: every photon lands on
+    // the inner wall first, so the first-bounce photons carry all of
+    // the light's power.
+    let world = HitableList::new(vec![
+        Box::new(Sphere::new(Vec3::zero(), 10.0, Lambertian::new(&Vec3::new(0.0, 0.0, 0.0))))]);
+    let background = background::constant(Vec3::zero());
+    let emission = Vec3::new(1.0, 2.0, 3.0);
+    let light = AreaLight::new(LightShape::Sphere {
+        center: Vec3::zero(),
+        radius: 1.0
+    }, emission);
+    let area = light.area();
+    let lights = LightList::new(vec![Box::new(light)]);
+    let settings = RenderSettings::default();
+    let context = RenderContext {
+        world: &world,
+        background: &background,
+        lights: &lights,
+        settings: &settings
+    };
+
+    let (global, caustic) = trace_photons(&context, 10000);
+    assert!(caustic.is_empty());
+    let total = global.iter().fold(Vec3::zero(), |acc, p| acc + p.1.power);
+    // a two-sided lambertian emitter radiates 2 pi A Le
+    let expected = emission * (2.0 * PI * area);
+    for i in 0..3 {
+        assert!((total[i] - expected[i]).abs() < 1e-6 * expected[i]);
+    }
+}
+
+#[test]
+fn photons_come_from_the_background_too() {
+    use background;
+    use background::Background;
+    use hitable_list::HitableList;
+    use lambertian::Lambertian;
+    use light::LightList;
+    use path_tracer::PathTracer;
+    use rectangle::Rectangle;
+    use render_settings::RenderSettings;
+    use sampling;
+    use std::sync::Arc;
+
+    // a floor and a wall under a white sky: the floor by the wall also
+    // gets the sky's light off the wall, which only photons from the
+    // sky bring
+    let grey = || Lambertian::new(&Vec3::new(0.5, 0.5, 0.5));
+    let objects: Vec<Box<Hitable + Send + Sync>> = vec![
+        Box::new(Rectangle::new(Vec3::new(-1.0, 0.0, 2.0), Vec3::new(3.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -4.0), grey())),
+        Box::new(Rectangle::new(Vec3::new(-1.0, 0.0, 2.0), Vec3::new(0.0, 0.0, -4.0), Vec3::new(0.0, 2.0, 0.0), grey()))];
+    let background: Arc<Background + Send + Sync> = Arc::new(background::constant(Vec3::new(1.0, 1.0, 1.0)));
+    let lights = LightList::collect(&objects, Vec::new(), &background);
+    let world = HitableList::new(objects);
+    let settings = RenderSettings::default();
+    let context = RenderContext {
+        world: &world,
+        background: &*background,
+        lights: &lights,
+        settings: &settings
+    };
+
+    let ray = Ray::new(Vec3::new(1.0, 1.0, 0.0), Vec3::new(-1.0, -1.0, 0.0));
+    let n = 40000;
+    let (path, _) = sampling::avstdev(&mut (0..n).map(|_| PathTracer::new().color(&ray, &context).x()));
+    let photon_mapping = PhotonMapping::new(20000, Some(0.05), 0.7, 16);
+    let passes = 20;
+    let mut photons = 0.0;
+    for pass in 0..passes {
+        let pass = photon_mapping.begin_pass(pass, &context).unwrap();
+        photons += (0..100).map(|_| pass.color(&ray, &context).x()).sum::<f64>() / 100.0;
+    }
+    photons /= passes as f64;
+    assert!((photons - path).abs() < 0.02);
+}
+
+#[test]
+fn sky_caustics_are_counted_once() {
+    use background;
+    use background::Background;
+    use dielectric::Dielectric;
+    use hitable_list::HitableList;
+    use lambertian::Lambertian;
+    use light::LightList;
+    use path_tracer::PathTracer;
+    use rectangle::Rectangle;
+    use render_settings::RenderSettings;
+    use sampling;
+    use sphere::Sphere;
+    use std::sync::Arc;
+
+    // a glass ball over a floor under a white sky: the sky's light
+    // through the ball reaches the floor through the caustic map, and
+    // must not come back through final gather as well
+    let objects: Vec<Box<Hitable + Send + Sync>> = vec![
+        Box::new(Rectangle::new(Vec3::new(-1.0, 0.0, 1.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -2.0),
+                                Lambertian::new(&Vec3::new(0.5, 0.5, 0.5)))),
+        Box::new(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 0.5, Dielectric::new(1.5)))];
+    let background: Arc<Background + Send + Sync> = Arc::new(background::constant(Vec3::new(1.0, 1.0, 1.0)));
+    let lights = LightList::collect(&objects, Vec::new(), &background);
+    let world = HitableList::new(objects);
+    let settings = RenderSettings::default();
+    let context = RenderContext {
+        world: &world,
+        background: &*background,
+        lights: &lights,
+        settings: &settings
+    };
+
+    // onto the floor under the ball, past its side
+    let ray = Ray::new(Vec3::new(2.0, 1.0, 0.0), Vec3::new(-2.0, -1.0, 0.0));
+    let n = 40000;
+    let (path, path_stdev) = sampling::avstdev(&mut (0..n).map(|_| PathTracer::new().color(&ray, &context).x()));
+    // the passes' estimates are nearly independent, so they give the
+    // photon map's noise, which the caustic's few photons dominate
+    let photon_mapping = PhotonMapping::new(50000, Some(0.1), 0.7, 16);
+    let passes = 40;
+    let (photons, pass_stdev) = sampling::avstdev(&mut (0..passes).map(|pass| {
+        let pass = photon_mapping.begin_pass(pass, &context).unwrap();
+        (0..100).map(|_| pass.color(&ray, &context).x()).sum::<f64>() / 100.0
+    }));
+    let stderr = (path_stdev * path_stdev / n as f64 + pass_stdev * pass_stdev / passes as f64).sqrt();
+    assert!((photons - path).abs() < 5.0 * stderr);
+    // and tight enough to catch the sky through the ball counted twice,
+    // which adds about a quarter
+    assert!(5.0 * stderr < 0.08);
+}
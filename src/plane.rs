@@ -1,3 +1,7 @@
+use aabb::AABB;
+use area_light::AreaLight;
+use hitable::*;
+use material::*;
 use vector::Vec3;
 use vector;
 use ray::Ray;
@@ -131,6 +135,48 @@ impl Plane {
     }
 }
 
+//////////////////////////////////////////////////////////////////////////////
+// an infinite plane in the scene, seen from both sides. It has no
+// bounding box, so the BVH keeps it on the side (see bvh.rs), and it
+// never makes an area light.
+
+pub struct PlaneSurface {
+    pub plane: Plane,
+    pub material: Box<Material>
+}
+
+impl PlaneSurface {
+    pub fn new(point: &Vec3, normal: &Vec3, material: Box<Material>) -> PlaneSurface {
+        PlaneSurface {
+            plane: Plane::from_point_and_normal(point, normal),
+            material: material
+        }
+    }
+}
+
+impl Hitable for PlaneSurface {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'a>> {
+        let den = self.plane.normal.dot(&r.direction());
+        if den == 0.0 {
+            return None;
+        }
+        let t = -self.plane.eval(&r.origin()) / den;
+        if t <= t_min || t >= t_max {
+            None
+        } else {
+            Some(HitRecord::hit(t, r.point_at_parameter(t), self.plane.normal, &*self.material))
+        }
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        None
+    }
+
+    fn area_lights(&self) -> Vec<AreaLight> {
+        Vec::new()
+    }
+}
+
 //////////////////////////////////////////////////////////////////////////////
 
 #[test]
@@ -0,0 +1,115 @@
+use std::f64::consts::PI;
+
+//////////////////////////////////////////////////////////////////////////////
+// real roots of low-degree polynomials, for intersecting rays with
+// analytic surfaces. Roots come back in increasing order, repeated ones
+// once or twice, as they happen to fall out numerically.
+
+// a x^2 + b x + c = 0, in the form that doesn't lose precision when
+// b^2 is much bigger than 4 a c
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a == 0.0 {
+        return if b == 0.0 { Vec::new() } else { vec![-c / b] };
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return Vec::new();
+    }
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    if q == 0.0 {
+        // b and c are both zero
+        return vec![0.0];
+    }
+    let (x0, x1) = (q / a, c / q);
+    if x0 < x1 { vec![x0, x1] } else { vec![x1, x0] }
+}
+
+// x^3 + a x^2 + b x + c = 0, after Numerical Recipes
+pub fn solve_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    let q = (a * a - 3.0 * b) / 9.0;
+    let r = (2.0 * a * a * a - 9.0 * a * b + 27.0 * c) / 54.0;
+    let mut roots = if r * r < q * q * q {
+        let theta = (r / (q * q * q).sqrt()).acos();
+        let s = -2.0 * q.sqrt();
+        vec![s * (theta / 3.0).cos() - a / 3.0,
+             s * ((theta + 2.0 * PI) / 3.0).cos() - a / 3.0,
+             s * ((theta - 2.0 * PI) / 3.0).cos() - a / 3.0]
+    } else {
+        let big_a = -r.signum() * (r.abs() + (r * r - q * q * q).sqrt()).cbrt();
+        let big_b = if big_a == 0.0 { 0.0 } else { q / big_a };
+        vec![big_a + big_b - a / 3.0]
+    };
+    roots.sort_by(|x, y| x.partial_cmp(y).unwrap());
+    roots
+}
+
+// x^4 + a x^3 + b x^2 + c x + d = 0, by Ferrari's method: the depressed
+// quartic y^4 + p y^2 + q y + r (x = y - a / 4) splits into two
+// quadratics given a positive root m of its resolvent cubic. Each root
+// is then polished with a few Newton steps on the original.
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    let a2 = a * a;
+    let p = b - 3.0 * a2 / 8.0;
+    let q = c - a * b / 2.0 + a2 * a / 8.0;
+    let r = d - a * c / 4.0 + a2 * b / 16.0 - 3.0 * a2 * a2 / 256.0;
+
+    let mut ys = Vec::new();
+    if q.abs() < 1e-12 {
+        // biquadratic: y^2 is a root of z^2 + p z + r
+        for z in solve_quadratic(1.0, p, r) {
+            if z >= 0.0 {
+                ys.push(z.sqrt());
+                ys.push(-z.sqrt());
+            }
+        }
+    } else {
+        // m^3 + p m^2 + (p^2 / 4 - r) m - q^2 / 8 = 0 is negative at
+        // zero, so its largest root is positive
+        let m = *solve_cubic(p, p * p / 4.0 - r, -q * q / 8.0).last().unwrap();
+        if m > 0.0 {
+            let s = (2.0 * m).sqrt();
+            ys.extend(solve_quadratic(1.0, s, p / 2.0 + m - q / (2.0 * s)));
+            ys.extend(solve_quadratic(1.0, -s, p / 2.0 + m + q / (2.0 * s)));
+        }
+    }
+
+    let mut roots: Vec<f64> = ys.iter().map(|y| {
+        let mut x = y - a / 4.0;
+        for _ in 0..2 {
+            let f = (((x + a) * x + b) * x + c) * x + d;
+            let df = ((4.0 * x + 3.0 * a) * x + 2.0 * b) * x + c;
+            if df != 0.0 {
+                x -= f / df;
+            }
+        }
+        x
+    }).collect();
+    roots.sort_by(|x, y| x.partial_cmp(y).unwrap());
+    roots
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn polynomials_have_their_roots() {
+    let close = |xs: Vec<f64>, ys: &[f64]| {
+        xs.len() == ys.len() && xs.iter().zip(ys.iter()).all(|(x, y)| (x - y).abs() < 1e-9)
+    };
+    // (x - 1)(x - 2)
+    assert!(close(solve_quadratic(1.0, -3.0, 2.0), &[1.0, 2.0]));
+    assert!(close(solve_quadratic(2.0, 0.0, 2.0), &[]));
+    // b^2 much bigger than 4 a c: the small root keeps its digits
+    let roots = solve_quadratic(1.0, -1e8, 1.0);
+    assert!((roots[0] - 1e-8).abs() < 1e-20);
+    // (x + 1)(x - 2)(x - 3), and x^3 - 8
+    assert!(close(solve_cubic(-4.0, 1.0, 6.0), &[-1.0, 2.0, 3.0]));
+    assert!(close(solve_cubic(0.0, 0.0, -8.0), &[2.0]));
+    // (x + 2)(x - 1)(x - 3)(x - 4)
+    assert!(close(solve_quartic(-6.0, 3.0, 26.0, -24.0), &[-2.0, 1.0, 3.0, 4.0]));
+    // (x^2 - 1)(x^2 - 4), with no odd terms
+    assert!(close(solve_quartic(0.0, -5.0, 0.0, 4.0), &[-2.0, -1.0, 1.0, 2.0]));
+    // (x^2 + 1)(x - 1)(x - 2): two complex roots
+    assert!(close(solve_quartic(-3.0, 3.0, -3.0, 2.0), &[1.0, 2.0]));
+    // and none at all
+    assert!(close(solve_quartic(0.0, 2.0, 0.0, 1.0), &[]));
+}
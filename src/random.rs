@@ -2,8 +2,30 @@ use rand;
 use rand::Rng;
 use rand::ThreadRng;
 
+use std::cell::RefCell;
+use std::mem;
+
 static mut RNG: Option<ThreadRng> = None;
 
+// everything random in the renderer goes through rand_double, which
+// draws from the thread's sample source if one is installed and from
+// the thread rng otherwise. Metropolis sampling installs a source that
+// replays and mutates earlier streams of numbers.
+//
+// Sources must not call rand_double themselves.
+pub trait SampleSource {
+    fn next_sample(&mut self) -> f64;
+}
+
+thread_local! {
+    static SAMPLE_SOURCE: RefCell<Option<Box<SampleSource>>> = RefCell::new(None);
+}
+
+// installs source on the current thread, returning the previous one.
+pub fn set_sample_source(source: Option<Box<SampleSource>>) -> Option<Box<SampleSource>> {
+    SAMPLE_SOURCE.with(|s| mem::replace(&mut *s.borrow_mut(), source))
+}
+
 pub fn init_rng() {
     unsafe {
         RNG = Some(rand::thread_rng());
@@ -19,6 +41,12 @@ pub fn get_rng() -> &'static mut ThreadRng {
 
 #[inline]
 pub fn rand_double() -> f64 {
+    let sample = SAMPLE_SOURCE.with(|s| {
+        s.borrow_mut().as_mut().map(|source| source.next_sample())
+    });
+    if let Some(u) = sample {
+        return u;
+    }
     rand::thread_rng().gen::<f64>()
     // unsafe {
     //     RNG.as_mut().unwrap().gen::<f64>()
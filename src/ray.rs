@@ -1,20 +1,30 @@
 use vector::Vec3;
 
+// rays happen at a time, for motion blur. The camera picks it while
+// its shutter is open, and every ray spawned along a path keeps it;
+// rays made with new() happen at time 0.
+
 #[derive(Debug, Copy, Clone)]
 pub struct Ray {
     a: Vec3,
-    b: Vec3
+    b: Vec3,
+    time: f64
 }
 
 impl Ray {
     #[inline]
     pub fn new(a: Vec3, b: Vec3) -> Ray {
-        Ray { a: a, b: b }
+        Ray::with_time(a, b, 0.0)
+    }
+
+    #[inline]
+    pub fn with_time(a: Vec3, b: Vec3, time: f64) -> Ray {
+        Ray { a: a, b: b, time: time }
     }
 
     #[inline]
     pub fn zero() -> Ray {
-        Ray { a: Vec3::zero(), b: Vec3::zero() }
+        Ray::new(Vec3::zero(), Vec3::zero())
     }
 
     #[inline]
@@ -23,6 +33,9 @@ impl Ray {
     #[inline]
     pub fn direction(&self) -> Vec3 { self.b }
 
+    #[inline]
+    pub fn time(&self) -> f64 { self.time }
+
     #[inline]
     pub fn point_at_parameter(&self, t: f64) -> Vec3 {
         self.a + t * self.b
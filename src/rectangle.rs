@@ -1,5 +1,6 @@
 use aabb::AABB;
 use aabb;
+use area_light::*;
 use hitable::*;
 use material::*;
 use ray::Ray;
@@ -114,11 +115,15 @@ impl Hitable for Rectangle {
         self.bounding_box
     }
 
-    fn importance_distribution(&self) -> Option<AABB> {
+    fn area_lights(&self) -> Vec<AreaLight> {
         if self.material.is_emitter() {
-            self.bounding_box()
+            vec![AreaLight::with_spectrum(LightShape::Rectangle {
+                bottom_left: self.bottom_left,
+                right: self.right,
+                up: self.up
+            }, self.material.emission_spectrum())]
         } else {
-            None
+            Vec::new()
         }
     }
 }
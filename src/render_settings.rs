@@ -0,0 +1,39 @@
+//////////////////////////////////////////////////////////////////////////////
+// knobs that control the estimators rather than the scene. They can
+// come from a "render_settings" block in the scene file, and the
+// command line overrides them.
+
+#[derive(Clone, Copy, Debug)]
+pub struct RenderSettings {
+    // paths are never terminated by russian roulette before min_depth
+    // bounces
+    pub min_depth: usize,
+    // hard cap on the number of bounces. It only exists to keep
+    // pathological scenes (eg. closed mirrored boxes) from spinning
+    // forever, so it should be large enough to never matter.
+    pub max_depth: usize,
+    // trace paths at sampled wavelengths rather than in RGB; see
+    // spectrum.rs
+    pub spectral: bool,
+    // the camera's, for integrators that start paths at the lights
+    pub shutter_open: f64,
+    pub shutter_close: f64
+}
+
+impl RenderSettings {
+    pub fn new(min_depth: usize, max_depth: usize) -> RenderSettings {
+        RenderSettings {
+            min_depth: min_depth,
+            max_depth: max_depth,
+            spectral: false,
+            shutter_open: 0.0,
+            shutter_close: 0.0
+        }
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings::new(3, 50)
+    }
+}
@@ -1,6 +1,8 @@
 use vector;
 use vector::Vec3;
 use random::*;
+
+use std::f64::consts::PI;
     
 //////////////////////////////////////////////////////////////////////////////
 
@@ -32,6 +34,215 @@ pub fn random_in_unit_disk() -> Vec3 {
     p
 }
 
+// a direction distributed uniformly over the cone around axis (a unit
+// vector) of directions within an angle whose cosine is 1 -
+// one_minus_cos_max. Its density is 1 / (2 pi one_minus_cos_max).
+// Taking 1 - cos rather than cos keeps narrow cones accurate.
+pub fn random_in_cone(axis: &Vec3, one_minus_cos_max: f64) -> Vec3 {
+    let cos = 1.0 - rand_double() * one_minus_cos_max;
+    let sin = (1.0 - cos * cos).max(0.0).sqrt();
+    let phi = 2.0 * PI * rand_double();
+    let (t1, t2) = vector::tangent_space(axis);
+    *axis * cos + (t1 * phi.cos() + t2 * phi.sin()) * sin
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// the rectangle corner + [0, 1] ex + [0, 1] ey (ex and ey perpendicular)
+// as seen from a point p, for sampling it uniformly by solid angle.
+// After Urena, Fajardo and King, "An Area-Preserving Parametrization for
+// Spherical Rectangles", 2013, by way of pbrt-v4.
+
+pub struct SphericalRectangle {
+    pub solid_angle: f64,
+    // the rectangle's frame, with the origin at p and z pointing away
+    // from the rectangle
+    origin: Vec3,
+    x: Vec3,
+    y: Vec3,
+    z: Vec3,
+    x0: f64,
+    x1: f64,
+    y0: f64,
+    y1: f64,
+    z0: f64,
+    b0: f64,
+    b1: f64,
+    k: f64
+}
+
+fn angle_between(v1: &Vec3, v2: &Vec3) -> f64 {
+    v1.dot(v2).clamp(-1.0, 1.0).acos()
+}
+
+impl SphericalRectangle {
+    pub fn new(p: &Vec3, corner: &Vec3, ex: &Vec3, ey: &Vec3) -> SphericalRectangle {
+        let ex_length = ex.length();
+        let ey_length = ey.length();
+        let x = *ex / ex_length;
+        let y = *ey / ey_length;
+        let mut z = vector::cross(&x, &y);
+        let d = *corner - *p;
+        let mut z0 = d.dot(&z);
+        if z0 > 0.0 {
+            z = -z;
+            z0 = -z0;
+        }
+        let x0 = d.dot(&x);
+        let y0 = d.dot(&y);
+        let x1 = x0 + ex_length;
+        let y1 = y0 + ey_length;
+
+        let v00 = Vec3::new(x0, y0, z0);
+        let v01 = Vec3::new(x0, y1, z0);
+        let v10 = Vec3::new(x1, y0, z0);
+        let v11 = Vec3::new(x1, y1, z0);
+        let n0 = vector::unit_vector(&vector::cross(&v00, &v10));
+        let n1 = vector::unit_vector(&vector::cross(&v10, &v11));
+        let n2 = vector::unit_vector(&vector::cross(&v11, &v01));
+        let n3 = vector::unit_vector(&vector::cross(&v01, &v00));
+        let g0 = angle_between(&-n0, &n1);
+        let g1 = angle_between(&-n1, &n2);
+        let g2 = angle_between(&-n2, &n3);
+        let g3 = angle_between(&-n3, &n0);
+        let k = 2.0 * PI - g2 - g3;
+        let solid_angle = g0 + g1 - k;
+        SphericalRectangle {
+            // p in the plane of the rectangle sees nothing, and gives NaNs
+            solid_angle: if solid_angle > 0.0 { solid_angle } else { 0.0 },
+            origin: *p,
+            x: x, y: y, z: z,
+            x0: x0, x1: x1, y0: y0, y1: y1, z0: z0,
+            b0: n0.z(),
+            b1: n2.z(),
+            k: k
+        }
+    }
+
+    // a point on the rectangle, distributed uniformly over the solid
+    // angle it subtends
+    pub fn sample(&self) -> Vec3 {
+        let u0 = rand_double();
+        let u1 = rand_double();
+        let au = u0 * self.solid_angle + self.k;
+        let fu = (au.cos() * self.b0 - self.b1) / au.sin();
+        let cu = (1.0 / (fu * fu + self.b0 * self.b0).sqrt())
+            .copysign(fu)
+            .clamp(-1.0 + 1e-12, 1.0 - 1e-12);
+        let xu = (-(cu * self.z0) / (1.0 - cu * cu).max(0.0).sqrt())
+            .clamp(self.x0, self.x1);
+        let dd = (xu * xu + self.z0 * self.z0).sqrt();
+        let h0 = self.y0 / (dd * dd + self.y0 * self.y0).sqrt();
+        let h1 = self.y1 / (dd * dd + self.y1 * self.y1).sqrt();
+        let hv = h0 + u1 * (h1 - h0);
+        let hv2 = hv * hv;
+        let yv = if hv2 < 1.0 - 1e-6 { hv * dd / (1.0 - hv2).sqrt() } else { self.y1 };
+        self.origin + self.x * xu + self.y * yv + self.z * self.z0
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// a discrete distribution over 0..n, in proportion to the weights it was
+// built from, sampled by binary search over its CDF. If all the weights
+// are zero, there is nothing to sample.
+
+#[derive(Debug, Clone)]
+pub struct Distribution1D {
+    pub weights: Vec<f64>,
+    // cdf[i] is the probability of choosing something before i
+    pub cdf: Vec<f64>,
+    pub total: f64
+}
+
+impl Distribution1D {
+    pub fn new(weights: Vec<f64>) -> Distribution1D {
+        let total: f64 = weights.iter().map(|w| w.max(0.0)).sum();
+        let mut cdf = Vec::with_capacity(weights.len() + 1);
+        let mut running = 0.0;
+        cdf.push(0.0);
+        for w in weights.iter() {
+            running += w.max(0.0);
+            cdf.push(if total > 0.0 { running / total } else { 0.0 });
+        }
+        Distribution1D {
+            weights: weights,
+            cdf: cdf,
+            total: total
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.weights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weights.is_empty()
+    }
+
+    // the probability of sample() choosing i
+    pub fn pmf(&self, i: usize) -> f64 {
+        if self.total > 0.0 { self.weights[i].max(0.0) / self.total } else { 0.0 }
+    }
+
+    // an index, and the probability it had of being chosen
+    pub fn sample(&self) -> Option<(usize, f64)> {
+        if self.total <= 0.0 {
+            return None;
+        }
+        let u = rand_double();
+        // the last i with cdf[i] <= u. That skips zero weights, except
+        // at the end where rounding can leave cdf[n - 1] just below 1.
+        let mut lo = 0;
+        let mut hi = self.len();
+        while hi - lo > 1 {
+            let mid = (lo + hi) / 2;
+            if self.cdf[mid] <= u { lo = mid; } else { hi = mid; }
+        }
+        while self.pmf(lo) == 0.0 {
+            lo -= 1;
+        }
+        Some((lo, self.pmf(lo)))
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// a discrete distribution over the cells of a width x height grid, by
+// weight: a row is chosen by the rows' total weights, then a cell
+// within it.
+
+#[derive(Debug, Clone)]
+pub struct Distribution2D {
+    pub rows: Vec<Distribution1D>,
+    pub marginal: Distribution1D
+}
+
+impl Distribution2D {
+    // weights are row by row
+    pub fn new(weights: &[f64], width: usize, height: usize) -> Distribution2D {
+        let rows: Vec<Distribution1D> = (0..height).map(|j| {
+            Distribution1D::new(weights[j * width..(j + 1) * width].to_vec())
+        }).collect();
+        let marginal = Distribution1D::new(rows.iter().map(|r| r.total).collect());
+        Distribution2D {
+            rows: rows,
+            marginal: marginal
+        }
+    }
+
+    pub fn pmf(&self, i: usize, j: usize) -> f64 {
+        self.marginal.pmf(j) * self.rows[j].pmf(i)
+    }
+
+    // the column and row of a cell, and the probability it had of
+    // being chosen
+    pub fn sample(&self) -> Option<(usize, usize, f64)> {
+        let (j, row_pmf) = self.marginal.sample()?;
+        let (i, pmf) = self.rows[j].sample()?;
+        Some((i, j, row_pmf * pmf))
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
 pub fn t_stat(itor: &mut std::iter::Iterator<Item=f64>,
               mean: f64) -> f64
 {
@@ -45,6 +256,7 @@ pub fn t_stat(itor: &mut std::iter::Iterator<Item=f64>,
     (ex - mean) / (exx.sqrt() / (n as f64).sqrt())
 }
 
+// the mean and standard deviation of the values
 pub fn avstdev(itor: &mut std::iter::Iterator<Item=f64>) ->
     (f64, f64)
 {
@@ -53,8 +265,8 @@ pub fn avstdev(itor: &mut std::iter::Iterator<Item=f64>) ->
     });
     let n   = sufficient.0;
     let ex  = sufficient.1/n;
-    let exx = sufficient.2 * sufficient.2/n;
-    (ex, exx - ex * ex)
+    let exx = sufficient.2/n;
+    (ex, (exx - ex * ex).max(0.0).sqrt())
 }
 
 #[test]
@@ -71,3 +283,72 @@ fn it_works()
     println!("random unit disk sampling average in x: {}", stats1.0);
     println!("random unit disk sampling average in y: {}", stats2.0);
 }
+
+#[test]
+fn distribution_1d_follows_its_weights()
+{
+    let d = Distribution1D::new(vec![1.0, 0.0, 3.0, 4.0]);
+    assert!((d.pmf(0) - 0.125).abs() < 1e-12);
+    assert!(d.pmf(1) == 0.0);
+    let n = 100000;
+    let mut counts = [0usize; 4];
+    for _ in 0..n {
+        let (i, p) = d.sample().unwrap();
+        assert!((p - d.pmf(i)).abs() < 1e-12);
+        counts[i] += 1;
+    }
+    assert!(counts[1] == 0);
+    for (i, count) in counts.iter().enumerate() {
+        assert!((*count as f64 / n as f64 - d.pmf(i)).abs() < 0.01);
+    }
+
+    assert!(Distribution1D::new(vec![0.0, 0.0]).sample().is_none());
+    assert!(Distribution1D::new(Vec::new()).sample().is_none());
+}
+
+#[test]
+fn spherical_rectangles_are_sampled_uniformly()
+{
+    // the unit square seen from one unit above its corner subtends
+    // pi / 6 steradians
+    let p = Vec3::new(0.0, 0.0, 1.0);
+    let rect = SphericalRectangle::new(&p, &Vec3::zero(),
+                                       &Vec3::new(1.0, 0.0, 0.0), &Vec3::new(0.0, 1.0, 0.0));
+    assert!((rect.solid_angle - PI / 6.0).abs() < 1e-8);
+
+    // uniform in solid angle means the area density of the samples is
+    // cos / (dist^2 solid_angle), so their average 1 / area density
+    // is the area of the square
+    let n = 100000;
+    let mut itor = (0..n).map(|_| {
+        let q = rect.sample();
+        assert!(q.x() >= -1e-8 && q.x() <= 1.0 + 1e-8);
+        assert!(q.y() >= -1e-8 && q.y() <= 1.0 + 1e-8);
+        assert!(q.z().abs() < 1e-8);
+        let d = q - p;
+        let cos = d.z().abs() / d.length();
+        d.length_squared() * rect.solid_angle / cos
+    });
+    let (average, _) = avstdev(&mut itor);
+    assert!((average - 1.0).abs() < 0.01);
+}
+
+#[test]
+fn distribution_2d_follows_its_weights()
+{
+    let d = Distribution2D::new(&[1.0, 0.0, 2.0,
+                                  0.0, 0.0, 0.0,
+                                  3.0, 2.0, 0.0], 3, 3);
+    assert!((d.pmf(0, 2) - 0.375).abs() < 1e-12);
+    assert!(d.pmf(1, 1) == 0.0);
+    let n = 100000;
+    let mut counts = [0usize; 9];
+    for _ in 0..n {
+        let (i, j, p) = d.sample().unwrap();
+        assert!((p - d.pmf(i, j)).abs() < 1e-12);
+        counts[j * 3 + i] += 1;
+    }
+    for (k, c) in counts.iter().enumerate() {
+        assert!((*c as f64 / n as f64 - d.pmf(k % 3, k / 3)).abs() < 0.01);
+    }
+}
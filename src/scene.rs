@@ -1,23 +1,41 @@
 use camera::Camera;
 use background::Background;
 use hitable::Hitable;
+use integrator::Integrator;
+use light::Light;
+use medium::Medium;
+use render_settings::RenderSettings;
 
 use std::vec::Vec;
 
 pub struct Scene {
     pub camera: Camera,
     pub background: Box<Background + Send + Sync>,
-    pub object_list: Vec<Box<Hitable + Send + Sync>>
+    pub object_list: Vec<Box<Hitable + Send + Sync>>,
+    // lights that aren't objects, like point lights
+    pub lights: Vec<Box<Light>>,
+    pub integrator: Option<Box<Integrator>>,
+    pub render_settings: RenderSettings,
+    // fog filling the whole scene
+    pub medium: Option<Box<Medium>>
 }
 
 impl Scene {
     pub fn new(camera: &Camera,
                background: Box<Background + Send + Sync>,
-               object_list: Vec<Box<Hitable + Send + Sync>>) -> Scene {
+               object_list: Vec<Box<Hitable + Send + Sync>>,
+               lights: Vec<Box<Light>>,
+               integrator: Option<Box<Integrator>>,
+               render_settings: RenderSettings,
+               medium: Option<Box<Medium>>) -> Scene {
         Scene {
             camera: *camera,
             background: background,
-            object_list: object_list
+            object_list: object_list,
+            lights: lights,
+            integrator: integrator,
+            render_settings: render_settings,
+            medium: medium
         }
     }
 }
@@ -0,0 +1,240 @@
+use aabb::AABB;
+use area_light::AreaLight;
+use hitable::*;
+use material::*;
+use ray::Ray;
+use vector::Vec3;
+use vector;
+
+//////////////////////////////////////////////////////////////////////////////
+// implicit surfaces: the zero set of a signed distance function,
+// negative inside, built from a small expression tree of shapes and
+// the ways to combine and bend them.
+//
+// The shapes' distances are exact, and so are union, translation and
+// repetition (as long as repeated copies stay inside their cells), but
+// intersection, difference, smooth union and twist only bound the
+// distance from below, or not even that: twists can overstep, which is
+// what SdfSurface's step is for.
+
+#[derive(Debug, Clone)]
+pub enum Sdf {
+    Sphere { center: Vec3, radius: f64 },
+    // half_size along each axis, with its edges rounded off by rounding
+    Box { center: Vec3, half_size: Vec3, rounding: f64 },
+    // around the y axis
+    Torus { center: Vec3, major_radius: f64, minor_radius: f64 },
+    // along the y axis, capped, height high
+    Cylinder { center: Vec3, radius: f64, height: f64 },
+    Plane { point: Vec3, normal: Vec3 },
+    Union(Vec<Sdf>),
+    Intersection(Vec<Sdf>),
+    // the first, without the second
+    Difference(Box<Sdf>, Box<Sdf>),
+    // blends over a distance of about k where they meet
+    SmoothUnion(Vec<Sdf>, f64),
+    Translate(Vec3, Box<Sdf>),
+    // copies every period along each axis (not along those where it's
+    // zero), with the original in the cell around the origin
+    Repeat(Vec3, Box<Sdf>),
+    // turns by rate radians per unit of height, around the y axis
+    Twist(f64, Box<Sdf>)
+}
+
+// polynomial smooth minimum, after Inigo Quilez
+pub fn smooth_min(a: f64, b: f64, k: f64) -> f64 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b * (1.0 - h) + a * h - k * h * (1.0 - h)
+}
+
+impl Sdf {
+    pub fn distance(&self, p: &Vec3) -> f64 {
+        match *self {
+            Sdf::Sphere { center, radius } => (*p - center).length() - radius,
+            Sdf::Box { center, half_size, rounding } => {
+                let q = *p - center;
+                let r = rounding.max(0.0);
+                let d = Vec3::new(q.x().abs() - half_size.x() + r,
+                                  q.y().abs() - half_size.y() + r,
+                                  q.z().abs() - half_size.z() + r);
+                let outside = Vec3::new(d.x().max(0.0), d.y().max(0.0), d.z().max(0.0)).length();
+                let inside = d.x().max(d.y()).max(d.z()).min(0.0);
+                outside + inside - r
+            },
+            Sdf::Torus { center, major_radius, minor_radius } => {
+                let q = *p - center;
+                let ring = (q.x() * q.x() + q.z() * q.z()).sqrt() - major_radius;
+                (ring * ring + q.y() * q.y()).sqrt() - minor_radius
+            },
+            Sdf::Cylinder { center, radius, height } => {
+                let q = *p - center;
+                let dr = (q.x() * q.x() + q.z() * q.z()).sqrt() - radius;
+                let dy = q.y().abs() - height / 2.0;
+                (dr.max(0.0) * dr.max(0.0) + dy.max(0.0) * dy.max(0.0)).sqrt() + dr.max(dy).min(0.0)
+            },
+            Sdf::Plane { point, normal } => (*p - point).dot(&vector::unit_vector(&normal)),
+            Sdf::Union(ref sdfs) => sdfs.iter().map(|s| s.distance(p)).fold(1e20, f64::min),
+            Sdf::Intersection(ref sdfs) => sdfs.iter().map(|s| s.distance(p)).fold(-1e20, f64::max),
+            Sdf::Difference(ref a, ref b) => a.distance(p).max(-b.distance(p)),
+            Sdf::SmoothUnion(ref sdfs, k) => {
+                let mut distances = sdfs.iter().map(|s| s.distance(p));
+                let first = distances.next().unwrap_or(1e20);
+                distances.fold(first, |a, b| smooth_min(a, b, k))
+            },
+            Sdf::Translate(offset, ref sdf) => sdf.distance(&(*p - offset)),
+            Sdf::Repeat(period, ref sdf) => {
+                let mut q = *p;
+                for axis in 0..3 {
+                    if period[axis] > 0.0 {
+                        q[axis] -= period[axis] * (q[axis] / period[axis]).round();
+                    }
+                }
+                sdf.distance(&q)
+            },
+            Sdf::Twist(rate, ref sdf) => {
+                let (sin, cos) = (-rate * p.y()).sin_cos();
+                sdf.distance(&Vec3::new(cos * p.x() - sin * p.z(), p.y(), sin * p.x() + cos * p.z()))
+            }
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// a signed distance function in the scene, inside a box that must hold
+// its surface (it's only looked for there). Rays are sphere traced:
+// each step goes as far along the ray as the distance function says is
+// empty, times step, which has to be below 1 for functions that
+// overstep.
+
+pub struct SdfSurface {
+    pub sdf: Sdf,
+    pub bbox: AABB,
+    pub material: Box<Material>,
+    pub step: f64
+}
+
+const MAX_STEPS: usize = 1000;
+
+impl SdfSurface {
+    pub fn new(sdf: Sdf, bbox: AABB, material: Box<Material>, step: f64) -> SdfSurface {
+        SdfSurface {
+            sdf: sdf,
+            bbox: bbox,
+            material: material,
+            step: step
+        }
+    }
+
+    // closer than this to the surface counts as on it
+    fn epsilon(&self) -> f64 {
+        (self.bbox.max() - self.bbox.min()).length() * 1e-6
+    }
+
+    // the gradient, by central differences
+    pub fn normal(&self, p: &Vec3) -> Vec3 {
+        let h = self.epsilon() * 10.0;
+        let d = |dx: f64, dy: f64, dz: f64| self.sdf.distance(&(*p + Vec3::new(dx, dy, dz)));
+        vector::unit_vector(&Vec3::new(d(h, 0.0, 0.0) - d(-h, 0.0, 0.0),
+                                       d(0.0, h, 0.0) - d(0.0, -h, 0.0),
+                                       d(0.0, 0.0, h) - d(0.0, 0.0, -h)))
+    }
+}
+
+impl Hitable for SdfSurface {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'a>> {
+        let (t0, t1) = self.bbox.ray_interval(r, t_min, t_max)?;
+        let length = r.direction().length();
+        let eps = self.epsilon();
+        // rays leaving the surface start on it, so which side of it
+        // they're on is judged a little way in, and the surface is
+        // looked for from there on; others start where they enter the
+        // box, on the side they're on there
+        let leaving = self.sdf.distance(&r.point_at_parameter(t_min)).abs() < eps;
+        let mut t = if leaving { t0 + eps * 100.0 / length } else { t0 };
+        let inside = self.sdf.distance(&r.point_at_parameter(t)) < 0.0;
+        for _ in 0..MAX_STEPS {
+            if t >= t1 {
+                return None;
+            }
+            let p = r.point_at_parameter(t);
+            let d = self.sdf.distance(&p);
+            let ahead = if inside { -d } else { d };
+            if ahead < eps {
+                return Some(HitRecord::hit(t, p, self.normal(&p), &*self.material));
+            }
+            t += ahead * self.step / length;
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.bbox)
+    }
+
+    fn area_lights(&self) -> Vec<AreaLight> {
+        Vec::new()
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn sdfs_are_sphere_traced() {
+    use lambertian::Lambertian;
+    use std::f64::consts::PI;
+
+    let unit_sphere = Sdf::Sphere { center: Vec3::zero(), radius: 1.0 };
+    let bounds = AABB::new(Vec3::new(-2.0, -2.0, -2.0), Vec3::new(2.0, 2.0, 2.0));
+    let surface = SdfSurface::new(unit_sphere.clone(), bounds, Lambertian::new(&Vec3::new(0.5, 0.5, 0.5)), 1.0);
+
+    // the same as the analytic sphere, outside and in
+    let ray = Ray::new(Vec3::new(0.3, 0.2, 5.0), Vec3::new(0.0, 0.0, -2.0));
+    let hr = surface.hit(&ray, 0.00001, 1e20).unwrap();
+    let z = (1.0f64 - 0.09 - 0.04).sqrt();
+    assert!((hr.p - Vec3::new(0.3, 0.2, z)).length() < 1e-5);
+    assert!((hr.t - (5.0 - z) / 2.0).abs() < 1e-5);
+    assert!((hr.normal - hr.p).length() < 1e-4);
+    // a ray leaving the surface inwards finds the far side, not the
+    // point it left from
+    let through = Ray::new(hr.p, Vec3::new(0.0, 0.0, -1.0));
+    let hr = surface.hit(&through, 0.00001, 1e20).unwrap();
+    assert!((hr.p - Vec3::new(0.3, 0.2, -z)).length() < 1e-5);
+    // and one leaving outwards nothing
+    assert!(surface.hit(&Ray::new(hr.p, Vec3::new(0.0, 0.0, -1.0)), 0.00001, 1e20).is_none());
+    // but rays starting just off it, closer than that first step, hit
+    // it from whichever side they're on
+    let hr = surface.hit(&Ray::new(Vec3::new(0.0, 0.0, 1.0003), Vec3::new(0.0, 0.0, -1.0)), 0.0, 1e20).unwrap();
+    assert!((hr.p - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-5);
+    let hr = surface.hit(&Ray::new(Vec3::new(0.0, 0.0, 0.9997), Vec3::new(0.0, 0.0, 1.0)), 0.0, 1e20).unwrap();
+    assert!((hr.p - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-5);
+
+    // shapes and combinations
+    let p = Vec3::new(3.0, 0.0, 0.0);
+    let cube = Sdf::Box { center: Vec3::zero(), half_size: Vec3::new(1.0, 1.0, 1.0), rounding: 0.0 };
+    assert!((cube.distance(&Vec3::new(2.0, 2.0, 1.0)) - 2.0f64.sqrt()).abs() < 1e-12);
+    assert!((cube.distance(&Vec3::zero()) + 1.0).abs() < 1e-12);
+    let torus = Sdf::Torus { center: Vec3::zero(), major_radius: 2.0, minor_radius: 0.5 };
+    assert!((torus.distance(&Vec3::new(0.0, 0.0, 2.0)) + 0.5).abs() < 1e-12);
+    assert!((torus.distance(&Vec3::zero()) - 1.5).abs() < 1e-12);
+    let moved = Sdf::Translate(Vec3::new(3.0, 0.0, 0.0), Box::new(unit_sphere.clone()));
+    assert!((moved.distance(&p) + 1.0).abs() < 1e-12);
+    let both = Sdf::Union(vec![unit_sphere.clone(), moved.clone()]);
+    assert!((both.distance(&Vec3::new(1.5, 0.0, 0.0)) - 0.5).abs() < 1e-12);
+    // smooth unions fill in where they meet
+    let blended = Sdf::SmoothUnion(vec![unit_sphere.clone(), moved.clone()], 1.0);
+    assert!(blended.distance(&Vec3::new(1.5, 0.0, 0.0)) < 0.5);
+    assert!((blended.distance(&Vec3::new(-2.0, 0.0, 0.0)) - 1.0).abs() < 1e-12);
+    // copies every 3 along x, and nowhere else
+    let row = Sdf::Repeat(Vec3::new(3.0, 0.0, 0.0), Box::new(unit_sphere.clone()));
+    assert!((row.distance(&Vec3::new(30.0, 1.0, 0.0))).abs() < 1e-12);
+    assert!((row.distance(&Vec3::new(30.0, 3.0, 0.0)) - 2.0).abs() < 1e-12);
+    // a bar along x, a quarter turn further round one unit up
+    let bar = Sdf::Box { center: Vec3::zero(), half_size: Vec3::new(2.0, 5.0, 0.1), rounding: 0.0 };
+    let twisted = Sdf::Twist(PI / 2.0, Box::new(bar));
+    assert!(twisted.distance(&Vec3::new(1.5, 0.0, 0.0)) < 0.0);
+    assert!(twisted.distance(&Vec3::new(1.5, 1.0, 0.0)) > 0.0);
+    assert!(twisted.distance(&Vec3::new(0.0, 1.0, 1.5)) < 0.0);
+}
@@ -0,0 +1,256 @@
+use background::Background;
+use random::*;
+use sampling;
+use vector::*;
+
+use std::f64::consts::PI;
+
+//////////////////////////////////////////////////////////////////////////////
+// a clear daylight sky, after Preetham, Shirley and Smits, "A Practical
+// Analytic Model for Daylight", 1999. The sky's luminance and
+// chromaticity come from Perez distributions fitted to the turbidity
+// and the sun's height; the sun is a disc of the real sun's size whose
+// color is what Rayleigh and aerosol scattering leave of it. Below the
+// horizon is a diffuse ground lit by both, without shadows.
+//
+// One unit of radiance is 10 kcd/m^2 (before intensity), which puts a
+// clear zenith between 0.2 and 2 and the noon sun's irradiance around
+// 10.
+
+const LUMINANCE_UNIT: f64 = 10.0;
+
+// the sun's angular radius, and its luminance outside the atmosphere,
+// in kcd/m^2
+const SUN_ANGULAR_RADIUS: f64 = 0.004654;
+const SUN_LUMINANCE: f64 = 2.0e6;
+
+// wavelengths standing in for the red, green and blue channels, in
+// micrometers
+const RGB_WAVELENGTHS: [f64; 3] = [0.65, 0.55, 0.45];
+
+// Perez et al's five-parameter distribution of sky luminance over the
+// zenith angle theta and the angle gamma from the sun
+#[derive(Debug, Clone, Copy)]
+struct Perez {
+    a: f64, b: f64, c: f64, d: f64, e: f64
+}
+
+impl Perez {
+    fn f(&self, cos_theta: f64, gamma: f64) -> f64 {
+        let cos_gamma = gamma.cos();
+        (1.0 + self.a * (self.b / cos_theta.max(0.01)).exp()) *
+            (1.0 + self.c * (self.d * gamma).exp() + self.e * cos_gamma * cos_gamma)
+    }
+}
+
+pub struct PreethamSky {
+    // unit vector towards the sun
+    pub sun_direction: Vec3,
+    pub turbidity: f64,
+    pub ground_albedo: Vec3,
+    pub intensity: f64,
+    pub sun_radiance: Vec3,
+    pub ground_radiance: Vec3,
+    perez: [Perez; 3],
+    // luminance and chromaticity at the zenith, over the Perez
+    // distributions' value there
+    zenith: [f64; 3],
+    // 1 - the cosine of the sun's angular radius
+    sun_one_minus_cos: f64,
+    // how often sample_direction aims at the sun
+    sun_probability: f64,
+    average_luminance: f64
+}
+
+impl PreethamSky {
+    pub fn new(sun_direction: &Vec3, turbidity: f64, ground_albedo: &Vec3, intensity: f64) -> PreethamSky {
+        let sun_direction = unit_vector(sun_direction);
+        // the fits are made for hazy to clear skies, and with the sun up
+        let t = turbidity.clamp(1.7, 10.0);
+        let theta_s = sun_direction.y().clamp(-1.0, 1.0).acos().min(PI / 2.0 - 0.01);
+
+        let perez = [
+            Perez { a:  0.1787 * t - 1.4630, b: -0.3554 * t + 0.4275, c: -0.0227 * t + 5.3251,
+                    d:  0.1206 * t - 2.5771, e: -0.0670 * t + 0.3703 },
+            Perez { a: -0.0193 * t - 0.2592, b: -0.0665 * t + 0.0008, c: -0.0004 * t + 0.2125,
+                    d: -0.0641 * t - 0.8989, e: -0.0033 * t + 0.0452 },
+            Perez { a: -0.0167 * t - 0.2608, b: -0.0950 * t + 0.0092, c: -0.0079 * t + 0.2102,
+                    d: -0.0441 * t - 1.6537, e: -0.0109 * t + 0.0529 }];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let (th, th2, th3) = (theta_s, theta_s * theta_s, theta_s * theta_s * theta_s);
+        let zenith_x =
+            t * t * (0.00166 * th3 - 0.00375 * th2 + 0.00209 * th) +
+            t * (-0.02903 * th3 + 0.06377 * th2 - 0.03202 * th + 0.00394) +
+            (0.11693 * th3 - 0.21196 * th2 + 0.06052 * th + 0.25886);
+        let zenith_y =
+            t * t * (0.00275 * th3 - 0.00610 * th2 + 0.00317 * th) +
+            t * (-0.04214 * th3 + 0.08970 * th2 - 0.04153 * th + 0.00516) +
+            (0.15346 * th3 - 0.26756 * th2 + 0.06670 * th + 0.26688);
+        let zenith_values = [zenith_luminance, zenith_x, zenith_y];
+        let mut zenith = [0.0; 3];
+        for i in 0..3 {
+            zenith[i] = zenith_values[i] / perez[i].f(1.0, theta_s);
+        }
+
+        let sun_radiance = if sun_direction.y() > 0.0 {
+            sun_transmittance(sun_direction.y().acos(), t) * (SUN_LUMINANCE / LUMINANCE_UNIT)
+        } else {
+            Vec3::zero()
+        };
+        let half = SUN_ANGULAR_RADIUS / 2.0;
+        let sun_one_minus_cos = 2.0 * half.sin() * half.sin();
+        let sun_solid_angle = 2.0 * PI * sun_one_minus_cos;
+
+        let mut sky = PreethamSky {
+            sun_direction: sun_direction,
+            turbidity: t,
+            ground_albedo: *ground_albedo,
+            intensity: intensity,
+            sun_radiance: sun_radiance,
+            ground_radiance: Vec3::zero(),
+            perez: perez,
+            zenith: zenith,
+            sun_one_minus_cos: sun_one_minus_cos,
+            sun_probability: 0.0,
+            average_luminance: 0.0
+        };
+
+        // light reaching the ground, and the luminance of the upper
+        // hemisphere, by the midpoint rule
+        let (n_theta, n_phi) = (32, 64);
+        let mut sky_irradiance = Vec3::zero();
+        let mut sky_luminance = 0.0;
+        for i in 0..n_theta {
+            let theta = (i as f64 + 0.5) / n_theta as f64 * PI / 2.0;
+            let d_omega = theta.sin() * (PI / 2.0 / n_theta as f64) * (2.0 * PI / n_phi as f64);
+            for j in 0..n_phi {
+                let phi = (j as f64 + 0.5) / n_phi as f64 * 2.0 * PI;
+                let d = Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+                let l = sky.sky_radiance(&d);
+                sky_irradiance = sky_irradiance + l * (theta.cos() * d_omega);
+                sky_luminance += luminance(&l) * d_omega;
+            }
+        }
+        let sun_irradiance = sun_radiance * (sun_solid_angle * sun_direction.y().max(0.0));
+        sky.ground_radiance = *ground_albedo * (sky_irradiance + sun_irradiance) / PI;
+
+        let sun_power = luminance(&sun_radiance) * sun_solid_angle;
+        let ground_power = luminance(&sky.ground_radiance) * 2.0 * PI;
+        let total = sky_luminance + ground_power + sun_power;
+        sky.sun_probability = if total > 0.0 { (sun_power / total).clamp(0.0, 0.9) } else { 0.0 };
+        sky.average_luminance = total * intensity / (4.0 * PI);
+        sky
+    }
+
+    // the sky alone, above the horizon
+    fn sky_radiance(&self, unit_direction: &Vec3) -> Vec3 {
+        let cos_theta = unit_direction.y().max(0.0);
+        let gamma = unit_direction.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+        let big_y = (self.zenith[0] * self.perez[0].f(cos_theta, gamma)).max(0.0);
+        let x = self.zenith[1] * self.perez[1].f(cos_theta, gamma);
+        let y = self.zenith[2] * self.perez[2].f(cos_theta, gamma);
+        if y <= 0.0 {
+            return Vec3::zero();
+        }
+        let xyz = Vec3::new(x / y * big_y, big_y, (1.0 - x - y) / y * big_y);
+        let rgb = xyz_to_rgb(&xyz) / LUMINANCE_UNIT;
+        Vec3::new(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0))
+    }
+
+    fn in_sun(&self, unit_direction: &Vec3) -> bool {
+        1.0 - unit_direction.dot(&self.sun_direction) <= self.sun_one_minus_cos
+    }
+}
+
+// the fraction of the sun's light that makes it through the atmosphere
+// at zenith angle theta: Rayleigh scattering, and aerosols after
+// Angstrom's formula with Preetham et al's turbidity fit. Absorption by
+// ozone and other gases is left out.
+fn sun_transmittance(theta: f64, turbidity: f64) -> Vec3 {
+    // relative optical mass, after Kasten
+    let m = 1.0 / (theta.cos() + 0.15 * (93.885 - theta.to_degrees()).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let alpha = 1.3;
+    let t: Vec<f64> = RGB_WAVELENGTHS.iter().map(|lambda| {
+        let rayleigh = (-0.008735 * lambda.powf(-4.08) * m).exp();
+        let aerosol = (-beta * lambda.powf(-alpha) * m).exp();
+        rayleigh * aerosol
+    }).collect();
+    Vec3::new(t[0], t[1], t[2])
+}
+
+impl Background for PreethamSky {
+    fn get_background(&self, unit_direction: &Vec3) -> Vec3 {
+        if unit_direction.y() < 0.0 {
+            return self.ground_radiance * self.intensity;
+        }
+        let mut result = self.sky_radiance(unit_direction);
+        if self.in_sun(unit_direction) {
+            result = result + self.sun_radiance;
+        }
+        result * self.intensity
+    }
+
+    // the sun's disc uniformly some of the time, everything else
+    // uniformly over the sphere the rest
+    fn sample_direction(&self) -> (Vec3, f64) {
+        let direction = if rand_double() < self.sun_probability {
+            unit_vector(&sampling::random_in_cone(&self.sun_direction, self.sun_one_minus_cos))
+        } else {
+            sampling::random_3d_direction()
+        };
+        (direction, self.direction_pdf(&direction))
+    }
+
+    fn direction_pdf(&self, unit_direction: &Vec3) -> f64 {
+        let mut pdf = (1.0 - self.sun_probability) / (4.0 * PI);
+        if self.in_sun(unit_direction) {
+            pdf += self.sun_probability / (2.0 * PI * self.sun_one_minus_cos);
+        }
+        pdf
+    }
+
+    fn average_luminance(&self) -> f64 {
+        self.average_luminance
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn preetham_skies_look_like_daylight() {
+    let noon = PreethamSky::new(&Vec3::new(0.0, 1.0, 0.2), 3.0, &Vec3::new(0.3, 0.3, 0.3), 1.0);
+    let evening = PreethamSky::new(&Vec3::new(0.0, 0.1, 1.0), 3.0, &Vec3::new(0.3, 0.3, 0.3), 1.0);
+
+    // the sky is blue, brighter towards the sun, and the ground is
+    // lit by it
+    let zenith = noon.get_background(&Vec3::new(0.0, 1.0, 0.0));
+    assert!(zenith.z() > zenith.x());
+    let near_sun = noon.get_background(&unit_vector(&Vec3::new(0.0, 1.0, 0.4)));
+    let away = noon.get_background(&unit_vector(&Vec3::new(0.0, 1.0, -1.0)));
+    assert!(luminance(&near_sun) > luminance(&away));
+    assert!(luminance(&noon.get_background(&Vec3::new(0.0, -1.0, 0.0))) > 0.0);
+
+    // the sun is much brighter than the sky, and redder when it's low
+    let sun = noon.get_background(&noon.sun_direction);
+    assert!(luminance(&sun) > 1000.0 * luminance(&zenith));
+    let low_sun = evening.get_background(&evening.sun_direction);
+    assert!(low_sun.x() / low_sun.z() > sun.x() / sun.z());
+    assert!(noon.ground_radiance.x() > evening.ground_radiance.x());
+}
+
+#[test]
+fn preetham_skies_are_sampled_by_their_density() {
+    let sky = PreethamSky::new(&Vec3::new(1.0, 1.0, 0.0), 4.0, &Vec3::new(0.2, 0.2, 0.2), 1.0);
+    assert!(sky.sun_probability > 0.1);
+    let n = 200000;
+    let mut itor = (0..n).map(|_| {
+        let (d, pdf) = sky.sample_direction();
+        assert!((pdf - sky.direction_pdf(&d)).abs() < 1e-8 * pdf);
+        luminance(&sky.get_background(&d)) / pdf
+    });
+    let (integral, _) = sampling::avstdev(&mut itor);
+    assert!((integral / (4.0 * PI * sky.average_luminance()) - 1.0).abs() < 0.02);
+}
@@ -0,0 +1,458 @@
+use background::Background;
+use random::*;
+use vector::*;
+
+use std::cell::Cell;
+use std::sync::Arc;
+use std::sync::OnceLock;
+
+//////////////////////////////////////////////////////////////////////////////
+// spectral rendering. Paths normally carry RGB in their Vec3s; in
+// spectral mode they carry the values of the spectrum at three
+// wavelengths instead, one per component. The wavelengths are chosen
+// per camera sample with hero wavelength sampling (Wilkie et al. 2014):
+// the first uniformly over the visible range, the other two a third and
+// two thirds of the range further along, wrapping around.
+//
+// The wavelengths of the path being traced live in a thread-local, like
+// the sample source in random.rs, so materials and lights can turn
+// their RGB parameters into spectral values without anyone passing the
+// wavelengths around. Outside spectral mode there are none, and the
+// conversions leave RGB alone.
+//
+// Surfaces that send different wavelengths different ways (dispersive
+// glass) can only follow one of them, through single_wavelength. In
+// spectral mode that's the hero, and the other two are terminated: from
+// then on the path only estimates the hero wavelength, which counts
+// three times. RGB paths pick one of the channels, and a wavelength in
+// its band, and only estimate that channel, three times over. Whether
+// that happens is decided by the part of the path that doesn't depend
+// on the wavelengths, so the estimate stays unbiased.
+
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 720.0;
+
+// the wavelengths red, green and blue stand for in RGB renders
+const CHANNEL_BANDS: [(f64, f64); 3] = [(580.0, 720.0), (490.0, 580.0), (380.0, 490.0)];
+
+#[derive(Clone, Copy)]
+struct PathState {
+    // between begin_path and end_path
+    in_path: bool,
+    // None in RGB mode
+    wavelengths: Option<Vec3>,
+    // the channel and wavelength the path follows alone, once it has
+    // had to pick one
+    single: Option<(usize, f64)>
+}
+
+thread_local! {
+    static PATH: Cell<PathState> = const { Cell::new(PathState {
+        in_path: false,
+        wavelengths: None,
+        single: None
+    }) };
+}
+
+// starts a camera path on the current thread, at the given wavelengths,
+// or None for RGB
+pub fn begin_path(wavelengths: Option<Vec3>) {
+    PATH.with(|p| p.set(PathState {
+        in_path: true,
+        wavelengths: wavelengths,
+        single: None
+    }));
+}
+
+// the linear RGB estimate from the radiance the path found
+pub fn end_path(values: &Vec3) -> Vec3 {
+    let state = PATH.with(|p| p.replace(PathState {
+        in_path: false,
+        wavelengths: None,
+        single: None
+    }));
+    let values = match state.single {
+        None => *values,
+        Some((c, _)) => {
+            let mut single = Vec3::zero();
+            single[c] = 3.0 * values[c];
+            single
+        }
+    };
+    match state.wavelengths {
+        None => values,
+        Some(lambda) => to_rgb(&values, &lambda)
+    }
+}
+
+pub fn wavelengths() -> Option<Vec3> {
+    PATH.with(|p| p.get().wavelengths)
+}
+
+// the one wavelength a path that can't follow all of them follows, and
+// the weight of the event. Paths begun with begin_path keep to the
+// same wavelength, and end_path weights it; anything else (photons, say)
+// picks a channel for just this event, and weights it here.
+pub fn single_wavelength() -> (f64, Vec3) {
+    let mut state = PATH.with(|p| p.get());
+    if let Some((_, lambda)) = state.single {
+        return (lambda, Vec3::new(1.0, 1.0, 1.0));
+    }
+    let (c, lambda) = match state.wavelengths {
+        Some(lambda) => (0, lambda[0]),
+        None => {
+            let c = ((3.0 * rand_double()) as usize).min(2);
+            let (lo, hi) = CHANNEL_BANDS[c];
+            (c, lo + rand_double() * (hi - lo))
+        }
+    };
+    if state.in_path {
+        state.single = Some((c, lambda));
+        PATH.with(|p| p.set(state));
+        (lambda, Vec3::new(1.0, 1.0, 1.0))
+    } else {
+        let mut weight = Vec3::zero();
+        weight[c] = 3.0;
+        (lambda, weight)
+    }
+}
+
+pub fn sample_wavelengths() -> Vec3 {
+    let u = rand_double();
+    let lambda = |offset: f64| LAMBDA_MIN + ((u + offset) % 1.0) * (LAMBDA_MAX - LAMBDA_MIN);
+    Vec3::new(lambda(0.0), lambda(1.0 / 3.0), lambda(2.0 / 3.0))
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// from spectra to RGB
+
+fn lobe(lambda: f64, mu: f64, sigma_below: f64, sigma_above: f64) -> f64 {
+    let t = (lambda - mu) / if lambda < mu { sigma_below } else { sigma_above };
+    (-0.5 * t * t).exp()
+}
+
+// the CIE 1931 2-degree color matching functions, after the multi-lobe
+// fit of Wyman, Sloan and Shirley, "Simple Analytic Approximations to
+// the CIE XYZ Color Matching Functions", 2013. lambda is in nm.
+pub fn cie_xyz(lambda: f64) -> Vec3 {
+    Vec3::new(1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+              - 0.065 * lobe(lambda, 501.1, 20.4, 26.2),
+              0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1),
+              1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8))
+}
+
+// RGB of the spectrum that's 1 everywhere. Colors are divided by it so
+// that spectra upsampled from white come back white.
+fn white_rgb() -> Vec3 {
+    static WHITE: OnceLock<Vec3> = OnceLock::new();
+    *WHITE.get_or_init(|| xyz_to_rgb(&integrate_xyz(|_| 1.0)))
+}
+
+// the XYZ of spectrum over the visible range, by the midpoint rule
+fn integrate_xyz<F: Fn(f64) -> f64>(spectrum: F) -> Vec3 {
+    let n = 680;
+    let d_lambda = (LAMBDA_MAX - LAMBDA_MIN) / n as f64;
+    (0..n).fold(Vec3::zero(), |acc, i| {
+        let lambda = LAMBDA_MIN + (i as f64 + 0.5) * d_lambda;
+        acc + cie_xyz(lambda) * (spectrum(lambda) * d_lambda)
+    })
+}
+
+fn xyz_to_balanced_rgb(xyz: &Vec3) -> Vec3 {
+    xyz_to_rgb(xyz) / white_rgb()
+}
+
+// the RGB estimate from the values of a spectrum at wavelengths drawn
+// by sample_wavelengths
+pub fn to_rgb(values: &Vec3, wavelengths: &Vec3) -> Vec3 {
+    let xyz = (0..3).fold(Vec3::zero(), |acc, i| acc + cie_xyz(wavelengths[i]) * values[i]);
+    xyz_to_balanced_rgb(&(xyz * ((LAMBDA_MAX - LAMBDA_MIN) / 3.0)))
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// from RGB to spectra, after Smits, "An RGB to Spectrum Conversion for
+// Reflectances", 1999: the color is made of as much white as its
+// smallest component, then as much of a secondary (cyan, magenta or
+// yellow) as it can, and the rest of a primary. The bases are piecewise
+// constant over ten bins spanning the visible range.
+
+const SMITS_WHITE: [f64; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f64; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f64; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f64; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f64; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f64; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f64; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+// the value at lambda of the spectrum Smits' method makes of rgb.
+// Greys come out exactly flat.
+pub fn rgb_spectrum_at(rgb: &Vec3, lambda: f64) -> f64 {
+    let (r, g, b) = (rgb.r(), rgb.g(), rgb.b());
+    if r == g && g == b {
+        return r;
+    }
+    let bin = (((lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN) * 10.0) as isize).clamp(0, 9) as usize;
+    if r <= g && r <= b {
+        r * SMITS_WHITE[bin] + if g <= b {
+            (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin]
+        } else {
+            (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin]
+        }
+    } else if g <= r && g <= b {
+        g * SMITS_WHITE[bin] + if r <= b {
+            (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin]
+        } else {
+            (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin]
+        }
+    } else {
+        b * SMITS_WHITE[bin] + if r <= g {
+            (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin]
+        } else {
+            (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin]
+        }
+    }
+}
+
+// rgb as the current path sees it: unchanged in RGB mode, and
+// upsampled to the path's wavelengths in spectral mode
+pub fn upsample(rgb: &Vec3) -> Vec3 {
+    match wavelengths() {
+        None => *rgb,
+        Some(lambda) => Vec3::new(rgb_spectrum_at(rgb, lambda[0]),
+                                  rgb_spectrum_at(rgb, lambda[1]),
+                                  rgb_spectrum_at(rgb, lambda[2]))
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// spectra given in scene files: either RGB, which is upsampled in
+// spectral mode, or tabulated, which is reduced to RGB outside it.
+
+#[derive(Debug, Clone)]
+pub struct Spectrum {
+    // what the spectrum looks like in RGB mode
+    pub rgb: Vec3,
+    // (wavelength in nm, value) pairs, by wavelength, interpolated
+    // linearly and extended flat past the ends. None for RGB spectra.
+    pub samples: Option<Arc<Vec<(f64, f64)>>>
+}
+
+impl Spectrum {
+    pub fn from_rgb(rgb: &Vec3) -> Spectrum {
+        Spectrum {
+            rgb: *rgb,
+            samples: None
+        }
+    }
+
+    pub fn constant(value: f64) -> Spectrum {
+        Spectrum::from_rgb(&Vec3::new(value, value, value))
+    }
+
+    pub fn from_samples(mut samples: Vec<(f64, f64)>) -> Spectrum {
+        samples.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let samples = Arc::new(samples);
+        let rgb = {
+            let s = samples.clone();
+            xyz_to_balanced_rgb(&integrate_xyz(|lambda| interpolate(&s, lambda)))
+        };
+        Spectrum {
+            rgb: rgb,
+            samples: Some(samples)
+        }
+    }
+
+    // f, in nm, sampled every 5 nm over the visible range
+    pub fn tabulate<F: Fn(f64) -> f64>(f: F) -> Spectrum {
+        Spectrum::from_samples((0..69).map(|i| {
+            let lambda = LAMBDA_MIN + 5.0 * i as f64;
+            (lambda, f(lambda))
+        }).collect())
+    }
+
+    // Planck's law at temperature (in kelvin), scaled to a luminance
+    // of one
+    pub fn blackbody(temperature: f64) -> Spectrum {
+        let c = 299792458.0;
+        let h = 6.62607015e-34;
+        let k = 1.380649e-23;
+        let planck = |lambda: f64| {
+            let l = lambda * 1e-9;
+            2.0 * h * c * c / (l.powi(5) * ((h * c / (l * k * temperature)).exp() - 1.0))
+        };
+        let unscaled = Spectrum::tabulate(planck);
+        unscaled.scale(1.0 / luminance(&unscaled.rgb))
+    }
+
+    pub fn scale(&self, s: f64) -> Spectrum {
+        match self.samples {
+            None => Spectrum::from_rgb(&(self.rgb * s)),
+            Some(ref samples) => Spectrum {
+                rgb: self.rgb * s,
+                samples: Some(Arc::new(samples.iter().map(|&(l, v)| (l, v * s)).collect()))
+            }
+        }
+    }
+
+    pub fn value_at(&self, lambda: f64) -> f64 {
+        match self.samples {
+            None => rgb_spectrum_at(&self.rgb, lambda),
+            Some(ref samples) => interpolate(samples, lambda)
+        }
+    }
+
+    // the spectrum as the current path sees it, like upsample
+    pub fn values(&self) -> Vec3 {
+        match wavelengths() {
+            None => self.rgb,
+            Some(lambda) => Vec3::new(self.value_at(lambda[0]),
+                                      self.value_at(lambda[1]),
+                                      self.value_at(lambda[2]))
+        }
+    }
+
+    // whether the value is the same at every wavelength
+    pub fn is_constant(&self) -> bool {
+        match self.samples {
+            None => self.rgb.r() == self.rgb.g() && self.rgb.g() == self.rgb.b(),
+            Some(ref samples) => samples.iter().all(|s| s.1 == samples[0].1)
+        }
+    }
+
+    pub fn lerp(a: &Spectrum, b: &Spectrum, u: f64) -> Spectrum {
+        if a.samples.is_none() && b.samples.is_none() {
+            return Spectrum::from_rgb(&lerp(&a.rgb, &b.rgb, u));
+        }
+        Spectrum::tabulate(|lambda| a.value_at(lambda) * (1.0 - u) + b.value_at(lambda) * u)
+    }
+}
+
+fn interpolate(samples: &[(f64, f64)], lambda: f64) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let i = samples.iter().position(|s| s.0 > lambda).unwrap_or(samples.len());
+    if i == 0 {
+        samples[0].1
+    } else if i == samples.len() {
+        samples[i - 1].1
+    } else {
+        let (l0, v0) = samples[i - 1];
+        let (l1, v1) = samples[i];
+        v0 + (v1 - v0) * (lambda - l0) / (l1 - l0)
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// a background seen through upsample, for spectral mode. Sampling and
+// power stay in RGB, where they were worked out.
+
+pub struct UpsampledBackground {
+    pub background: Arc<Background + Send + Sync>
+}
+
+impl Background for UpsampledBackground {
+    fn get_background(&self, unit_direction: &Vec3) -> Vec3 {
+        upsample(&self.background.get_background(unit_direction))
+    }
+
+    fn sample_direction(&self) -> (Vec3, f64) {
+        self.background.sample_direction()
+    }
+
+    fn direction_pdf(&self, unit_direction: &Vec3) -> f64 {
+        self.background.direction_pdf(unit_direction)
+    }
+
+    fn average_luminance(&self) -> f64 {
+        self.background.average_luminance()
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn spectra_come_back_as_the_colors_they_were() {
+    // the color matching functions have about the right areas
+    let xyz = integrate_xyz(|_| 1.0);
+    assert!((xyz.y() - 106.86).abs() < 2.0);
+    assert!((xyz.x() / xyz.y() - 1.0).abs() < 0.05 && (xyz.z() / xyz.y() - 1.0).abs() < 0.05);
+
+    // upsampled colors average back to roughly themselves, over many
+    // sets of wavelengths
+    for rgb in [Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.8, 0.3, 0.3),
+                Vec3::new(0.2, 0.6, 0.3), Vec3::new(0.1, 0.2, 0.7)].iter() {
+        let n = 20000;
+        let estimate = (0..n).fold(Vec3::zero(), |acc, _| {
+            let lambda = sample_wavelengths();
+            let values = Vec3::new(rgb_spectrum_at(rgb, lambda[0]),
+                                   rgb_spectrum_at(rgb, lambda[1]),
+                                   rgb_spectrum_at(rgb, lambda[2]));
+            acc + to_rgb(&values, &lambda)
+        }) / n as f64;
+        let exact = xyz_to_balanced_rgb(&integrate_xyz(|lambda| rgb_spectrum_at(rgb, lambda)));
+        assert!((estimate - exact).length() < 0.02);
+        assert!((exact - *rgb).length() < 0.1);
+    }
+
+    // conversions only happen in spectral mode
+    let red = Vec3::new(1.0, 0.0, 0.0);
+    assert!(upsample(&red) == red);
+    let lambda = Vec3::new(450.0, 550.0, 650.0);
+    begin_path(Some(lambda));
+    let values = upsample(&red);
+    assert!(values.x() < 0.1 && values.y() < 0.1 && values.z() > 0.8);
+    assert!(end_path(&values) == to_rgb(&values, &lambda));
+    assert!(wavelengths().is_none());
+}
+
+#[test]
+fn paths_keep_to_their_single_wavelength() {
+    // spectral paths follow the hero, and count it three times
+    let lambda = Vec3::new(450.0, 550.0, 650.0);
+    let values = Vec3::new(1.0, 2.0, 3.0);
+    begin_path(Some(lambda));
+    assert!(single_wavelength() == (450.0, Vec3::new(1.0, 1.0, 1.0)));
+    assert!(single_wavelength() == (450.0, Vec3::new(1.0, 1.0, 1.0)));
+    assert!(end_path(&values) == to_rgb(&Vec3::new(3.0, 0.0, 0.0), &lambda));
+
+    // RGB paths follow one channel, with a wavelength in its band
+    for _ in 0..100 {
+        begin_path(None);
+        let (lambda, weight) = single_wavelength();
+        assert!(weight == Vec3::new(1.0, 1.0, 1.0));
+        assert!(single_wavelength().0 == lambda);
+        let rgb = end_path(&values);
+        let c = (0..3).find(|&c| rgb[c] != 0.0).unwrap();
+        assert!(rgb[c] == 3.0 * values[c]);
+        assert!(lambda >= CHANNEL_BANDS[c].0 && lambda <= CHANNEL_BANDS[c].1);
+    }
+
+    // and events outside of paths weight themselves
+    let (_, weight) = single_wavelength();
+    assert!(weight.x() + weight.y() + weight.z() == 3.0);
+    assert!(end_path(&values) == values);
+}
+
+#[test]
+fn tabulated_spectra_interpolate() {
+    let s = Spectrum::from_samples(vec![(600.0, 1.0), (400.0, 3.0)]);
+    assert!(s.value_at(300.0) == 3.0);
+    assert!((s.value_at(500.0) - 2.0).abs() < 1e-12);
+    assert!(s.value_at(800.0) == 1.0);
+    assert!(!s.is_constant());
+    assert!(Spectrum::constant(1.5).is_constant());
+    assert!(Spectrum::constant(1.5).value_at(500.0) == 1.5);
+
+    // a blackbody at about the sun's temperature is nearly white, and
+    // a candle is orange
+    let sun = Spectrum::blackbody(5800.0).rgb;
+    let candle = Spectrum::blackbody(1900.0).rgb;
+    assert!((luminance(&sun) - 1.0).abs() < 1e-6);
+    assert!(sun.x() / sun.z() < 1.3);
+    assert!(candle.x() > 2.0 * candle.z());
+
+    let mix = Spectrum::lerp(&Spectrum::constant(1.0), &s, 0.5);
+    assert!((mix.value_at(500.0) - 1.5).abs() < 1e-12);
+    assert!((s.scale(2.0).value_at(500.0) - 4.0).abs() < 1e-12);
+}
@@ -1,4 +1,5 @@
 use aabb::AABB;
+use area_light::*;
 use hitable::*;
 use material::*;
 use ray::Ray;
@@ -45,17 +46,36 @@ impl Hitable for Sphere {
         None
     }
 
+    fn intervals<'a>(&'a self, r: &Ray) -> Vec<(HitRecord<'a>, HitRecord<'a>)> {
+        let oc = r.origin() - self.center;
+        let a = vector::dot(&r.direction(), &r.direction());
+        let b = vector::dot(&oc, &r.direction());
+        let c = vector::dot(&oc, &oc) - self.radius * self.radius;
+        let discriminant = b * b - a * c;
+        if discriminant <= 0.0 {
+            return Vec::new();
+        }
+        let at = |t: f64| {
+            let p = r.point_at_parameter(t);
+            HitRecord::hit(t, p, (p - self.center) / self.radius, &*self.material)
+        };
+        vec![(at((-b - discriminant.sqrt()) / a), at((-b + discriminant.sqrt()) / a))]
+    }
+
     fn bounding_box(&self) -> Option<AABB> {
         let r = self.radius.abs(); // works when radius is negative
         Some(AABB::new(self.center - Vec3::new(r, r, r),
                        self.center + Vec3::new(r, r, r)))
     }
 
-    fn importance_distribution(&self) -> Option<AABB> {
+    fn area_lights(&self) -> Vec<AreaLight> {
         if self.material.is_emitter() {
-            self.bounding_box()
+            vec![AreaLight::with_spectrum(LightShape::Sphere {
+                center: self.center,
+                radius: self.radius
+            }, self.material.emission_spectrum())]
         } else {
-            None
+            Vec::new()
         }
     }
 }
@@ -0,0 +1,165 @@
+use aabb::AABB;
+use area_light::{AreaLight, LightShape};
+use hitable::*;
+use material::*;
+use matrix::Matrix;
+use polynomial::solve_quartic;
+use ray::Ray;
+use vector::Vec3;
+use vector;
+
+//////////////////////////////////////////////////////////////////////////////
+// a torus: the circle of radius minor_radius swept around axis at
+// major_radius from center. Rays meet it where a quartic vanishes,
+// which is solved in a frame where the torus lies around the z axis and
+// the major radius is 1, starting from the point of the ray closest to
+// the center, to keep the coefficients small.
+
+pub struct Torus {
+    pub center: Vec3,
+    pub axis: Vec3,
+    pub major_radius: f64,
+    pub minor_radius: f64,
+    pub material: Box<Material>,
+    pub to_world: Matrix,
+    pub to_local: Matrix
+}
+
+impl Torus {
+    pub fn new(center: Vec3, axis: Vec3, major_radius: f64, minor_radius: f64,
+               material: Box<Material>) -> Torus {
+        let s = major_radius;
+        let to_world = Matrix::frame_along(&center, &axis) *
+            Matrix::scale(&Vec3::new(s, s, s));
+        Torus {
+            center: center,
+            axis: axis,
+            major_radius: major_radius,
+            minor_radius: minor_radius,
+            material: material,
+            to_world: to_world,
+            to_local: to_world.inverse().unwrap()
+        }
+    }
+
+    // the ray's parameters where it crosses the surface, in order
+    fn local_hits(&self, r: &Ray) -> Vec<f64> {
+        let length = r.direction().length();
+        unit_torus_crossings(&r.origin(), &(r.direction() / length), self.minor_radius / self.major_radius)
+            .into_iter()
+            .map(|s| s / length)
+            .collect()
+    }
+
+    fn local_normal(p: &Vec3) -> Vec3 {
+        // away from the nearest point on the circle through the tube
+        let ring = vector::unit_vector(&Vec3::new(p.x(), p.y(), 0.0));
+        *p - ring
+    }
+}
+
+// how far along the unit vector d from o the line crosses the torus of
+// major radius 1 and minor radius rho around the z axis, in order. If
+// the point closest to the center is outside the torus' bounding
+// sphere, so is the whole line.
+pub fn unit_torus_crossings(o: &Vec3, d: &Vec3, rho: f64) -> Vec<f64> {
+    let s0 = -o.dot(d);
+    let o = *o + *d * s0;
+    if o.length_squared() > (1.0 + rho) * (1.0 + rho) {
+        return Vec::new();
+    }
+
+    // (|p|^2 + 1 - rho^2)^2 = 4 (px^2 + py^2), at p = o + s d
+    let g = o.dot(d);
+    let e = o.length_squared() + 1.0 - rho * rho;
+    let dxy = d.x() * d.x() + d.y() * d.y();
+    let odxy = o.x() * d.x() + o.y() * d.y();
+    let oxy = o.x() * o.x() + o.y() * o.y();
+    solve_quartic(4.0 * g,
+                  4.0 * g * g + 2.0 * e - 4.0 * dxy,
+                  4.0 * g * e - 8.0 * odxy,
+                  e * e - 4.0 * oxy)
+        .into_iter()
+        .map(|s| s + s0)
+        .collect()
+}
+
+impl Hitable for Torus {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'a>> {
+        let local = self.to_local.transform_ray(r);
+        self.local_hits(&local).into_iter()
+            .find(|&t| t > t_min && t < t_max)
+            .map(|t| {
+                let normal = Torus::local_normal(&local.point_at_parameter(t));
+                HitRecord::hit(t, r.point_at_parameter(t),
+                               vector::unit_vector(&self.to_world.transform_vector(&normal)),
+                               &*self.material)
+            })
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        let rho = self.minor_radius / self.major_radius;
+        let outer = 1.0 + rho;
+        Some(self.to_world.transform_aabb(&AABB::new(Vec3::new(-outer, -outer, -rho),
+                                                     Vec3::new(outer, outer, rho))))
+    }
+
+    fn area_lights(&self) -> Vec<AreaLight> {
+        if !self.material.is_emitter() {
+            return Vec::new();
+        }
+        vec![AreaLight::with_spectrum(LightShape::Torus {
+            center: self.center,
+            axis: vector::unit_vector(&self.axis),
+            major_radius: self.major_radius,
+            minor_radius: self.minor_radius
+        }, self.material.emission_spectrum())]
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn tori_have_holes() {
+    use emitter::Emitter;
+    use lambertian::Lambertian;
+    use std::f64::consts::PI;
+
+    // lying flat around +y, 2 out and 0.5 thick
+    let torus = Torus::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 2.0, 0.5,
+                           Lambertian::new(&Vec3::new(0.5, 0.5, 0.5)));
+    let along_x = |y: f64| Ray::new(Vec3::new(-10.0, y, 0.0), Vec3::new(2.0, 0.0, 0.0));
+
+    // through both sides of the ring
+    let hr = torus.hit(&along_x(1.0), 0.0, 1e20).unwrap();
+    assert!((hr.p - Vec3::new(-2.5, 1.0, 0.0)).length() < 1e-9);
+    assert!((hr.t - 3.75).abs() < 1e-9);
+    assert!((hr.normal - Vec3::new(-1.0, 0.0, 0.0)).length() < 1e-9);
+    let hr = torus.hit(&along_x(1.0), 4.0, 1e20).unwrap();
+    assert!((hr.p - Vec3::new(-1.5, 1.0, 0.0)).length() < 1e-9);
+    assert!((hr.normal - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-9);
+    let crossings: Vec<f64> = torus.intervals(&along_x(1.0)).iter()
+        .flat_map(|&(ref a, ref b)| vec![a.p.x(), b.p.x()]).collect();
+    assert!(crossings.len() == 4 && (crossings[2] - 1.5).abs() < 1e-9);
+
+    // down the hole, and over the top
+    assert!(torus.hit(&Ray::new(Vec3::new(0.0, 10.0, 0.0), Vec3::new(0.0, -1.0, 0.0)), 0.0, 1e20).is_none());
+    assert!(torus.hit(&along_x(1.6), 0.0, 1e20).is_none());
+    // straight down onto the top of the tube
+    let hr = torus.hit(&Ray::new(Vec3::new(2.0, 10.0, 0.0), Vec3::new(0.0, -1.0, 0.0)), 0.0, 1e20).unwrap();
+    assert!((hr.p - Vec3::new(2.0, 1.5, 0.0)).length() < 1e-6);
+    assert!((hr.normal - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-6);
+
+    let b = torus.bounding_box().unwrap();
+    assert!((b.min() - Vec3::new(-2.5, 0.5, -2.5)).length() < 1e-9);
+    assert!((b.max() - Vec3::new(2.5, 1.5, 2.5)).length() < 1e-9);
+
+    // glowing ones are lights, with the same box
+    let glowing = Torus::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 3.0, 0.0), 2.0, 0.5,
+                             Emitter::new(&Vec3::new(1.0, 1.0, 1.0)));
+    let lights = glowing.area_lights();
+    assert!(lights.len() == 1 && (lights[0].area() - 4.0 * PI * PI).abs() < 1e-9);
+    assert!((lights[0].bounding_box().min() - b.min()).length() < 1e-9);
+    assert!((lights[0].bounding_box().max() - b.max()).length() < 1e-9);
+    assert!(torus.area_lights().is_empty());
+}
@@ -1,5 +1,6 @@
 use aabb::AABB;
 use aabb;
+use area_light::*;
 use hitable::*;
 use material::Material;
 use ray::*;
@@ -228,11 +229,15 @@ impl Hitable for TriangleMesh {
         self.hit_bvh(&self.bvh, r, t_min, t_max)
     }
 
-    fn importance_distribution(&self) -> Option<AABB> {
+    // one light per triangle, so that they can be sampled by area
+    fn area_lights(&self) -> Vec<AreaLight> {
         if self.material.is_emitter() {
-            self.bounding_box()
+            let emission = self.material.emission_spectrum();
+            self.triangles.iter().map(|t| {
+                AreaLight::with_spectrum(LightShape::Triangle { vertices: t.vertices }, emission.clone())
+            }).collect()
         } else {
-            None
+            Vec::new()
         }
     }
 }
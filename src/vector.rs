@@ -28,7 +28,6 @@ impl Vec3 {
     pub fn y(&self) -> f64 { self.e[1] }
     #[inline]
     pub fn z(&self) -> f64 { self.e[2] }
-    #[inline]
 
     #[inline]
     pub fn r(&self) -> f64 { self.e[0] }
@@ -236,6 +235,13 @@ pub fn luminance(v: &Vec3) -> f64 {
     return dot(v, &Vec3::new(0.2126, 0.7152, 0.0722));
 }
 
+// CIE XYZ to linear sRGB (D65 white)
+pub fn xyz_to_rgb(xyz: &Vec3) -> Vec3 {
+    Vec3::new( 3.2406 * xyz.x() - 1.5372 * xyz.y() - 0.4986 * xyz.z(),
+              -0.9689 * xyz.x() + 1.8758 * xyz.y() + 0.0415 * xyz.z(),
+               0.0557 * xyz.x() - 0.2040 * xyz.y() + 1.0570 * xyz.z())
+}
+
 //////////////////////////////////////////////////////////////////////////////
 
 #[test]
@@ -0,0 +1,139 @@
+use aabb::AABB;
+use area_light::AreaLight;
+use hitable::*;
+use medium::Medium;
+use ray::Ray;
+use vector::Vec3;
+
+// testing imports
+#[allow(unused_imports)]
+use lambertian::Lambertian;
+#[allow(unused_imports)]
+use medium::Homogeneous;
+#[allow(unused_imports)]
+use sphere::Sphere;
+
+//////////////////////////////////////////////////////////////////////////////
+// a medium filling the inside of a closed boundary. The boundary itself
+// is invisible (its material is never used): rays either collide with
+// the medium, and report a hit there with the medium's phase function
+// as the material, or pass through untouched. Put a dielectric around
+// it for cloudy glass.
+//
+// Scattering events have no surface, so their hit records carry a zero
+// normal.
+
+pub struct Volume {
+    pub boundary: Box<Hitable + Send + Sync>,
+    pub medium: Box<Medium>
+}
+
+impl Volume {
+    pub fn new(boundary: Box<Hitable + Send + Sync>, medium: Box<Medium>) -> Volume {
+        Volume {
+            boundary: boundary,
+            medium: medium
+        }
+    }
+}
+
+fn medium_hit<'a>(medium: &'a Medium, r: &Ray, t: f64) -> HitRecord<'a> {
+    HitRecord::hit(t, r.point_at_parameter(t), Vec3::zero(), medium.phase_function())
+}
+
+impl Hitable for Volume {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'a>> {
+        // the whole line's intervals, so that rays starting inside the
+        // medium, and boundaries that aren't convex, both work out.
+        for (enter, exit) in self.boundary.intervals(r) {
+            let t0 = enter.t.max(t_min);
+            let t1 = exit.t.min(t_max);
+            if t0 < t1 {
+                if let Some(t_hit) = self.medium.sample_distance(r, t0, t1) {
+                    return Some(medium_hit(&*self.medium, r, t_hit));
+                }
+            }
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        self.boundary.bounding_box()
+    }
+
+    fn area_lights(&self) -> Vec<AreaLight> {
+        Vec::new()
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// atmospheric fog: a medium filling the bounding box of the whole world,
+// up to the first surface along each ray. Rays that leave the box see
+// the background unattenuated by what lies beyond.
+
+pub struct GlobalVolume {
+    pub world: Box<Hitable + Send + Sync>,
+    pub medium: Box<Medium>
+}
+
+impl GlobalVolume {
+    pub fn new(world: Box<Hitable + Send + Sync>, medium: Box<Medium>) -> GlobalVolume {
+        GlobalVolume {
+            world: world,
+            medium: medium
+        }
+    }
+}
+
+impl Hitable for GlobalVolume {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'a>> {
+        let surface = self.world.hit(r, t_min, t_max);
+        let t_surface = surface.as_ref().map_or(t_max, |hr| hr.t);
+        let interval = match self.world.bounding_box() {
+            None => Some((t_min, t_surface)),
+            Some(bbox) => bbox.ray_interval(r, t_min, t_surface)
+        };
+        if let Some((t0, t1)) = interval {
+            if let Some(t) = self.medium.sample_distance(r, t0, t1) {
+                return Some(medium_hit(&*self.medium, r, t));
+            }
+        }
+        surface
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        self.world.bounding_box()
+    }
+
+    fn area_lights(&self) -> Vec<AreaLight> {
+        self.world.area_lights()
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn rays_from_inside_and_outside_see_the_medium() {
+    let volume = Volume::new(
+        Box::new(Sphere::new(Vec3::zero(), 1.0, Lambertian::new(&Vec3::zero()))),
+        Homogeneous::new(0.0, 1.0, 0.0, &Vec3::new(1.0, 1.0, 1.0)));
+    let n = 100000;
+
+    // from outside, the ray crosses the whole diameter
+    let outside = Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+    let through = (0..n).filter(|_| volume.hit(&outside, 0.00001, 1e20).is_none()).count();
+    assert!((through as f64 / n as f64 - (-2.0f64).exp()).abs() < 0.01);
+
+    // from the center, only the radius
+    let inside = Ray::new(Vec3::zero(), Vec3::new(0.0, 1.0, 0.0));
+    let through = (0..n).filter(|_| volume.hit(&inside, 0.00001, 1e20).is_none()).count();
+    assert!((through as f64 / n as f64 - (-1.0f64).exp()).abs() < 0.01);
+
+    // and collisions only happen inside
+    for _ in 0..1000 {
+        if let Some(hr) = volume.hit(&outside, 0.00001, 1e20) {
+            assert!(hr.p.length() <= 1.0 + 1e-8);
+            assert!(hr.normal == Vec3::zero());
+        }
+    }
+}
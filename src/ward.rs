@@ -2,6 +2,7 @@ use material::*;
 use vector::Vec3;
 use vector;
 use ray::Ray;
+use spectrum;
 use hitable::*;
 use random::*;
 
@@ -33,10 +34,12 @@ impl Ward {
 impl Material for Ward {
     fn is_emitter(&self) -> bool { false }
 
+    fn emission(&self) -> Vec3 { Vec3::zero() }
+
     fn wants_importance_sampling(&self) -> bool { true }
     
     fn albedo(&self, _ray_in: &Ray, ray_out: &Ray, surface_normal: &Vec3) -> Vec3 {
-        self.albedo * surface_normal.dot(&ray_out.direction())
+        spectrum::upsample(&self.albedo) * surface_normal.dot(&ray_out.direction())
     }
 
     fn bsdf(&self, ray_in: &Ray, ray_out: &Ray, n: &Vec3) -> f64 {
@@ -91,7 +94,7 @@ impl Material for Ward {
         // fixme, add the weighting term here from Equation (10) in Walter 2005
         let rho_s = self.rho_s;
         Scatter::Bounce(Vec3::new(rho_s, rho_s, rho_s),
-                        Ray::new(hr.p, o))
+                        Ray::with_time(hr.p, o, ray_in.time()))
     }
 
     fn debug(&self, f: &mut fmt::Formatter) -> fmt::Result {